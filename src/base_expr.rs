@@ -4,46 +4,168 @@ use pretty::{BoxAllocator, DocAllocator, DocBuilder};
 use std::collections::HashMap;
 use termcolor::{Color, ColorSpec, WriteColor};
 
-use std::{io::Result, rc::Rc};
+use std::{io, rc::Rc};
 
-use crate::expr::Expr;
+use crate::diagnostics::Span;
+use crate::error::CompileError;
+use crate::expr::{Expr, LamInfo};
+use crate::interp::Procedure;
 use crate::literals::Literal;
+use crate::syntax::{source_name, Datum};
+use crate::syntax_rules::SyntaxRules;
 use crate::utils::clone_rc;
 
 // TODO: Type Families magick?
 
 #[derive(Debug, Clone)]
 pub enum BExpr {
-    Var(String),
-    Lit(Literal),
-    BuiltinIdent(String),
-    If(Rc<BExpr>, Rc<BExpr>, Rc<BExpr>),
-    Set(String, Rc<BExpr>),
-    Let(Vec<(String, BExpr)>, BExprBody),
-    Lam(Vec<String>, BExprBody),
-    App(Rc<BExpr>, Vec<BExpr>),
+    Var(String, Span),
+    Lit(Literal, Span),
+    BuiltinIdent(String, Span),
+    If(Rc<BExpr>, Rc<BExpr>, Rc<BExpr>, Span),
+    Set(String, Rc<BExpr>, Span),
+    Let(Vec<(String, BExpr)>, BExprBody, Span),
+    LetStar(Vec<(String, BExpr)>, BExprBody, Span),
+    LetRec(Vec<(String, BExpr)>, BExprBody, Span),
+    NamedLet(String, Vec<(String, BExpr)>, BExprBody, Span),
+    /// The parameters, and the parameter taking a list of any arguments after
+    /// them if the lambda is variadic
+    Lam(Vec<String>, Option<String>, BExprBody, Span),
+    App(Rc<BExpr>, Vec<BExpr>, Span),
+    Cond(Vec<CondClause>, Span),
+    Case(
+        Rc<BExpr>,
+        Vec<(Vec<BExpr>, Vec<BExpr>)>,
+        Option<Vec<BExpr>>,
+        Span,
+    ),
+    And(Vec<BExpr>, Span),
+    Or(Vec<BExpr>, Span),
+    When(Rc<BExpr>, Vec<BExpr>, Span),
+    Unless(Rc<BExpr>, Vec<BExpr>, Span),
+    Begin(Vec<BExpr>, Span),
+    /// `(guard (var clause...) body...)`, the clauses are those of a `cond`
+    /// with `var` bound to the raised object
+    Guard(String, Vec<CondClause>, BExprBody, Span),
+    /// `(do ((var init step)...) (test result...) command...)`
+    Do(
+        Vec<(String, BExpr, Option<BExpr>)>,
+        Rc<BExpr>,
+        Vec<BExpr>,
+        Vec<BExpr>,
+        Span,
+    ),
+    /// A named let that only calls itself in tail position
+    Loop(String, Vec<(String, BExpr)>, BExprBody, Span),
+    /// A tail call of the loop `name`, rebinding each of the loop's variables
+    Recur(String, Vec<String>, Vec<BExpr>, Span),
+    /// A use of what might be a macro, the expansion pass decides. When the
+    /// use parsed as an application that is kept for if it isn't a macro.
+    MacroUse(Rc<Datum>, Option<Rc<BExpr>>, Span),
+    LetSyntax(Vec<(String, Rc<SyntaxRules>)>, BExprBody, Span),
+    LetRecSyntax(Vec<(String, Rc<SyntaxRules>)>, BExprBody, Span),
+}
+
+#[derive(Debug, Clone)]
+pub enum CondClause {
+    /// `(test body...)`, with no body the clause gives the value of the test
+    Test(BExpr, Vec<BExpr>),
+    /// `(test => receiver)`
+    Arrow(BExpr, BExpr),
+    Else(Vec<BExpr>),
 }
 
 #[derive(Debug, Clone)]
 pub enum BExprBodyExpr {
-    Def(String, BExpr),
+    Def(String, BExpr, Span),
+    /// `define-syntax` or `define-macro`
+    DefSyntax(String, Transformer, Span),
     Expr(BExpr),
 }
 
+/// What turns a use of a macro into the code it stands for
+#[derive(Debug, Clone)]
+pub enum Transformer {
+    Rules(Rc<SyntaxRules>),
+    Procedure(Rc<Procedure>),
+}
+
 #[derive(Debug, Clone)]
 pub struct BExprBody(pub Vec<BExprBodyExpr>, pub Rc<BExpr>);
 
 impl BExpr {
+    pub fn span(&self) -> Span {
+        match self {
+            BExpr::Var(_, s)
+            | BExpr::Lit(_, s)
+            | BExpr::BuiltinIdent(_, s)
+            | BExpr::If(_, _, _, s)
+            | BExpr::Set(_, _, s)
+            | BExpr::Let(_, _, s)
+            | BExpr::LetStar(_, _, s)
+            | BExpr::LetRec(_, _, s)
+            | BExpr::NamedLet(_, _, _, s)
+            | BExpr::Lam(_, _, _, s)
+            | BExpr::App(_, _, s)
+            | BExpr::Cond(_, s)
+            | BExpr::Case(_, _, _, s)
+            | BExpr::And(_, s)
+            | BExpr::Or(_, s)
+            | BExpr::When(_, _, s)
+            | BExpr::Unless(_, _, s)
+            | BExpr::Begin(_, s)
+            | BExpr::Guard(_, _, _, s)
+            | BExpr::Do(_, _, _, _, s)
+            | BExpr::Loop(_, _, _, s)
+            | BExpr::Recur(_, _, _, s)
+            | BExpr::MacroUse(_, _, s)
+            | BExpr::LetSyntax(_, _, s)
+            | BExpr::LetRecSyntax(_, _, s) => *s,
+        }
+    }
+
+    /// The same expression, with its own span (but not those of its
+    /// children) changed by `f`
+    pub fn map_span(self, f: impl FnOnce(Span) -> Span) -> BExpr {
+        match self {
+            BExpr::Var(n, s) => BExpr::Var(n, f(s)),
+            BExpr::Lit(l, s) => BExpr::Lit(l, f(s)),
+            BExpr::BuiltinIdent(n, s) => BExpr::BuiltinIdent(n, f(s)),
+            BExpr::If(c, ift, iff, s) => BExpr::If(c, ift, iff, f(s)),
+            BExpr::Set(n, e, s) => BExpr::Set(n, e, f(s)),
+            BExpr::Let(b, body, s) => BExpr::Let(b, body, f(s)),
+            BExpr::LetStar(b, body, s) => BExpr::LetStar(b, body, f(s)),
+            BExpr::LetRec(b, body, s) => BExpr::LetRec(b, body, f(s)),
+            BExpr::NamedLet(n, b, body, s) => BExpr::NamedLet(n, b, body, f(s)),
+            BExpr::Lam(p, r, body, s) => BExpr::Lam(p, r, body, f(s)),
+            BExpr::App(g, args, s) => BExpr::App(g, args, f(s)),
+            BExpr::Cond(clauses, s) => BExpr::Cond(clauses, f(s)),
+            BExpr::Case(k, clauses, e, s) => BExpr::Case(k, clauses, e, f(s)),
+            BExpr::And(es, s) => BExpr::And(es, f(s)),
+            BExpr::Or(es, s) => BExpr::Or(es, f(s)),
+            BExpr::When(c, es, s) => BExpr::When(c, es, f(s)),
+            BExpr::Unless(c, es, s) => BExpr::Unless(c, es, f(s)),
+            BExpr::Begin(es, s) => BExpr::Begin(es, f(s)),
+            BExpr::Guard(v, clauses, body, s) => BExpr::Guard(v, clauses, body, f(s)),
+            BExpr::Do(b, t, r, c, s) => BExpr::Do(b, t, r, c, f(s)),
+            BExpr::Loop(n, b, body, s) => BExpr::Loop(n, b, body, f(s)),
+            BExpr::Recur(n, v, args, s) => BExpr::Recur(n, v, args, f(s)),
+            BExpr::MacroUse(d, app, s) => BExpr::MacroUse(d, app, f(s)),
+            BExpr::LetSyntax(b, body, s) => BExpr::LetSyntax(b, body, f(s)),
+            BExpr::LetRecSyntax(b, body, s) => BExpr::LetRecSyntax(b, body, f(s)),
+        }
+    }
+
     pub fn pretty<'a, D>(&self, allocator: &'a D) -> DocBuilder<'a, D, ColorSpec>
     where
         D: DocAllocator<'a, ColorSpec>,
         D::Doc: Clone,
     {
         match self {
-            BExpr::Var(s) => allocator.as_string(s),
-            BExpr::Lit(l) => l.pretty(allocator),
-            BExpr::BuiltinIdent(s) => allocator.as_string(s),
-            BExpr::Set(n, e) => {
+            BExpr::Var(s, _) => allocator.as_string(s),
+            BExpr::Lit(l, _) => l.pretty(allocator),
+            BExpr::BuiltinIdent(s, _) => allocator.as_string(s),
+            BExpr::Set(n, e, _) => {
                 let e_pret = e.pretty(allocator);
 
                 allocator
@@ -60,44 +182,34 @@ impl BExpr {
                     .group()
                     .parens()
             }
-            BExpr::Let(bindings, body) => {
-                let bindings_pret = allocator
-                    .intersperse(
-                        bindings.iter().map(|(n, e)| {
-                            allocator
-                                .text(n.to_owned())
-                                .annotate(ColorSpec::new().set_fg(Some(Color::Magenta)).clone())
-                                .append(allocator.space())
-                                .append(e.pretty(allocator))
-                                .group()
-                                .parens()
-                        }),
-                        allocator.space(),
-                    )
-                    .parens();
-
-                allocator
-                    .text("let")
-                    .annotate(ColorSpec::new().set_fg(Some(Color::Magenta)).clone())
-                    .append(allocator.space())
-                    .append(bindings_pret)
-                    .append(allocator.line())
-                    .append(body.pretty(allocator))
-                    .nest(1)
-                    .group()
-                    .parens()
+            BExpr::Let(bindings, body, _) => pretty_let(allocator, "let", None, bindings, body),
+            BExpr::LetStar(bindings, body, _) => {
+                pretty_let(allocator, "let*", None, bindings, body)
             }
-            BExpr::Lam(pat, body) => {
-                let pat_pret = allocator
-                    .intersperse(
-                        pat.iter().map(|p| {
-                            allocator
-                                .text(p.to_owned())
-                                .annotate(ColorSpec::new().set_fg(Some(Color::Green)).clone())
-                        }),
-                        allocator.line(),
-                    )
-                    .parens();
+            BExpr::LetRec(bindings, body, _) => {
+                pretty_let(allocator, "letrec", None, bindings, body)
+            }
+            BExpr::NamedLet(name, bindings, body, _) => {
+                pretty_let(allocator, "let", Some(name), bindings, body)
+            }
+            BExpr::Lam(pat, rest, body, _) => {
+                let param_pret = |p: &str| {
+                    allocator
+                        .text(p.to_owned())
+                        .annotate(ColorSpec::new().set_fg(Some(Color::Green)).clone())
+                };
+                let pat_pret = match (pat.as_slice(), rest) {
+                    ([], Some(rest)) => param_pret(rest),
+                    _ => allocator
+                        .intersperse(
+                            pat.iter()
+                                .map(String::as_str)
+                                .chain(rest.iter().flat_map(|r| [".", r.as_str()]))
+                                .map(param_pret),
+                            allocator.line(),
+                        )
+                        .parens(),
+                };
 
                 allocator
                     .text("lambda")
@@ -110,7 +222,7 @@ impl BExpr {
                     .group()
                     .parens()
             }
-            BExpr::If(c, ift, iff) => {
+            BExpr::If(c, ift, iff, _) => {
                 let c_pret = c.pretty(allocator);
                 let ift_pret = ift.pretty(allocator);
                 let iff_pret = iff.pretty(allocator);
@@ -126,7 +238,7 @@ impl BExpr {
                     .group()
                     .parens()
             }
-            BExpr::App(f, params) => {
+            BExpr::App(f, params, _) => {
                 let f_pret = f.pretty(allocator);
                 let v_pret = allocator
                     .intersperse(params.iter().map(|v| v.pretty(allocator)), allocator.line());
@@ -138,10 +250,141 @@ impl BExpr {
                     .group()
                     .parens()
             }
+            BExpr::Cond(clauses, _) => pretty_form(
+                allocator,
+                "cond",
+                clauses.iter().map(|c| c.pretty(allocator)),
+            ),
+            BExpr::Case(key, clauses, else_body, _) => {
+                let clauses = clauses.iter().map(|(datums, body)| {
+                    let datums = allocator
+                        .intersperse(
+                            datums.iter().map(|d| d.pretty(allocator)),
+                            allocator.space(),
+                        )
+                        .parens();
+
+                    allocator
+                        .intersperse(
+                            std::iter::once(datums).chain(body.iter().map(|e| e.pretty(allocator))),
+                            allocator.space(),
+                        )
+                        .group()
+                        .parens()
+                });
+                let else_clause = else_body.iter().map(|body| {
+                    allocator
+                        .intersperse(
+                            std::iter::once(allocator.text("else"))
+                                .chain(body.iter().map(|e| e.pretty(allocator))),
+                            allocator.space(),
+                        )
+                        .group()
+                        .parens()
+                });
+
+                pretty_form(
+                    allocator,
+                    "case",
+                    std::iter::once(key.pretty(allocator))
+                        .chain(clauses)
+                        .chain(else_clause),
+                )
+            }
+            BExpr::And(es, _) => {
+                pretty_form(allocator, "and", es.iter().map(|e| e.pretty(allocator)))
+            }
+            BExpr::Or(es, _) => {
+                pretty_form(allocator, "or", es.iter().map(|e| e.pretty(allocator)))
+            }
+            BExpr::When(c, es, _) => pretty_form(
+                allocator,
+                "when",
+                std::iter::once(c.pretty(allocator)).chain(es.iter().map(|e| e.pretty(allocator))),
+            ),
+            BExpr::Unless(c, es, _) => pretty_form(
+                allocator,
+                "unless",
+                std::iter::once(c.pretty(allocator)).chain(es.iter().map(|e| e.pretty(allocator))),
+            ),
+            BExpr::Begin(es, _) => {
+                pretty_form(allocator, "begin", es.iter().map(|e| e.pretty(allocator)))
+            }
+            BExpr::Guard(var, clauses, body, _) => {
+                let clauses_pret = allocator
+                    .intersperse(
+                        std::iter::once(
+                            allocator
+                                .text(var.to_owned())
+                                .annotate(ColorSpec::new().set_fg(Some(Color::Green)).clone()),
+                        )
+                        .chain(clauses.iter().map(|c| c.pretty(allocator))),
+                        allocator.line(),
+                    )
+                    .nest(1)
+                    .group()
+                    .parens();
+
+                pretty_form(
+                    allocator,
+                    "guard",
+                    vec![clauses_pret, body.pretty(allocator)].into_iter(),
+                )
+            }
+            BExpr::Do(bindings, test, result, commands, _) => {
+                let bindings_pret = allocator
+                    .intersperse(
+                        bindings.iter().map(|(n, init, step)| {
+                            allocator
+                                .intersperse(
+                                    std::iter::once(allocator.text(n.to_owned()))
+                                        .chain(std::iter::once(init.pretty(allocator)))
+                                        .chain(step.iter().map(|e| e.pretty(allocator))),
+                                    allocator.space(),
+                                )
+                                .group()
+                                .parens()
+                        }),
+                        allocator.space(),
+                    )
+                    .parens();
+                let result_pret = allocator
+                    .intersperse(
+                        std::iter::once(test.pretty(allocator))
+                            .chain(result.iter().map(|e| e.pretty(allocator))),
+                        allocator.space(),
+                    )
+                    .group()
+                    .parens();
+
+                pretty_form(
+                    allocator,
+                    "do",
+                    vec![bindings_pret, result_pret]
+                        .into_iter()
+                        .chain(commands.iter().map(|e| e.pretty(allocator))),
+                )
+            }
+            BExpr::Loop(name, bindings, body, _) => {
+                pretty_let(allocator, "loop", Some(name), bindings, body)
+            }
+            BExpr::Recur(name, _, args, _) => pretty_form(
+                allocator,
+                "recur",
+                std::iter::once(allocator.text(name.to_owned()))
+                    .chain(args.iter().map(|e| e.pretty(allocator))),
+            ),
+            BExpr::MacroUse(datum, _, _) => allocator.as_string(datum),
+            BExpr::LetSyntax(bindings, body, _) => {
+                pretty_let_syntax(allocator, "let-syntax", bindings, body)
+            }
+            BExpr::LetRecSyntax(bindings, body, _) => {
+                pretty_let_syntax(allocator, "letrec-syntax", bindings, body)
+            }
         }
     }
 
-    pub fn pretty_print(&self, out: impl WriteColor) -> Result<()> {
+    pub fn pretty_print(&self, out: impl WriteColor) -> io::Result<()> {
         let allocator = BoxAllocator;
 
         self.pretty(&allocator).1.render_colored(70, out)?;
@@ -151,26 +394,82 @@ impl BExpr {
 
     pub fn rewrite<F: Fn(BExpr) -> BExpr>(self, f: &F) -> BExpr {
         let processed_children = match self {
-            BExpr::Var(_) | BExpr::Lit(_) | BExpr::BuiltinIdent(_) => self,
-            BExpr::Set(n, e) => BExpr::Set(n, Rc::new(clone_rc(e).rewrite(f))),
-            BExpr::If(c, ift, iff) => BExpr::If(
+            BExpr::Var(..) | BExpr::Lit(..) | BExpr::BuiltinIdent(..) => self,
+            BExpr::Set(n, e, s) => BExpr::Set(n, Rc::new(clone_rc(e).rewrite(f)), s),
+            BExpr::If(c, ift, iff, s) => BExpr::If(
                 Rc::new(clone_rc(c).rewrite(f)),
                 Rc::new(clone_rc(ift).rewrite(f)),
                 Rc::new(clone_rc(iff).rewrite(f)),
+                s,
             ),
-            BExpr::Let(bindings, body) => {
-                let new_bindings: Vec<_> = bindings
+            BExpr::Let(bindings, body, s) => {
+                BExpr::Let(rewrite_bindings(bindings, f), body.rewrite(f), s)
+            }
+            BExpr::LetStar(bindings, body, s) => {
+                BExpr::LetStar(rewrite_bindings(bindings, f), body.rewrite(f), s)
+            }
+            BExpr::LetRec(bindings, body, s) => {
+                BExpr::LetRec(rewrite_bindings(bindings, f), body.rewrite(f), s)
+            }
+            BExpr::NamedLet(n, bindings, body, s) => {
+                BExpr::NamedLet(n, rewrite_bindings(bindings, f), body.rewrite(f), s)
+            }
+            BExpr::Lam(n, r, body, s) => BExpr::Lam(n, r, body.rewrite(f), s),
+            BExpr::App(r, es, s) => {
+                let new_es = es.into_iter().map(|e| e.rewrite(f)).collect();
+
+                BExpr::App(Rc::new(clone_rc(r).rewrite(f)), new_es, s)
+            }
+            BExpr::Cond(clauses, s) => BExpr::Cond(rewrite_clauses(clauses, f), s),
+            BExpr::Case(key, clauses, else_body, s) => {
+                let new_clauses = clauses
                     .into_iter()
-                    .map(|(n, e)| (n, e.rewrite(f)))
+                    .map(|(datums, body)| (rewrite_all(datums, f), rewrite_all(body, f)))
                     .collect();
 
-                BExpr::Let(new_bindings, body.rewrite(f))
+                BExpr::Case(
+                    Rc::new(clone_rc(key).rewrite(f)),
+                    new_clauses,
+                    else_body.map(|body| rewrite_all(body, f)),
+                    s,
+                )
             }
-            BExpr::Lam(n, body) => BExpr::Lam(n, body.rewrite(f)),
-            BExpr::App(r, es) => {
-                let new_es = es.into_iter().map(|e| e.rewrite(f)).collect();
+            BExpr::And(es, s) => BExpr::And(rewrite_all(es, f), s),
+            BExpr::Or(es, s) => BExpr::Or(rewrite_all(es, f), s),
+            BExpr::When(c, es, s) => {
+                BExpr::When(Rc::new(clone_rc(c).rewrite(f)), rewrite_all(es, f), s)
+            }
+            BExpr::Unless(c, es, s) => {
+                BExpr::Unless(Rc::new(clone_rc(c).rewrite(f)), rewrite_all(es, f), s)
+            }
+            BExpr::Begin(es, s) => BExpr::Begin(rewrite_all(es, f), s),
+            BExpr::Guard(var, clauses, body, s) => {
+                BExpr::Guard(var, rewrite_clauses(clauses, f), body.rewrite(f), s)
+            }
+            BExpr::Do(bindings, test, result, commands, s) => {
+                let new_bindings = bindings
+                    .into_iter()
+                    .map(|(n, init, step)| (n, init.rewrite(f), step.map(|e| e.rewrite(f))))
+                    .collect();
 
-                BExpr::App(Rc::new(clone_rc(r).rewrite(f)), new_es)
+                BExpr::Do(
+                    new_bindings,
+                    Rc::new(clone_rc(test).rewrite(f)),
+                    rewrite_all(result, f),
+                    rewrite_all(commands, f),
+                    s,
+                )
+            }
+            BExpr::Loop(n, bindings, body, s) => {
+                BExpr::Loop(n, rewrite_bindings(bindings, f), body.rewrite(f), s)
+            }
+            BExpr::Recur(n, vars, args, s) => BExpr::Recur(n, vars, rewrite_all(args, f), s),
+            BExpr::MacroUse(d, app, s) => {
+                BExpr::MacroUse(d, app.map(|app| Rc::new(clone_rc(app).rewrite(f))), s)
+            }
+            BExpr::LetSyntax(bindings, body, s) => BExpr::LetSyntax(bindings, body.rewrite(f), s),
+            BExpr::LetRecSyntax(bindings, body, s) => {
+                BExpr::LetRecSyntax(bindings, body.rewrite(f), s)
             }
         };
 
@@ -180,12 +479,94 @@ impl BExpr {
     pub fn remove_let(self) -> BExpr {
         fn t(e: BExpr) -> BExpr {
             match e {
-                BExpr::Let(b, e) => {
+                BExpr::Let(b, e, s) => {
                     let (names, params) = b.into_iter().unzip();
 
-                    let lam = Rc::new(BExpr::Lam(names, e));
-                    BExpr::App(lam, params)
+                    let lam = Rc::new(BExpr::Lam(names, None, e, s));
+                    BExpr::App(lam, params, s)
+                }
+                _ => e,
+            }
+        }
+
+        self.rewrite(&t)
+    }
+
+    /// Rewrite `let*` and `letrec` in terms of plain `let`, `do` in terms of
+    /// named `let`, and `guard` in terms of `call/cc` and
+    /// `with-exception-handler`.
+    ///
+    /// This happens before defines are lifted, as the bodies of these forms can
+    /// contain defines too.
+    pub fn remove_let_variants(self) -> BExpr {
+        fn t(e: BExpr) -> BExpr {
+            match e {
+                BExpr::LetStar(bindings, body, s) => desugar_let_star(bindings, body, s),
+                BExpr::LetRec(bindings, body, s) => desugar_letrec(bindings, body, s),
+                BExpr::Do(bindings, test, result, commands, s) => {
+                    desugar_do(bindings, clone_rc(test), result, commands, s)
+                }
+                BExpr::Guard(var, clauses, body, s) => desugar_guard(var, clauses, body, s),
+                _ => e,
+            }
+        }
+
+        self.rewrite(&t)
+    }
+
+    /// Turn each named `let` that only ever calls itself in tail position into
+    /// a `Loop`, which reuses one environment for every iteration instead of
+    /// allocating a closure per call. Any other named `let` is rewritten in
+    /// terms of `letrec`.
+    ///
+    /// The variables of a loop are updated in place, so a loop whose variables
+    /// are captured by a lambda is left alone, as each iteration has to see a
+    /// fresh binding. So is a loop that makes a call partway through an
+    /// iteration that could capture its continuation, as coming back into that
+    /// continuation later would see the variables of a later iteration.
+    pub fn recognise_loops(self) -> BExpr {
+        fn t(e: BExpr) -> BExpr {
+            match e {
+                BExpr::NamedLet(name, bindings, body, s) => {
+                    let vars: Vec<String> = bindings.iter().map(|(n, _)| n.clone()).collect();
+
+                    if body_only_tail_calls(&body, &name, &vars, true)
+                        && !body_captures(&body, &vars, false)
+                        && !body_calls_out(&body, true)
+                    {
+                        let BExprBody(body_exprs, last) = body;
+                        let last = into_recur(clone_rc(last), &name, &vars);
+
+                        BExpr::Loop(name, bindings, BExprBody(body_exprs, Rc::new(last)), s)
+                    } else {
+                        desugar_named_let(name, bindings, body, s)
+                    }
+                }
+                _ => e,
+            }
+        }
+
+        self.rewrite(&t)
+    }
+
+    /// Rewrite `cond`, `case`, `and`, `or`, `when`, `unless` and `begin` in
+    /// terms of `if`, `let` and application.
+    pub fn remove_derived_forms(self) -> BExpr {
+        fn t(e: BExpr) -> BExpr {
+            match e {
+                BExpr::Cond(clauses, s) => desugar_cond(clauses, s),
+                BExpr::Case(key, clauses, else_body, s) => {
+                    desugar_case(clone_rc(key), clauses, else_body, s)
+                }
+                BExpr::And(es, s) => desugar_and(es, s),
+                BExpr::Or(es, s) => desugar_or(es, s),
+                BExpr::When(c, es, s) => {
+                    BExpr::If(c, Rc::new(sequence(es, s)), Rc::new(void(s)), s)
+                }
+                BExpr::Unless(c, es, s) => {
+                    BExpr::If(c, Rc::new(void(s)), Rc::new(sequence(es, s)), s)
                 }
+                BExpr::Begin(es, s) => sequence(es, s),
                 _ => e,
             }
         }
@@ -196,8 +577,9 @@ impl BExpr {
     pub fn lift_defines(self) -> BExpr {
         fn t(e: BExpr) -> BExpr {
             match e {
-                BExpr::Let(e, body) => BExpr::Let(e, body.pull_defines()),
-                BExpr::Lam(e, body) => BExpr::Lam(e, body.pull_defines()),
+                BExpr::Let(e, body, s) => BExpr::Let(e, body.pull_defines(s), s),
+                BExpr::Lam(e, r, body, s) => BExpr::Lam(e, r, body.pull_defines(s), s),
+                BExpr::NamedLet(n, e, body, s) => BExpr::NamedLet(n, e, body.pull_defines(s), s),
                 _ => e,
             }
         }
@@ -205,102 +587,796 @@ impl BExpr {
         self.rewrite(&t)
     }
 
-    pub fn into_expr(self) -> Expr {
+    pub fn into_expr(self) -> Result<Expr, CompileError> {
         let env = HashMap::new();
-        self.lift_defines().remove_let().into_expr_inner(&env)
+        self.remove_let_variants()
+            .lift_defines()
+            .remove_derived_forms()
+            .recognise_loops()
+            .remove_let()
+            .into_expr_inner(&env)
     }
 
-    fn into_expr_inner(self, env: &HashMap<String, FreeVar<String>>) -> Expr {
-        match self {
-            BExpr::Var(n) => Expr::Var(Var::Free(
-                env.get(&n)
-                    .unwrap_or_else(|| panic!("unbound arg: {}", n))
-                    .clone(),
-            )),
-            BExpr::Lit(l) => Expr::Lit(Ignore(l)),
-            BExpr::BuiltinIdent(l) => Expr::BuiltinIdent(Ignore(l)),
-            BExpr::Set(n, e) => Expr::Set(
+    fn into_expr_inner(self, env: &HashMap<String, FreeVar<String>>) -> Result<Expr, CompileError> {
+        let expr = match self {
+            BExpr::Var(n, s) => Expr::Var(Var::Free(env.get(&n).cloned().ok_or_else(|| {
+                CompileError::UnboundVariable {
+                    name: n.clone(),
+                    span: s,
+                    suggestion: None,
+                }
+            })?)),
+            BExpr::Lit(l, _) => Expr::Lit(Ignore(l)),
+            BExpr::BuiltinIdent(l, _) => Expr::BuiltinIdent(Ignore(l)),
+            BExpr::Set(n, e, s) => Expr::Set(
                 Var::Free(
                     env.get(&n)
-                        .unwrap_or_else(|| panic!("unbound arg: {}", n))
-                        .clone(),
+                        .cloned()
+                        .ok_or_else(|| CompileError::UndefinedSet {
+                            name: n.clone(),
+                            span: s,
+                            suggestion: None,
+                        })?,
                 ),
-                Rc::new(clone_rc(e).into_expr_inner(env)),
+                Rc::new(name_lambda(clone_rc(e).into_expr_inner(env)?, &n)),
             ),
-            BExpr::Lam(params, body) => {
+            BExpr::Lam(params, rest, body, s) => {
                 let mut env = env.clone();
-                env.extend(params.iter().map(|n| (n.clone(), FreeVar::fresh_named(n))));
-                let body = body.as_expressions();
-
-                let body = match body.as_slice() {
-                    [] => Expr::Lit(Ignore(Literal::Void)),
-                    [first, rest @ ..] => {
-                        rest.iter()
-                            .fold(first.clone().into_expr_inner(&env), |acc, e| {
-                                Expr::App(
-                                    Rc::new(Expr::Lam(Scope::new(
-                                        Binder(FreeVar::fresh_named("_unused")),
-                                        Rc::new(e.clone().into_expr_inner(&env)),
-                                    ))),
-                                    Rc::new(acc),
-                                )
-                            })
-                    }
-                };
+                env.extend(
+                    params
+                        .iter()
+                        .chain(&rest)
+                        .map(|n| (n.clone(), FreeVar::fresh_named(n))),
+                );
+                let body = body.into_expr(&env)?;
+                let binder = |p: &String| Binder(env.get(p).unwrap().clone());
 
-                match params.as_slice() {
-                    [] => {
-                        // for zero param functions, we turn it into a 1-param function
-                        // and then on calls with zero parameters, we add in a parameter of null
-                        Expr::Lam(Scope::new(
-                            Binder(FreeVar::fresh_named("_unused")),
-                            Rc::new(body),
-                        ))
-                    }
-                    [rest @ .., last] => {
-                        let last = Expr::Lam(Scope::new(
-                            Binder(env.get(last).unwrap().clone()),
-                            Rc::new(body),
-                        ));
-                        rest.iter().rev().fold(last, |acc, p| {
-                            Expr::Lam(Scope::new(
-                                Binder(env.get(p).unwrap().clone()),
-                                Rc::new(acc),
-                            ))
-                        })
-                    }
-                }
+                Expr::Lam(
+                    Scope::new(
+                        (
+                            params.iter().map(binder).collect(),
+                            rest.as_ref().map(binder),
+                        ),
+                        Rc::new(body),
+                    ),
+                    Ignore(LamInfo {
+                        name: None,
+                        span: s,
+                    }),
+                )
             }
-            BExpr::If(c, ift, iff) => {
-                let c = clone_rc(c).into_expr_inner(env);
-                let ift = clone_rc(ift).into_expr_inner(env);
-                let iff = clone_rc(iff).into_expr_inner(env);
+            BExpr::If(c, ift, iff, _) => {
+                let c = clone_rc(c).into_expr_inner(env)?;
+                let ift = clone_rc(ift).into_expr_inner(env)?;
+                let iff = clone_rc(iff).into_expr_inner(env)?;
 
                 Expr::If(Rc::new(c), Rc::new(ift), Rc::new(iff))
             }
-            BExpr::App(expr, params) => {
-                let expr = clone_rc(expr).into_expr_inner(env);
+            BExpr::App(expr, params, s) => {
+                // the arguments of a let are named after the variables they bind
+                let names = match &*expr {
+                    BExpr::Lam(names, _, _, _) => names.clone(),
+                    _ => Vec::new(),
+                };
+                let expr = clone_rc(expr).into_expr_inner(env)?;
 
-                match params.as_slice() {
-                    [] => Expr::App(Rc::new(expr), Rc::new(Expr::Lit(Ignore(Literal::Void)))),
-                    args => args.iter().fold(expr, |acc, p| {
-                        Expr::App(Rc::new(acc), Rc::new(p.clone().into_expr_inner(env)))
-                    }),
-                }
+                let args = params
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, p)| {
+                        let arg = p.into_expr_inner(env)?;
+                        Ok(Rc::new(match names.get(i) {
+                            Some(name) => name_lambda(arg, name),
+                            None => arg,
+                        }))
+                    })
+                    .collect::<Result<_, CompileError>>()?;
+
+                Expr::App(Rc::new(expr), args, Ignore(s))
+            }
+            BExpr::Loop(name, bindings, body, _) => {
+                let (vars, inits): (Vec<_>, Vec<_>) = bindings.into_iter().unzip();
+                let inits = inits
+                    .into_iter()
+                    .map(|e| Ok(Rc::new(e.into_expr_inner(env)?)))
+                    .collect::<Result<Vec<_>, CompileError>>()?;
+
+                let mut env = env.clone();
+                let name_var = FreeVar::fresh_named(name.as_str());
+                let vars = vars
+                    .into_iter()
+                    .map(|n| {
+                        let v = FreeVar::fresh_named(n.as_str());
+                        env.insert(n, v.clone());
+                        Binder(v)
+                    })
+                    .collect();
+                env.insert(name, name_var.clone());
+
+                let body = body.into_expr(&env)?;
+
+                Expr::Loop(inits, Scope::new((Binder(name_var), vars), Rc::new(body)))
+            }
+            BExpr::Recur(name, vars, args, _) => {
+                let lookup = |n: &String| {
+                    env.get(n).cloned().map(Var::Free).ok_or_else(|| {
+                        CompileError::Internal(format!("loop variable {} is not in scope", n))
+                    })
+                };
+
+                let vars = vars.iter().map(lookup).collect::<Result<_, _>>()?;
+                let args = args
+                    .into_iter()
+                    .map(|e| Ok(Rc::new(e.into_expr_inner(env)?)))
+                    .collect::<Result<_, CompileError>>()?;
+
+                Expr::Recur(lookup(&name)?, vars, args)
+            }
+            BExpr::Let(..) => {
+                return Err(CompileError::Internal(
+                    "let should have been removed before binding".to_owned(),
+                ))
+            }
+            BExpr::LetStar(..)
+            | BExpr::LetRec(..)
+            | BExpr::NamedLet(..)
+            | BExpr::Cond(..)
+            | BExpr::Case(..)
+            | BExpr::And(..)
+            | BExpr::Or(..)
+            | BExpr::When(..)
+            | BExpr::Unless(..)
+            | BExpr::Begin(..)
+            | BExpr::Guard(..)
+            | BExpr::Do(..) => {
+                return Err(CompileError::Internal(
+                    "derived forms should have been removed before binding".to_owned(),
+                ))
+            }
+            BExpr::MacroUse(..) | BExpr::LetSyntax(..) | BExpr::LetRecSyntax(..) => {
+                return Err(CompileError::Internal(
+                    "macros should have been expanded before binding".to_owned(),
+                ))
             }
-            BExpr::Let(_, _) => unreachable!(),
+        };
+
+        Ok(expr)
+    }
+}
+
+/// Name `expr` after the variable it is bound to if it is a lambda that
+/// doesn't have a name yet
+fn name_lambda(expr: Expr, name: &str) -> Expr {
+    match expr {
+        Expr::Lam(s, Ignore(LamInfo { name: None, span })) => Expr::Lam(
+            s,
+            Ignore(LamInfo {
+                name: Some(source_name(name).to_owned()),
+                span,
+            }),
+        ),
+        e => e,
+    }
+}
+
+fn pretty_form<'a, D>(
+    allocator: &'a D,
+    name: &'static str,
+    parts: impl Iterator<Item = DocBuilder<'a, D, ColorSpec>>,
+) -> DocBuilder<'a, D, ColorSpec>
+where
+    D: DocAllocator<'a, ColorSpec>,
+    D::Doc: Clone,
+{
+    allocator
+        .text(name)
+        .annotate(ColorSpec::new().set_fg(Some(Color::Magenta)).clone())
+        .append(allocator.space())
+        .append(allocator.intersperse(parts, allocator.line()))
+        .nest(1)
+        .group()
+        .parens()
+}
+
+fn pretty_let<'a, D>(
+    allocator: &'a D,
+    keyword: &'static str,
+    name: Option<&String>,
+    bindings: &[(String, BExpr)],
+    body: &BExprBody,
+) -> DocBuilder<'a, D, ColorSpec>
+where
+    D: DocAllocator<'a, ColorSpec>,
+    D::Doc: Clone,
+{
+    let bindings_pret = allocator
+        .intersperse(
+            bindings.iter().map(|(n, e)| {
+                allocator
+                    .text(n.to_owned())
+                    .annotate(ColorSpec::new().set_fg(Some(Color::Magenta)).clone())
+                    .append(allocator.space())
+                    .append(e.pretty(allocator))
+                    .group()
+                    .parens()
+            }),
+            allocator.space(),
+        )
+        .parens();
+
+    let name_pret = match name {
+        Some(n) => allocator
+            .text(n.to_owned())
+            .annotate(ColorSpec::new().set_fg(Some(Color::Green)).clone())
+            .append(allocator.space()),
+        None => allocator.nil(),
+    };
+
+    allocator
+        .text(keyword)
+        .annotate(ColorSpec::new().set_fg(Some(Color::Magenta)).clone())
+        .append(allocator.space())
+        .append(name_pret)
+        .append(bindings_pret)
+        .append(allocator.line())
+        .append(body.pretty(allocator))
+        .nest(1)
+        .group()
+        .parens()
+}
+
+fn pretty_let_syntax<'a, D>(
+    allocator: &'a D,
+    keyword: &'static str,
+    bindings: &[(String, Rc<SyntaxRules>)],
+    body: &BExprBody,
+) -> DocBuilder<'a, D, ColorSpec>
+where
+    D: DocAllocator<'a, ColorSpec>,
+    D::Doc: Clone,
+{
+    let bindings_pret = allocator
+        .intersperse(
+            bindings.iter().map(|(n, rules)| {
+                allocator
+                    .text(n.to_owned())
+                    .annotate(ColorSpec::new().set_fg(Some(Color::Green)).clone())
+                    .append(allocator.space())
+                    .append(allocator.as_string(rules))
+                    .group()
+                    .parens()
+            }),
+            allocator.line(),
+        )
+        .parens();
+
+    pretty_form(
+        allocator,
+        keyword,
+        vec![bindings_pret, body.pretty(allocator)].into_iter(),
+    )
+}
+
+fn rewrite_clauses<F: Fn(BExpr) -> BExpr>(clauses: Vec<CondClause>, f: &F) -> Vec<CondClause> {
+    clauses
+        .into_iter()
+        .map(|c| match c {
+            CondClause::Test(test, body) => CondClause::Test(test.rewrite(f), rewrite_all(body, f)),
+            CondClause::Arrow(test, receiver) => {
+                CondClause::Arrow(test.rewrite(f), receiver.rewrite(f))
+            }
+            CondClause::Else(body) => CondClause::Else(rewrite_all(body, f)),
+        })
+        .collect()
+}
+
+fn rewrite_bindings<F: Fn(BExpr) -> BExpr>(
+    bindings: Vec<(String, BExpr)>,
+    f: &F,
+) -> Vec<(String, BExpr)> {
+    bindings
+        .into_iter()
+        .map(|(n, e)| (n, e.rewrite(f)))
+        .collect()
+}
+
+/// `(let* ((a 1) (b a)) body)` is `(let ((a 1)) (let ((b a)) body))`
+fn desugar_let_star(bindings: Vec<(String, BExpr)>, body: BExprBody, s: Span) -> BExpr {
+    let mut bindings = bindings.into_iter().rev();
+
+    let innermost = match bindings.next() {
+        Some(last) => BExpr::Let(vec![last], body, s),
+        None => return BExpr::Let(Vec::new(), body, s),
+    };
+
+    bindings.fold(innermost, |acc, binding| {
+        BExpr::Let(vec![binding], BExprBody(Vec::new(), Rc::new(acc)), s)
+    })
+}
+
+/// Like `BExprBody::pull_defines`, bind every name to void first and then
+/// `set!` each one in order, so the bindings can refer to each other.
+fn desugar_letrec(bindings: Vec<(String, BExpr)>, body: BExprBody, s: Span) -> BExpr {
+    let let_bindings = bindings
+        .iter()
+        .map(|(n, e)| (n.clone(), BExpr::Lit(Literal::Void, e.span())))
+        .collect();
+
+    let sets = bindings.into_iter().map(|(n, e)| {
+        let span = e.span();
+        BExprBodyExpr::Expr(BExpr::Set(n, Rc::new(e), span))
+    });
+
+    let BExprBody(body_exprs, last) = body;
+    let body = BExprBody(sets.chain(body_exprs).collect(), last);
+
+    BExpr::Let(let_bindings, body, s)
+}
+
+/// `(let loop ((i 0)) body)` is `((letrec ((loop (lambda (i) body))) loop) 0)`
+fn desugar_named_let(
+    name: String,
+    bindings: Vec<(String, BExpr)>,
+    body: BExprBody,
+    s: Span,
+) -> BExpr {
+    let (params, inits): (Vec<_>, Vec<_>) = bindings.into_iter().unzip();
+
+    let lam = BExpr::Lam(params, None, body, s);
+    let loop_fn = desugar_letrec(
+        vec![(name.clone(), lam)],
+        BExprBody(Vec::new(), Rc::new(BExpr::Var(name, s))),
+        s,
+    );
+
+    BExpr::App(Rc::new(loop_fn), inits, s)
+}
+
+/// `(do ((var init step)...) (test result...) command...)` is
+/// `(let loop ((var init)...) (if test (begin result...) (begin command... (loop step...))))`
+fn desugar_do(
+    bindings: Vec<(String, BExpr, Option<BExpr>)>,
+    test: BExpr,
+    result: Vec<BExpr>,
+    commands: Vec<BExpr>,
+    s: Span,
+) -> BExpr {
+    let (let_bindings, steps): (Vec<_>, Vec<_>) = bindings
+        .into_iter()
+        .map(|(n, init, step)| {
+            let step = step.unwrap_or_else(|| BExpr::Var(n.clone(), s));
+            ((n, init), step)
+        })
+        .unzip();
+
+    let next = BExpr::App(Rc::new(BExpr::Var(DO_LOOP.to_owned(), s)), steps, s);
+    let body = BExpr::If(
+        Rc::new(test),
+        Rc::new(BExpr::Begin(result, s)),
+        Rc::new(BExpr::Begin(
+            commands.into_iter().chain(Some(next)).collect(),
+            s,
+        )),
+        s,
+    );
+
+    BExpr::NamedLet(
+        DO_LOOP.to_owned(),
+        let_bindings,
+        BExprBody(Vec::new(), Rc::new(body)),
+        s,
+    )
+}
+
+/// `guard` as given in R7RS, the clauses are tried with the guard's own
+/// handlers in place, and if none of them match the condition is raised again
+/// from where it was raised:
+///
+/// ```scheme
+/// ((call/cc
+///    (lambda (guard-k)
+///      (with-exception-handler
+///        (lambda (condition)
+///          ((call/cc
+///             (lambda (handler-k)
+///               (guard-k
+///                 (lambda ()
+///                   (let ((var condition))
+///                     (cond clause...
+///                           (else (handler-k (lambda () (raise-continuable condition))))))))))))
+///        (lambda ()
+///          body...
+///          (let ((tmp last)) (guard-k (lambda () tmp))))))))
+/// ```
+fn desugar_guard(var: String, mut clauses: Vec<CondClause>, body: BExprBody, s: Span) -> BExpr {
+    let var_ref = |n: &str| BExpr::Var(n.to_owned(), s);
+    let builtin = |n: &str| Rc::new(BExpr::BuiltinIdent(n.to_owned(), s));
+    let lambda = |params: Vec<&str>, body: BExpr| {
+        BExpr::Lam(
+            params.into_iter().map(str::to_owned).collect(),
+            None,
+            BExprBody(Vec::new(), Rc::new(body)),
+            s,
+        )
+    };
+    let thunk = |body: BExpr| lambda(Vec::new(), body);
+    let call_cc =
+        |k: &str, body: BExpr| BExpr::App(builtin("call/cc"), vec![lambda(vec![k], body)], s);
+
+    if !clauses.iter().any(|c| matches!(c, CondClause::Else(_))) {
+        let reraise = BExpr::App(builtin("raise-continuable"), vec![var_ref(CONDITION)], s);
+        let resume = BExpr::App(Rc::new(var_ref(HANDLER_K)), vec![thunk(reraise)], s);
+        clauses.push(CondClause::Else(vec![resume]));
+    }
+
+    let handle = BExpr::Let(
+        vec![(var, var_ref(CONDITION))],
+        BExprBody(Vec::new(), Rc::new(BExpr::Cond(clauses, s))),
+        s,
+    );
+    let to_guard = BExpr::App(Rc::new(var_ref(GUARD_K)), vec![thunk(handle)], s);
+    let handler = lambda(
+        vec![CONDITION],
+        BExpr::App(Rc::new(call_cc(HANDLER_K, to_guard)), Vec::new(), s),
+    );
+
+    let BExprBody(body_exprs, last) = body;
+    let finish = bind_temp(GUARD_TEMP, clone_rc(last), s, |t| {
+        BExpr::App(Rc::new(var_ref(GUARD_K)), vec![thunk(t)], s)
+    });
+    let body_thunk = BExpr::Lam(Vec::new(), None, BExprBody(body_exprs, Rc::new(finish)), s);
+
+    let install = BExpr::App(
+        builtin("with-exception-handler"),
+        vec![handler, body_thunk],
+        s,
+    );
+
+    BExpr::App(Rc::new(call_cc(GUARD_K, install)), Vec::new(), s)
+}
+
+/// Whether every use of the loop `name` in `e` is a call in tail position
+/// with one argument for each of `vars`.
+///
+/// Calls under a binding of one of `vars` don't count, as a `Recur` finds the
+/// loop's variables by name.
+fn only_tail_calls(e: &BExpr, name: &str, vars: &[String], tail: bool) -> bool {
+    let non_tail = |e: &BExpr| only_tail_calls(e, name, vars, false);
+    let scoped_body = |names: &mut dyn Iterator<Item = &String>, body: &BExprBody| {
+        let names: Vec<_> = names.collect();
+
+        // the loop is shadowed, nothing in here can refer to it
+        names.iter().any(|n| *n == name)
+            || body_only_tail_calls(
+                body,
+                name,
+                vars,
+                tail && !names.iter().any(|n| vars.contains(n)),
+            )
+    };
+
+    match e {
+        BExpr::Var(n, _) => n != name,
+        BExpr::Lit(..) | BExpr::BuiltinIdent(..) => true,
+        BExpr::Set(n, e, _) => n != name && non_tail(e),
+        BExpr::If(c, ift, iff, _) => {
+            non_tail(c)
+                && only_tail_calls(ift, name, vars, tail)
+                && only_tail_calls(iff, name, vars, tail)
+        }
+        BExpr::Let(bindings, body, _) => {
+            bindings.iter().all(|(_, e)| non_tail(e))
+                && scoped_body(&mut bindings.iter().map(|(n, _)| n), body)
+        }
+        BExpr::Loop(n, bindings, body, _) => {
+            bindings.iter().all(|(_, e)| non_tail(e))
+                && scoped_body(
+                    &mut std::iter::once(n).chain(bindings.iter().map(|(n, _)| n)),
+                    body,
+                )
+        }
+        BExpr::Lam(params, rest, body, _) => {
+            params.iter().chain(rest).any(|p| p == name)
+                || body_only_tail_calls(body, name, vars, false)
+        }
+        BExpr::App(f, args, _) => {
+            let args_ok = args.iter().all(non_tail);
+
+            match &**f {
+                BExpr::Var(n, _) if n == name => tail && args.len() == vars.len() && args_ok,
+                _ => non_tail(f) && args_ok,
+            }
+        }
+        BExpr::Recur(_, _, args, _) => args.iter().all(non_tail),
+        // everything else has been desugared by now
+        _ => false,
+    }
+}
+
+fn body_only_tail_calls(body: &BExprBody, name: &str, vars: &[String], tail: bool) -> bool {
+    body.0.iter().all(|e| match e {
+        BExprBodyExpr::Expr(e) => only_tail_calls(e, name, vars, false),
+        BExprBodyExpr::Def(..) | BExprBodyExpr::DefSyntax(..) => false,
+    }) && only_tail_calls(&body.1, name, vars, tail)
+}
+
+/// Whether a lambda in `e` refers to any of `names`
+fn captures(e: &BExpr, names: &[String], in_lambda: bool) -> bool {
+    let go = |e: &BExpr| captures(e, names, in_lambda);
+    let refers = |n: &String| in_lambda && names.contains(n);
+
+    match e {
+        BExpr::Var(n, _) => refers(n),
+        BExpr::Lit(..) | BExpr::BuiltinIdent(..) => false,
+        BExpr::Set(n, e, _) => refers(n) || go(e),
+        BExpr::If(c, ift, iff, _) => go(c) || go(ift) || go(iff),
+        BExpr::Let(bindings, body, _) | BExpr::Loop(_, bindings, body, _) => {
+            bindings.iter().any(|(_, e)| go(e)) || body_captures(body, names, in_lambda)
+        }
+        BExpr::Lam(_, _, body, _) => body_captures(body, names, true),
+        BExpr::App(f, args, _) => go(f) || args.iter().any(go),
+        BExpr::Recur(_, vars, args, _) => vars.iter().any(refers) || args.iter().any(go),
+        _ => true,
+    }
+}
+
+fn body_captures(body: &BExprBody, names: &[String], in_lambda: bool) -> bool {
+    body.0.iter().any(|e| match e {
+        BExprBodyExpr::Expr(e) => captures(e, names, in_lambda),
+        BExprBodyExpr::Def(..) | BExprBodyExpr::DefSyntax(..) => true,
+    }) || captures(&body.1, names, in_lambda)
+}
+
+/// The builtins that call a procedure they are given, which can capture the
+/// continuation of the call and return to it again later
+const CALLS_PROCEDURES: &[&str] = &[
+    "call/cc",
+    "call-with-current-continuation",
+    "dynamic-wind",
+    "with-exception-handler",
+    "raise-continuable",
+];
+
+/// Whether `e` makes a call, other than in tail position, that could capture
+/// its continuation.
+///
+/// A lambda's body doesn't count, it can only run when something calls it.
+fn calls_out(e: &BExpr, tail: bool) -> bool {
+    let non_tail = |e: &BExpr| calls_out(e, false);
+
+    match e {
+        BExpr::Var(..) | BExpr::Lit(..) | BExpr::BuiltinIdent(..) | BExpr::Lam(..) => false,
+        BExpr::Set(_, e, _) => non_tail(e),
+        BExpr::If(c, ift, iff, _) => non_tail(c) || calls_out(ift, tail) || calls_out(iff, tail),
+        BExpr::Let(bindings, body, _) | BExpr::Loop(_, bindings, body, _) => {
+            bindings.iter().any(|(_, e)| non_tail(e)) || body_calls_out(body, tail)
         }
+        BExpr::App(f, args, _) => {
+            let known = match &**f {
+                BExpr::BuiltinIdent(n, _) => !CALLS_PROCEDURES.contains(&n.as_str()),
+                _ => false,
+            };
+
+            (!tail && !known) || non_tail(f) || args.iter().any(non_tail)
+        }
+        BExpr::Recur(_, _, args, _) => args.iter().any(non_tail),
+        _ => true,
+    }
+}
+
+fn body_calls_out(body: &BExprBody, tail: bool) -> bool {
+    body.0.iter().any(|e| match e {
+        BExprBodyExpr::Expr(e) => calls_out(e, false),
+        BExprBodyExpr::Def(..) | BExprBodyExpr::DefSyntax(..) => true,
+    }) || calls_out(&body.1, tail)
+}
+
+/// Replace the tail calls of the loop `name` in `e` with `Recur`
+fn into_recur(e: BExpr, name: &str, vars: &[String]) -> BExpr {
+    let binds_name = |bindings: &[(String, BExpr)]| bindings.iter().any(|(n, _)| n == name);
+    let tail_of = |body: BExprBody| {
+        let BExprBody(body_exprs, last) = body;
+        BExprBody(body_exprs, Rc::new(into_recur(clone_rc(last), name, vars)))
+    };
+
+    match e {
+        BExpr::App(f, args, s) => match &*f {
+            BExpr::Var(n, _) if n == name => {
+                BExpr::Recur(name.to_owned(), vars.to_owned(), args, s)
+            }
+            _ => BExpr::App(f, args, s),
+        },
+        BExpr::If(c, ift, iff, s) => BExpr::If(
+            c,
+            Rc::new(into_recur(clone_rc(ift), name, vars)),
+            Rc::new(into_recur(clone_rc(iff), name, vars)),
+            s,
+        ),
+        BExpr::Let(bindings, body, s) if !binds_name(&bindings) => {
+            BExpr::Let(bindings, tail_of(body), s)
+        }
+        BExpr::Loop(n, bindings, body, s) if n != name && !binds_name(&bindings) => {
+            BExpr::Loop(n, bindings, tail_of(body), s)
+        }
+        e => e,
+    }
+}
+
+fn rewrite_all<F: Fn(BExpr) -> BExpr>(es: Vec<BExpr>, f: &F) -> Vec<BExpr> {
+    es.into_iter().map(|e| e.rewrite(f)).collect()
+}
+
+// Temporaries introduced by desugaring contain a space, so they can never
+// clash with a name written in the program
+const OR_TEMP: &str = " or-tmp";
+const COND_TEMP: &str = " cond-tmp";
+const CASE_KEY: &str = " case-key";
+const DO_LOOP: &str = " do-loop";
+const GUARD_K: &str = " guard-k";
+const HANDLER_K: &str = " handler-k";
+const CONDITION: &str = " condition";
+const GUARD_TEMP: &str = " guard-tmp";
+
+fn void(s: Span) -> BExpr {
+    BExpr::Lit(Literal::Void, s)
+}
+
+/// Evaluate each expression in turn, giving the value of the last
+fn sequence(mut es: Vec<BExpr>, s: Span) -> BExpr {
+    let last = match es.pop() {
+        Some(last) => last,
+        None => return void(s),
+    };
+
+    if es.is_empty() {
+        return last;
+    }
+
+    let body = es.into_iter().map(BExprBodyExpr::Expr).collect();
+    BExpr::Let(Vec::new(), BExprBody(body, Rc::new(last)), s)
+}
+
+/// `(let ((name e)) body)`, `body` is given a reference to `name`
+fn bind_temp(name: &str, e: BExpr, s: Span, body: impl FnOnce(BExpr) -> BExpr) -> BExpr {
+    let body = body(BExpr::Var(name.to_owned(), s));
+
+    BExpr::Let(
+        vec![(name.to_owned(), e)],
+        BExprBody(Vec::new(), Rc::new(body)),
+        s,
+    )
+}
+
+fn desugar_and(es: Vec<BExpr>, s: Span) -> BExpr {
+    let mut es = es.into_iter().rev();
+
+    match es.next() {
+        None => BExpr::Lit(Literal::Bool(true), s),
+        Some(last) => es.fold(last, |acc, e| {
+            BExpr::If(
+                Rc::new(e),
+                Rc::new(acc),
+                Rc::new(BExpr::Lit(Literal::Bool(false), s)),
+                s,
+            )
+        }),
+    }
+}
+
+fn desugar_or(es: Vec<BExpr>, s: Span) -> BExpr {
+    let mut es = es.into_iter().rev();
+
+    match es.next() {
+        None => BExpr::Lit(Literal::Bool(false), s),
+        Some(last) => es.fold(last, |acc, e| {
+            bind_temp(OR_TEMP, e, s, |t| {
+                BExpr::If(Rc::new(t.clone()), Rc::new(t), Rc::new(acc), s)
+            })
+        }),
+    }
+}
+
+fn desugar_cond(clauses: Vec<CondClause>, s: Span) -> BExpr {
+    clauses
+        .into_iter()
+        .rev()
+        .fold(void(s), |rest, clause| match clause {
+            CondClause::Else(body) => sequence(body, s),
+            CondClause::Test(test, ref body) if body.is_empty() => {
+                bind_temp(COND_TEMP, test, s, |t| {
+                    BExpr::If(Rc::new(t.clone()), Rc::new(t), Rc::new(rest), s)
+                })
+            }
+            CondClause::Test(test, body) => {
+                BExpr::If(Rc::new(test), Rc::new(sequence(body, s)), Rc::new(rest), s)
+            }
+            CondClause::Arrow(test, receiver) => bind_temp(COND_TEMP, test, s, |t| {
+                let call = BExpr::App(Rc::new(receiver), vec![t.clone()], s);
+                BExpr::If(Rc::new(t), Rc::new(call), Rc::new(rest), s)
+            }),
+        })
+}
+
+fn desugar_case(
+    key: BExpr,
+    clauses: Vec<(Vec<BExpr>, Vec<BExpr>)>,
+    else_body: Option<Vec<BExpr>>,
+    s: Span,
+) -> BExpr {
+    bind_temp(CASE_KEY, key, s, |key| {
+        let eq = Rc::new(BExpr::BuiltinIdent("eq?".to_owned(), s));
+        let otherwise = else_body.map_or_else(|| void(s), |body| sequence(body, s));
+
+        clauses
+            .into_iter()
+            .rev()
+            .fold(otherwise, |rest, (datums, body)| {
+                // the tests are all booleans, so they can be or'd without a temporary
+                let matches =
+                    datums
+                        .into_iter()
+                        .rev()
+                        .fold(BExpr::Lit(Literal::Bool(false), s), |acc, d| {
+                            BExpr::If(
+                                Rc::new(BExpr::App(eq.clone(), vec![key.clone(), d], s)),
+                                Rc::new(BExpr::Lit(Literal::Bool(true), s)),
+                                Rc::new(acc),
+                                s,
+                            )
+                        });
+
+                BExpr::If(
+                    Rc::new(matches),
+                    Rc::new(sequence(body, s)),
+                    Rc::new(rest),
+                    s,
+                )
+            })
+    })
+}
+
+impl CondClause {
+    pub fn pretty<'a, D>(&self, allocator: &'a D) -> DocBuilder<'a, D, ColorSpec>
+    where
+        D: DocAllocator<'a, ColorSpec>,
+        D::Doc: Clone,
+    {
+        let parts = match self {
+            CondClause::Test(test, body) => std::iter::once(test.pretty(allocator))
+                .chain(body.iter().map(|e| e.pretty(allocator)))
+                .collect::<Vec<_>>(),
+            CondClause::Arrow(test, receiver) => vec![
+                test.pretty(allocator),
+                allocator.text("=>"),
+                receiver.pretty(allocator),
+            ],
+            CondClause::Else(body) => std::iter::once(allocator.text("else"))
+                .chain(body.iter().map(|e| e.pretty(allocator)))
+                .collect(),
+        };
+
+        allocator
+            .intersperse(parts, allocator.space())
+            .group()
+            .parens()
     }
 }
 
 impl BExprBodyExpr {
+    pub fn span(&self) -> Span {
+        match self {
+            BExprBodyExpr::Def(_, _, s) | BExprBodyExpr::DefSyntax(_, _, s) => *s,
+            BExprBodyExpr::Expr(e) => e.span(),
+        }
+    }
+
     pub fn pretty<'a, D>(&self, allocator: &'a D) -> DocBuilder<'a, D, ColorSpec>
     where
         D: DocAllocator<'a, ColorSpec>,
         D::Doc: Clone,
     {
         match self {
-            BExprBodyExpr::Def(n, e) => {
+            BExprBodyExpr::Def(n, e, _) => {
                 let e_pret = e.pretty(allocator);
 
                 allocator
@@ -317,19 +1393,68 @@ impl BExprBodyExpr {
                     .group()
                     .parens()
             }
+            BExprBodyExpr::DefSyntax(n, Transformer::Rules(rules), _) => allocator
+                .text("define-syntax")
+                .annotate(ColorSpec::new().set_fg(Some(Color::Magenta)).clone())
+                .append(allocator.space())
+                .append(
+                    allocator
+                        .text(n.to_owned())
+                        .annotate(ColorSpec::new().set_fg(Some(Color::Green)).clone()),
+                )
+                .append(allocator.line())
+                .append(allocator.as_string(rules))
+                .nest(1)
+                .group()
+                .parens(),
+            BExprBodyExpr::DefSyntax(n, Transformer::Procedure(procedure), _) => {
+                let params = procedure
+                    .params
+                    .iter()
+                    .map(String::as_str)
+                    .chain(procedure.rest.iter().flat_map(|r| [".", r.as_str()]));
+                let header = allocator.intersperse(
+                    std::iter::once(n.as_str())
+                        .chain(params)
+                        .map(|p| allocator.text(p.to_owned())),
+                    allocator.space(),
+                );
+
+                allocator
+                    .text("define-macro")
+                    .annotate(ColorSpec::new().set_fg(Some(Color::Magenta)).clone())
+                    .append(allocator.space())
+                    .append(
+                        header
+                            .parens()
+                            .annotate(ColorSpec::new().set_fg(Some(Color::Green)).clone()),
+                    )
+                    .append(allocator.line())
+                    .append(procedure.body.pretty(allocator))
+                    .nest(1)
+                    .group()
+                    .parens()
+            }
             BExprBodyExpr::Expr(e) => e.pretty(allocator),
         }
     }
 
     pub fn rewrite<F: Fn(BExpr) -> BExpr>(self, f: &F) -> BExprBodyExpr {
         match self {
-            BExprBodyExpr::Def(n, e) => BExprBodyExpr::Def(n, e.rewrite(f)),
+            BExprBodyExpr::Def(n, e, s) => BExprBodyExpr::Def(n, e.rewrite(f), s),
+            BExprBodyExpr::DefSyntax(..) => self,
             BExprBodyExpr::Expr(e) => BExprBodyExpr::Expr(e.rewrite(f)),
         }
     }
 }
 
 impl BExprBody {
+    pub fn span(&self) -> Span {
+        self.0
+            .first()
+            .map_or_else(|| self.1.span(), |e| e.span().to(self.1.span()))
+    }
+
     pub fn pretty<'a, D>(&self, allocator: &'a D) -> DocBuilder<'a, D, ColorSpec>
     where
         D: DocAllocator<'a, ColorSpec>,
@@ -351,35 +1476,66 @@ impl BExprBody {
         )
     }
 
-    pub fn pull_defines(self) -> BExprBody {
+    pub fn pull_defines(self, span: Span) -> BExprBody {
         let mut defines = Vec::new();
 
         let body = self
             .0
             .into_iter()
             .map(|e| match e {
-                BExprBodyExpr::Def(n, e) => {
-                    defines.push(n.clone());
-                    BExprBodyExpr::Expr(BExpr::Set(n, Rc::new(e)))
+                BExprBodyExpr::Def(n, e, s) => {
+                    defines.push((n.clone(), s));
+                    BExprBodyExpr::Expr(BExpr::Set(n, Rc::new(e), s))
                 }
-                BExprBodyExpr::Expr(_) => e,
+                BExprBodyExpr::DefSyntax(..) | BExprBodyExpr::Expr(_) => e,
             })
             .collect();
 
         let let_bindings = defines
             .into_iter()
-            .map(|n| (n, BExpr::Lit(Literal::Void)))
+            .map(|(n, s)| (n, BExpr::Lit(Literal::Void, s)))
             .collect();
-        let let_ = BExpr::Let(let_bindings, BExprBody(body, self.1));
+        let let_ = BExpr::Let(let_bindings, BExprBody(body, self.1), span);
 
         BExprBody(Vec::new(), Rc::new(let_))
     }
 
+    /// Evaluate each expression of the body in turn, giving the value of the last
+    fn into_expr(self, env: &HashMap<String, FreeVar<String>>) -> Result<Expr, CompileError> {
+        let body = self.as_expressions();
+
+        let expr = match body.as_slice() {
+            [] => Expr::Lit(Ignore(Literal::Void)),
+            [first, rest @ ..] => {
+                rest.iter()
+                    .try_fold(first.clone().into_expr_inner(env)?, |acc, e| {
+                        let span = e.span();
+
+                        Ok::<_, CompileError>(Expr::App(
+                            Rc::new(Expr::Lam(
+                                Scope::new(
+                                    (vec![Binder(FreeVar::fresh_named("_unused"))], None),
+                                    Rc::new(e.clone().into_expr_inner(env)?),
+                                ),
+                                Ignore(LamInfo { name: None, span }),
+                            )),
+                            vec![Rc::new(acc)],
+                            Ignore(span),
+                        ))
+                    })?
+            }
+        };
+
+        Ok(expr)
+    }
+
     pub fn as_expressions(self) -> Vec<BExpr> {
         self.0
             .into_iter()
             .map(|e| match e {
-                BExprBodyExpr::Def(_, _) => panic!("should be removed"),
+                BExprBodyExpr::Def(..) | BExprBodyExpr::DefSyntax(..) => {
+                    panic!("should be removed")
+                }
                 BExprBodyExpr::Expr(e) => e,
             })
             .chain(std::iter::once(clone_rc(self.1)))
@@ -54,9 +54,10 @@ pub enum CExpr<'a> {
     LitStr(Cow<'a, str>),
     LitUInt(usize),
     LitIInt(isize),
+    LitFloat(f64),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum CType<'a> {
     Ptr(Rc<CType<'a>>),
     Arr(Rc<CType<'a>>, Option<usize>),
@@ -89,6 +90,7 @@ pub enum CStmt<'a> {
     Decl(CDecl<'a>),
     Block(Vec<Rc<CStmt<'a>>>),
     Expr(CExpr<'a>),
+    Continue,
 }
 
 #[derive(Debug)]
@@ -186,7 +188,7 @@ impl<'a> ToC for CExpr<'a> {
                 exp expr,
                 str ")[",
                 exp index,
-                chr ')'
+                chr ']'
             ),
             Dot { expr, attr } => export_helper!(
                 s, chr '(', exp expr, str ").", str attr
@@ -235,16 +237,34 @@ impl<'a> ToC for CExpr<'a> {
             ),
             Ident(name) => s.push_str(name),
             LitStr(lit) => {
-                let lit = &lit.replace("\n", "\\n");
-                export_helper!(
-                    s,
-                    chr '"',
-                    str lit,
-                    chr '"'
-                )
+                s.push('"');
+                for b in lit.bytes() {
+                    match b {
+                        b'"' | b'\\' => {
+                            s.push('\\');
+                            s.push(b as char);
+                        }
+                        // '?' is escaped so it can't form a trigraph
+                        b' '..=b'~' if b != b'?' => s.push(b as char),
+                        // always three octal digits, so following digits aren't
+                        // read as part of the escape
+                        _ => write!(s, "\\{:03o}", b).unwrap(),
+                    }
+                }
+                s.push('"');
             }
             LitUInt(lit) => export_helper!(s, str & lit.to_string()),
+            // C parses `-N` as negating `N`, which doesn't fit for the minimum value
+            LitIInt(lit) if *lit == isize::MIN => {
+                export_helper!(s, str & format!("({} - 1)", lit + 1))
+            }
             LitIInt(lit) => export_helper!(s, str & lit.to_string()),
+            LitFloat(lit) if lit.is_infinite() => {
+                export_helper!(s, str if *lit > 0.0 { "HUGE_VAL" } else { "(-HUGE_VAL)" })
+            }
+            // debug formatting prints the shortest representation that round trips,
+            // and always includes a '.' or an exponent
+            LitFloat(lit) => export_helper!(s, str & format!("{:?}", lit)),
         }
     }
 }
@@ -356,6 +376,7 @@ impl<'a> ToC for CStmt<'a> {
                 exp body,
                 chr ';'
             ),
+            Continue => export_helper!(s, str "continue;"),
         }
     }
 }
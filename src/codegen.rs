@@ -8,6 +8,9 @@ use crate::cdsl::CDecl;
 use crate::cdsl::CExpr;
 use crate::cdsl::CStmt;
 use crate::cdsl::CType;
+use crate::cdsl::ToCDC;
+use crate::diagnostics::SourceFile;
+use crate::error::CompileError;
 use crate::lifted_expr::LExpr;
 use crate::lifted_expr::LiftedLambda;
 use crate::literals::Literal;
@@ -17,15 +20,25 @@ pub struct CodegenCtx<'a> {
     protos: Vec<CDecl<'static>>,
     declarations: Vec<CDecl<'static>>,
     lambdas: &'a HashMap<usize, LiftedLambda>,
+    source: &'a SourceFile<'a>,
+    /// The name, arity and whether it is variadic of each builtin used, their
+    /// entries in `lambda_infos` come after those of the lambdas
+    builtins: Vec<(String, usize, bool)>,
+    /// The name of the loop whose body is being generated, a recur of it
+    /// jumps straight back to the start of the body
+    current_loop: Option<FreeVar<String>>,
 }
 
 impl<'a> CodegenCtx<'a> {
-    pub fn new(lambdas: &'a HashMap<usize, LiftedLambda>) -> Self {
+    pub fn new(lambdas: &'a HashMap<usize, LiftedLambda>, source: &'a SourceFile<'a>) -> Self {
         Self {
             unique_var_id: 0,
             protos: Vec::new(),
             declarations: Vec::new(),
             lambdas,
+            source,
+            builtins: Vec::new(),
+            current_loop: None,
         }
     }
 
@@ -46,6 +59,92 @@ impl<'a> CodegenCtx<'a> {
     fn add_decl(&mut self, decl: CDecl<'static>) {
         self.declarations.push(decl);
     }
+
+    /// The index into `lambda_infos` of a builtin, lambda ids count up from 0
+    /// so the builtins start after the last of them
+    fn builtin_info_index(&mut self, name: &str, arity: usize, variadic: bool) -> usize {
+        let index = match self.builtins.iter().position(|(n, _, _)| n == name) {
+            Some(index) => index,
+            None => {
+                self.builtins.push((name.to_owned(), arity, variadic));
+                self.builtins.len() - 1
+            }
+        };
+
+        self.lambdas.len() + index
+    }
+
+    /// The `lambda_infos` table, with the entries of the lambdas in order of
+    /// their ids and then those of the builtins
+    fn lambda_infos_table(&self) -> Result<CDecl<'static>, CompileError> {
+        let mut entries = Vec::new();
+
+        for id in 0..self.lambdas.len() {
+            let lambda = self
+                .lambdas
+                .get(&id)
+                .ok_or_else(|| CompileError::Internal(format!("lambda ids skip over {}", id)))?;
+            let (name, location) = match &lambda.info {
+                Some(info) => (info.name.clone(), Some(self.source.location(info.span))),
+                // continuations can be called by the program through call/cc,
+                // loops are only entered by the loop itself
+                None => (Some("continuation".to_owned()), None),
+            };
+
+            entries.push(lambda_info_entry(
+                name,
+                lambda.params.len(),
+                lambda.rest.is_some(),
+                location,
+            ));
+        }
+
+        for (name, arity, variadic) in &self.builtins {
+            entries.push(lambda_info_entry(
+                Some(name.clone()),
+                *arity,
+                *variadic,
+                None,
+            ));
+        }
+
+        Ok(CDecl::Var {
+            name: "lambda_infos".into(),
+            typ: CType::Arr(
+                Rc::new(CType::Const(Rc::new(CType::Struct("lambda_info".into())))),
+                None,
+            ),
+            init: Some(CExpr::InitList(entries)),
+        })
+    }
+}
+
+fn lambda_info_entry(
+    name: Option<String>,
+    arity: usize,
+    variadic: bool,
+    location: Option<String>,
+) -> CExpr<'static> {
+    let str_or_null =
+        |s: Option<String>| s.map_or(CExpr::Ident("NULL".into()), |s| CExpr::LitStr(s.into()));
+
+    CExpr::InitList(vec![
+        str_or_null(name),
+        CExpr::LitUInt(arity),
+        CExpr::Ident(if variadic { "true" } else { "false" }.into()),
+        str_or_null(location),
+    ])
+}
+
+/// A pointer to entry `index` of `lambda_infos`
+fn lambda_info_ref(index: usize) -> CExpr<'static> {
+    CExpr::PreUnOp {
+        op: "&".into(),
+        ex: Rc::new(CExpr::ArrIndexOp {
+            index: Rc::new(CExpr::LitUInt(index)),
+            expr: Rc::new(CExpr::Ident("lambda_infos".into())),
+        }),
+    }
 }
 
 fn name_for_free_var(var: &FreeVar<String>) -> String {
@@ -66,16 +165,10 @@ fn object_type() -> CType<'static> {
 
 impl LiftedLambda {
     fn env_struct(&self) -> CDecl<'static> {
-        let members = self
-            .freevars
-            .iter()
-            .map(|v| (name_for_free_var(v).into(), object_type()))
-            .collect();
-
-        CDecl::Struct {
-            name: format!("env_{}", self.id).into(),
-            members,
-        }
+        env_struct(
+            format!("env_{}", self.id),
+            self.freevars.union(&self.locals),
+        )
     }
 
     fn construct_env_code(&self, dest: &str) -> CStmt<'static> {
@@ -88,9 +181,11 @@ impl LiftedLambda {
         })
     }
 
+    /// Variables in `bound` aren't copied from `src_env`, the caller fills them in
     fn make_env_code(
         &self,
         src_env: &Rc<CExpr<'static>>,
+        bound: &[FreeVar<String>],
         ctx: &mut CodegenCtx,
         supporting_stmts: &mut Vec<Rc<CStmt<'static>>>,
     ) -> Rc<CExpr<'static>> {
@@ -102,7 +197,13 @@ impl LiftedLambda {
         let env_access = Rc::new(self.generate_env_cast(env_expr.clone()));
 
         let mut vars_to_copy = self.freevars.clone();
-        for param in &self.params {
+        for param in self
+            .params
+            .iter()
+            .chain(&self.rest)
+            .chain(&self.cont)
+            .chain(bound)
+        {
             vars_to_copy.remove(param);
         }
 
@@ -128,29 +229,35 @@ impl LiftedLambda {
         current_env: &Rc<CExpr<'static>>,
         ctx: &mut CodegenCtx,
         supporting_stmts: &mut Vec<Rc<CStmt<'static>>>,
-    ) -> CExpr<'static> {
-        let env_expr = self.make_env_code(current_env, ctx, supporting_stmts);
+    ) -> Result<CExpr<'static>, CompileError> {
+        let env_expr = self.make_env_code(current_env, &[], ctx, supporting_stmts);
 
-        let init_name = match self.params.len() {
-            1 => "OBJECT_CLOSURE_ONE_NEW",
-            2 => "OBJECT_CLOSURE_TWO_NEW",
-            n => panic!("closure was not one or two parameters, was: {}", n),
-        };
+        self.closure_code(env_expr, ctx, supporting_stmts)
+    }
 
+    fn closure_code(
+        &self,
+        env_expr: Rc<CExpr<'static>>,
+        ctx: &mut CodegenCtx,
+        supporting_stmts: &mut Vec<Rc<CStmt<'static>>>,
+    ) -> Result<CExpr<'static>, CompileError> {
         let var_name = ctx.gen_var();
 
+        let args: Vec<Rc<dyn ToCDC>> = vec![
+            Rc::new(CExpr::Ident(var_name.to_owned().into())),
+            Rc::new(CExpr::Ident(format!("lambda_{}", self.id).into())),
+            env_expr,
+            Rc::new(lambda_info_ref(self.id)),
+        ];
+
         let init_stmt = CStmt::Expr(CExpr::MacroCall {
-            name: init_name.into(),
-            args: vec![
-                Rc::new(CExpr::Ident(var_name.to_owned().into())),
-                Rc::new(CExpr::Ident(format!("lambda_{}", self.id).into())),
-                env_expr,
-            ],
+            name: "OBJECT_CLOSURE_NEW".into(),
+            args,
         });
 
         supporting_stmts.push(Rc::new(init_stmt));
 
-        CExpr::Ident(var_name.into())
+        Ok(CExpr::Ident(var_name.into()))
     }
 
     fn generate_env_cast(&self, in_expr: Rc<CExpr<'static>>) -> CExpr<'static> {
@@ -170,28 +277,76 @@ impl LiftedLambda {
         CType::Ptr(Rc::new(CType::Struct(format!("env_{}", self.id).into())))
     }
 
-    fn generate_func(&self, ctx: &mut CodegenCtx) {
-        let params = self
-            .params
-            .iter()
-            .map(|p| (p.clone(), ctx.gen_var()))
-            .collect::<Vec<_>>();
-
+    fn generate_func(&self, ctx: &mut CodegenCtx) -> Result<(), CompileError> {
         let env_obj_s = Rc::new(CType::Struct("env_obj".into()));
         let obj_s = Rc::new(CType::Struct("obj".into()));
 
-        let (mut with_names, mut types_only): (Vec<_>, Vec<_>) = params
+        let env_move_stmt = Rc::new(CStmt::Decl(CDecl::Var {
+            name: "env".into(),
+            typ: self.generate_env_ptr_typ(),
+            init: Some(self.generate_env_cast(Rc::new(CExpr::Ident("env_in".into())))),
+        }));
+
+        let mut stmts: Vec<Rc<CStmt<'static>>> = vec![env_move_stmt];
+
+        // each parameter and the C expression holding its argument, every
+        // lambda is given an array of its arguments
+        let args = Rc::new(CExpr::Ident("args".into()));
+        let mut params = self
+            .params
             .iter()
-            .map(|(_, n)| {
-                (
-                    (n.to_owned().into(), CType::Ptr(obj_s.clone())),
-                    CType::Ptr(obj_s.clone()),
-                )
+            .enumerate()
+            .map(|(i, p)| {
+                let arg = CExpr::ArrIndexOp {
+                    index: Rc::new(CExpr::LitUInt(i)),
+                    expr: args.clone(),
+                };
+
+                (p.clone(), arg)
             })
-            .unzip();
+            .collect::<Vec<_>>();
 
-        with_names.push(("env_in".into(), CType::Ptr(env_obj_s.clone())));
-        types_only.push(CType::Ptr(env_obj_s.clone()));
+        // the arguments past the parameters of a variadic lambda are made into
+        // a list
+        if let Some(rest) = &self.rest {
+            let rest_name = ctx.gen_var();
+            stmts.push(Rc::new(CStmt::Expr(CExpr::MacroCall {
+                name: "OBJECT_ARGS_LIST_NEW".into(),
+                args: vec![
+                    Rc::new(CExpr::Ident(rest_name.to_owned().into())),
+                    Rc::new(CExpr::BinOp {
+                        op: "+".into(),
+                        left: args,
+                        right: Rc::new(CExpr::LitUInt(self.params.len())),
+                    }),
+                    Rc::new(CExpr::BinOp {
+                        op: "-".into(),
+                        left: Rc::new(CExpr::Ident("nargs".into())),
+                        right: Rc::new(CExpr::LitUInt(self.params.len())),
+                    }),
+                ],
+            })));
+
+            params.push((rest.clone(), CExpr::Ident(rest_name.into())));
+        }
+
+        if let Some(cont) = &self.cont {
+            params.push((cont.clone(), CExpr::Ident("cont".into())));
+        }
+
+        let with_names = vec![
+            ("nargs".into(), CType::Other("size_t".into())),
+            (
+                "args".into(),
+                CType::Ptr(Rc::new(CType::Ptr(obj_s.clone()))),
+            ),
+            ("cont".into(), CType::Ptr(obj_s)),
+            ("env_in".into(), CType::Ptr(env_obj_s)),
+        ];
+        let types_only = with_names
+            .iter()
+            .map(|(_, t)| t.clone())
+            .collect::<Vec<_>>();
 
         let proto = CDecl::FunProto {
             name: format!("lambda_{}", self.id).into(),
@@ -202,45 +357,36 @@ impl LiftedLambda {
 
         ctx.add_proto(proto);
 
-        let env_move_stmt = Rc::new(CStmt::Decl(CDecl::Var {
-            name: "env".into(),
-            typ: self.generate_env_ptr_typ(),
-            init: Some(self.generate_env_cast(Rc::new(CExpr::Ident("env_in".into())))),
-        }));
-
         let env_expr = Rc::new(CExpr::Ident("env".into()));
 
-        let mut stmts: Vec<Rc<CStmt<'static>>> = vec![env_move_stmt];
-
-        for (dest_var, in_var) in &params {
-            if !self.freevars.contains(dest_var) {
+        for (dest_var, arg) in params {
+            if !self.freevars.contains(&dest_var) {
                 // if a parameter isn't used in the body, we discard it instead of actually using it
                 continue;
             }
 
-            let tmp_name = ctx.gen_var();
-            let tmp_var = Rc::new(CExpr::Ident(tmp_name.into()));
+            bind_cell(&env_expr, &dest_var, Rc::new(arg), ctx, &mut stmts);
+        }
 
-            stmts.push(Rc::new(CStmt::Expr(CExpr::MacroCall {
-                name: "OBJECT_CELL_OBJ_NEW".into(),
-                args: vec![
-                    tmp_var.clone(),
-                    Rc::new(CExpr::Ident(in_var.to_owned().into())),
-                ],
-            })));
+        if self.loop_name.is_some() {
+            // the body of a loop is a C loop, so an iteration that doesn't
+            // need a gc first can just start the next one
+            ctx.current_loop = self.loop_name.clone();
 
-            stmts.push(Rc::new(CStmt::Expr(CExpr::BinOp {
-                op: "=".into(),
-                left: Rc::new(CExpr::Arrow {
-                    expr: env_expr.clone(),
-                    attr: name_for_free_var(dest_var).into(),
-                }),
-                right: tmp_var,
-            })));
-        }
+            let mut body_stmts = Vec::new();
+            let final_expr = do_codegen_internal(&self.body, ctx, &mut body_stmts)?;
+            body_stmts.push(Rc::new(CStmt::Expr(final_expr)));
+
+            ctx.current_loop = None;
 
-        let final_expr = do_codegen_internal(&self.body, ctx, &mut stmts);
-        stmts.push(Rc::new(CStmt::Expr(final_expr)));
+            stmts.push(Rc::new(CStmt::While {
+                cond: CExpr::Ident("true".into()),
+                body: Rc::new(CStmt::Block(body_stmts)),
+            }));
+        } else {
+            let final_expr = do_codegen_internal(&self.body, ctx, &mut stmts)?;
+            stmts.push(Rc::new(CStmt::Expr(final_expr)));
+        }
 
         stmts.push(Rc::new(CStmt::Expr(CExpr::MacroCall {
             name: "__builtin_unreachable".into(),
@@ -255,40 +401,209 @@ impl LiftedLambda {
         };
 
         ctx.add_decl(fun);
+
+        Ok(())
     }
 }
 
+#[allow(clippy::type_complexity)]
 pub fn do_codegen(
     e: LExpr,
     lambdas: &HashMap<usize, LiftedLambda>,
-) -> (
-    Vec<Rc<CStmt<'static>>>,
-    Vec<CDecl<'static>>,
-    Vec<CDecl<'static>>,
-) {
-    let mut ctx = CodegenCtx::new(lambdas);
+    source: &SourceFile,
+) -> Result<
+    (
+        Vec<Rc<CStmt<'static>>>,
+        Vec<CDecl<'static>>,
+        Vec<CDecl<'static>>,
+    ),
+    CompileError,
+> {
+    let mut ctx = CodegenCtx::new(lambdas, source);
     let mut stmts = Vec::new();
 
     for lambda in lambdas.values() {
         ctx.add_proto(lambda.env_struct());
-        lambda.generate_func(&mut ctx);
+        lambda.generate_func(&mut ctx)?;
     }
 
-    let final_expr = do_codegen_internal(&e, &mut ctx, &mut stmts);
+    // the top level has no environment of its own, one is made for it if it
+    // binds anything
+    let locals = e.locals();
+    if !locals.is_empty() {
+        ctx.add_proto(env_struct("env_main".to_owned(), locals.iter()));
+
+        let env_name = ctx.gen_var();
+        stmts.push(Rc::new(CStmt::Expr(CExpr::MacroCall {
+            name: "OBJECT_ENV_OBJ_NEW".into(),
+            args: vec![
+                Rc::new(CExpr::Ident(env_name.to_owned().into())),
+                Rc::new(CType::Struct("env_main".into())),
+            ],
+        })));
+        stmts.push(Rc::new(CStmt::Decl(CDecl::Var {
+            name: "env".into(),
+            typ: CType::Ptr(Rc::new(CType::Struct("env_main".into()))),
+            init: Some(CExpr::Cast {
+                ex: Rc::new(CExpr::PreUnOp {
+                    op: "&".into(),
+                    ex: Rc::new(CExpr::Arrow {
+                        expr: Rc::new(CExpr::Ident(env_name.into())),
+                        attr: "env".into(),
+                    }),
+                }),
+                typ: CType::Ptr(Rc::new(CType::Struct("env_main".into()))),
+            }),
+        })));
+    }
+
+    let final_expr = do_codegen_internal(&e, &mut ctx, &mut stmts)?;
     stmts.push(Rc::new(CStmt::Expr(final_expr)));
 
-    (stmts, ctx.protos, ctx.declarations)
+    let table = ctx.lambda_infos_table()?;
+    ctx.add_proto(table);
+
+    Ok((stmts, ctx.protos, ctx.declarations))
+}
+
+/// An environment struct with a cell for each of `vars`
+fn env_struct<'v>(name: String, vars: impl Iterator<Item = &'v FreeVar<String>>) -> CDecl<'static> {
+    let members = vars
+        .map(|v| (name_for_free_var(v).into(), object_type()))
+        .collect();
+
+    CDecl::Struct {
+        name: name.into(),
+        members,
+    }
+}
+
+fn resolve_free_var(v: &moniker::Var<String>) -> Result<&FreeVar<String>, CompileError> {
+    match v {
+        moniker::Var::Free(f) => Ok(f),
+        moniker::Var::Bound(_) => Err(CompileError::Internal(format!("bound var: {:?}", v))),
+    }
+}
+
+fn resolve_var(v: &moniker::Var<String>) -> Result<String, CompileError> {
+    resolve_free_var(v).map(name_for_free_var)
+}
+
+/// The value held in the cell of a variable in the current environment
+fn cell_value(v: &moniker::Var<String>) -> Result<CExpr<'static>, CompileError> {
+    let resolved_name = resolve_var(v)?;
+
+    Ok(CExpr::Arrow {
+        expr: Rc::new(CExpr::Cast {
+            typ: CType::Ptr(Rc::new(CType::Struct("cell_obj".into()))),
+            ex: Rc::new(CExpr::Arrow {
+                expr: Rc::new(CExpr::Ident("env".into())),
+                attr: resolved_name.into(),
+            }),
+        }),
+        attr: "val".into(),
+    })
+}
+
+/// Put `value` in a new cell and store it as `var` in `env`
+fn bind_cell(
+    env: &Rc<CExpr<'static>>,
+    var: &FreeVar<String>,
+    value: Rc<CExpr<'static>>,
+    ctx: &mut CodegenCtx,
+    supporting_stmts: &mut Vec<Rc<CStmt<'static>>>,
+) {
+    let tmp_name = ctx.gen_var();
+    let tmp_var = Rc::new(CExpr::Ident(tmp_name.into()));
+
+    supporting_stmts.push(Rc::new(CStmt::Expr(CExpr::MacroCall {
+        name: "OBJECT_CELL_OBJ_NEW".into(),
+        args: vec![tmp_var.clone(), value],
+    })));
+
+    supporting_stmts.push(Rc::new(CStmt::Expr(CExpr::BinOp {
+        op: "=".into(),
+        left: Rc::new(CExpr::Arrow {
+            expr: env.clone(),
+            attr: name_for_free_var(var).into(),
+        }),
+        right: tmp_var,
+    })));
+}
+
+/// How a builtin called directly gives its result
+enum DirectResult {
+    /// An object of the given type, returned by value
+    Value(CType<'static>),
+    /// A pointer to an object
+    Pointer,
+}
+
+/// The arity, runtime function and result of each builtin that always returns
+/// straight away and never calls a procedure, so can be called directly
+fn direct_builtin(ident: &str) -> Option<(usize, &'static str, DirectResult)> {
+    use DirectResult::*;
+
+    let num = || Value(CType::Union("num_obj".into()));
+    let int = || Value(CType::Struct("int_obj".into()));
+    let bool = || Value(CType::Struct("bool_obj".into()));
+
+    let builtin = match ident {
+        "+" => (2, "object_num_obj_add", num()),
+        "-" => (2, "object_num_obj_sub", num()),
+        "*" => (2, "object_num_obj_mul", num()),
+        "/" => (2, "object_num_obj_div", num()),
+        "%" => (2, "object_int_obj_mod", int()),
+        "^" => (2, "object_int_obj_xor", int()),
+        "<" => (2, "object_num_obj_lt", bool()),
+        "<=" => (2, "object_num_obj_leq", bool()),
+        ">" => (2, "object_num_obj_gt", bool()),
+        ">=" => (2, "object_num_obj_geq", bool()),
+        "cons" => (
+            2,
+            "object_cons_obj_new",
+            Value(CType::Struct("cons_obj".into())),
+        ),
+        "cons?" => (1, "is_cons_inner", bool()),
+        "null?" => (1, "is_null_inner", bool()),
+        "car" => (1, "car_inner", Pointer),
+        "cdr" => (1, "cdr_inner", Pointer),
+        "ht-set!" => (3, "ht_set_inner", Pointer),
+        "ht-get" => (2, "ht_get_inner", Pointer),
+        "ht-del!" => (2, "ht_del_inner", bool()),
+        "eq?" => (2, "eq_inner", bool()),
+        "char?" => (1, "is_char_inner", bool()),
+        "char->integer" => (1, "char_to_integer_inner", int()),
+        "integer->char" => (
+            1,
+            "integer_to_char_inner",
+            Value(CType::Struct("char_obj".into())),
+        ),
+        "char=?" => (2, "char_eq_inner", bool()),
+        "symbol?" => (1, "is_symbol_inner", bool()),
+        "display" => (1, "display_inner", Pointer),
+        _ => return None,
+    };
+
+    Some(builtin)
+}
+
+/// Whether a call of the builtin `ident` with `nargs` arguments can be made
+/// directly, a call with the wrong number of arguments goes through the
+/// closure so the arity error is raised as usual
+pub fn is_direct_builtin(ident: &str, nargs: usize) -> bool {
+    matches!(direct_builtin(ident), Some((arity, _, _)) if arity == nargs)
 }
 
 fn builtin_ident_codegen(
     ident: &str,
     ctx: &mut CodegenCtx,
     supporting_stmts: &mut Vec<Rc<CStmt<'static>>>,
-) -> CExpr<'static> {
-    let (num_params, runtime_name) = match ident {
-        "tostring" => (2, "to_string_k"), // these are two-param because they take the cont param
-        "display" => (2, "display_k"),
-        "exit" => (1, "exit_k"),
+) -> Result<CExpr<'static>, CompileError> {
+    let (arity, runtime_name) = match ident {
+        "tostring" => (1, "to_string_k"),
+        "display" => (1, "display_k"),
+        "exit" => (1, "exit_k"), // this is a continuation, so has no continuation of its own
         "+" => (2, "add_k"),
         "-" => (2, "sub_k"),
         "*" => (2, "mul_k"),
@@ -300,128 +615,133 @@ fn builtin_ident_codegen(
         ">" => (2, "gt_k"),
         ">=" => (2, "geq_k"),
         "cons" => (2, "cons_k"),
-        "cons?" => (2, "is_cons_k"),
-        "null?" => (2, "is_null_k"),
-        "car" => (2, "car_k"),
-        "cdr" => (2, "cdr_k"),
+        "cons?" => (1, "is_cons_k"),
+        "null?" => (1, "is_null_k"),
+        "car" => (1, "car_k"),
+        "cdr" => (1, "cdr_k"),
         "string-concat" => (2, "string_concat_k"),
-        "string-chars" => (2, "string_chars_k"),
-        "ht-new" => (2, "ht_new_k"),
-        "ht-set!" => (2, "ht_set_k"),
+        "string-chars" => (1, "string_chars_k"),
+        "ht-new" => (0, "ht_new_k"),
+        "ht-set!" => (3, "ht_set_k"),
         "ht-get" => (2, "ht_get_k"),
         "ht-del!" => (2, "ht_del_k"),
-        "ht-keys" => (2, "ht_keys_k"),
+        "ht-keys" => (1, "ht_keys_k"),
         "eq?" => (2, "eq_k"),
-        _ => panic!("unknown builtin: {}", ident),
-    };
-
-    let init_name = match num_params {
-        1 => "OBJECT_CLOSURE_ONE_NEW",
-        2 => "OBJECT_CLOSURE_TWO_NEW",
-        n => panic!("closure was not one or two parameters, was: {}", n),
+        "append" => (2, "append_k"),
+        "char?" => (1, "is_char_k"),
+        "char->integer" => (1, "char_to_integer_k"),
+        "integer->char" => (1, "integer_to_char_k"),
+        "char=?" => (2, "char_eq_k"),
+        "symbol?" => (1, "is_symbol_k"),
+        "symbol->string" => (1, "symbol_to_string_k"),
+        "string->symbol" => (1, "string_to_symbol_k"),
+        "call/cc" | "call-with-current-continuation" => (1, "call_cc_k"),
+        "dynamic-wind" => (3, "dynamic_wind_k"),
+        "with-exception-handler" => (2, "with_exception_handler_k"),
+        "raise" => (1, "raise_k"),
+        "raise-continuable" => (1, "raise_continuable_k"),
+        "error" => (1, "error_k"),
+        "error-object?" => (1, "is_error_object_k"),
+        "error-object-message" => (1, "error_object_message_k"),
+        "error-object-irritants" => (1, "error_object_irritants_k"),
+        _ => {
+            return Err(CompileError::UnknownBuiltin {
+                name: ident.to_owned(),
+            })
+        }
     };
 
     let var_name = ctx.gen_var();
+    // `error` takes any number of irritants after its message
+    let variadic = ident == "error";
+    let info_index = ctx.builtin_info_index(ident, arity, variadic);
 
     let init_stmt = CStmt::Expr(CExpr::MacroCall {
-        name: init_name.into(),
+        name: "OBJECT_CLOSURE_NEW".into(),
         args: vec![
             Rc::new(CExpr::Ident(var_name.to_owned().into())),
             Rc::new(CExpr::Ident(runtime_name.into())),
             Rc::new(CExpr::Ident("NULL".into())),
+            Rc::new(lambda_info_ref(info_index)),
         ],
     });
 
     supporting_stmts.push(Rc::new(init_stmt));
 
-    CExpr::Ident(var_name.into())
+    Ok(CExpr::Ident(var_name.into()))
 }
 
 fn do_codegen_internal(
     e: &LExpr,
     ctx: &mut CodegenCtx,
     supporting_stmts: &mut Vec<Rc<CStmt<'static>>>,
-) -> CExpr<'static> {
-    match e {
-        LExpr::Var(v) => {
-            let resolved_name = match v {
-                moniker::Var::Free(f) => name_for_free_var(f),
-                moniker::Var::Bound(_) => panic!("bound var: {:?}", v),
-            };
-            CExpr::Arrow {
-                expr: Rc::new(CExpr::Cast {
-                    typ: CType::Ptr(Rc::new(CType::Struct("cell_obj".into()))),
-                    ex: Rc::new(CExpr::Arrow {
-                        expr: Rc::new(CExpr::Ident("env".into())),
-                        attr: resolved_name.into(),
-                    }),
-                }),
-                attr: "val".into(),
-            }
-        }
+) -> Result<CExpr<'static>, CompileError> {
+    let expr = match e {
+        LExpr::Var(v) => cell_value(v)?,
         LExpr::Lit(Ignore(l)) => {
-            let (ctor_name, expr) = match l {
-                Literal::String(s) => ("OBJECT_STRING_OBJ_NEW", CExpr::LitStr(s.to_owned().into())),
-                Literal::Int(i) => ("OBJECT_INT_OBJ_NEW", CExpr::LitIInt(*i as isize)),
-                Literal::Float(_f) => panic!("not yet"),
-                Literal::Void => return CExpr::Ident("NULL".into()),
+            let (ctor_name, exprs) = match l {
+                // strings can contain NULs, so pass the length along
+                Literal::String(s) => (
+                    "OBJECT_STRING_OBJ_NEW_LEN",
+                    vec![CExpr::LitStr(s.to_owned().into()), CExpr::LitUInt(s.len())],
+                ),
+                Literal::Int(i) => ("OBJECT_INT_OBJ_NEW", vec![CExpr::LitIInt(*i as isize)]),
+                Literal::Bool(b) => ("OBJECT_BOOL_OBJ_NEW", vec![CExpr::LitIInt(*b as isize)]),
+                Literal::Float(f) => ("OBJECT_FLOAT_OBJ_NEW", vec![CExpr::LitFloat(*f)]),
+                Literal::Char(c) => ("OBJECT_CHAR_OBJ_NEW", vec![CExpr::LitUInt(*c as usize)]),
+                Literal::Symbol(s) => (
+                    "OBJECT_SYMBOL_OBJ_NEW",
+                    vec![CExpr::LitStr(s.to_owned().into()), CExpr::LitUInt(s.len())],
+                ),
+                Literal::Void => return Ok(CExpr::Ident("NULL".into())),
             };
 
             let dest = ctx.gen_var();
 
+            let mut args: Vec<Rc<dyn ToCDC>> = vec![Rc::new(CExpr::Ident(dest.to_owned().into()))];
+            args.extend(exprs.into_iter().map(|e| Rc::new(e) as Rc<dyn ToCDC>));
+
             let init_stmt = CStmt::Expr(CExpr::MacroCall {
                 name: ctor_name.to_owned().into(),
-                args: vec![Rc::new(CExpr::Ident(dest.to_owned().into())), Rc::new(expr)],
+                args,
             });
 
             supporting_stmts.push(Rc::new(init_stmt));
 
             CExpr::Ident(dest.into())
         }
-        LExpr::BuiltinIdent(Ignore(i)) => builtin_ident_codegen(i.as_ref(), ctx, supporting_stmts),
+        LExpr::BuiltinIdent(Ignore(i)) => builtin_ident_codegen(i.as_ref(), ctx, supporting_stmts)?,
         LExpr::SetThen(v, e, c) => {
-            let e_expr = do_codegen_internal(e, ctx, supporting_stmts);
-            let resolved_name = match v {
-                moniker::Var::Free(f) => name_for_free_var(f),
-                moniker::Var::Bound(_) => panic!("bound var: {:?}", v),
-            };
-            let var_exp = CExpr::Arrow {
-                expr: Rc::new(CExpr::Cast {
-                    typ: CType::Ptr(Rc::new(CType::Struct("cell_obj".into()))),
-                    ex: Rc::new(CExpr::Arrow {
-                        expr: Rc::new(CExpr::Ident("env".into())),
-                        attr: resolved_name.into(),
-                    }),
-                }),
-                attr: "val".into(),
-            };
+            let e_expr = do_codegen_internal(e, ctx, supporting_stmts)?;
 
             supporting_stmts.push(Rc::new(CStmt::Expr(CExpr::BinOp {
                 op: "=".into(),
-                left: Rc::new(var_exp),
+                left: Rc::new(cell_value(v)?),
                 right: Rc::new(e_expr),
             })));
 
-            do_codegen_internal(c, ctx, supporting_stmts)
+            do_codegen_internal(c, ctx, supporting_stmts)?
         }
         LExpr::Lifted(Ignore(id)) => {
-            let lambda = ctx.lambdas.get(id).unwrap();
+            let lambda = ctx.lambdas.get(id).ok_or_else(|| {
+                CompileError::Internal(format!("reference to unknown lambda {}", id))
+            })?;
             let env_expr = Rc::new(CExpr::Ident("env".into()));
-            lambda.generate_closure(&env_expr, ctx, supporting_stmts)
+            lambda.generate_closure(&env_expr, ctx, supporting_stmts)?
         }
         LExpr::If(c, ift, iff) => {
             let mut ift_stmts = Vec::new();
-            let ift = do_codegen_internal(ift, ctx, &mut ift_stmts);
+            let ift = do_codegen_internal(ift, ctx, &mut ift_stmts)?;
             ift_stmts.push(Rc::new(CStmt::Expr(ift)));
 
             let mut iff_stmts = Vec::new();
-            let iff = do_codegen_internal(iff, ctx, &mut iff_stmts);
+            let iff = do_codegen_internal(iff, ctx, &mut iff_stmts)?;
             iff_stmts.push(Rc::new(CStmt::Expr(iff)));
 
             let stmt = CStmt::If {
                 cond: CExpr::MacroCall {
                     name: "obj_is_truthy".into(),
-                    args: vec![Rc::new(do_codegen_internal(c, ctx, supporting_stmts))],
+                    args: vec![Rc::new(do_codegen_internal(c, ctx, supporting_stmts)?)],
                 },
                 ift: Rc::new(CStmt::Block(ift_stmts)),
                 iff: Rc::new(CStmt::Block(iff_stmts)),
@@ -434,17 +754,177 @@ fn do_codegen_internal(
         LExpr::CallOne(c, a) => CExpr::MacroCall {
             name: "call_closure_one".into(),
             args: vec![
-                Rc::new(do_codegen_internal(c, ctx, supporting_stmts)),
-                Rc::new(do_codegen_internal(a, ctx, supporting_stmts)),
+                Rc::new(do_codegen_internal(c, ctx, supporting_stmts)?),
+                Rc::new(do_codegen_internal(a, ctx, supporting_stmts)?),
             ],
         },
-        LExpr::CallTwo(c, a, k) => CExpr::MacroCall {
-            name: "call_closure_two".into(),
-            args: vec![
-                Rc::new(do_codegen_internal(c, ctx, supporting_stmts)),
-                Rc::new(do_codegen_internal(a, ctx, supporting_stmts)),
-                Rc::new(do_codegen_internal(k, ctx, supporting_stmts)),
-            ],
-        },
-    }
+        LExpr::Call(c, args, k, Ignore(site)) => {
+            let f = do_codegen_internal(c, ctx, supporting_stmts)?;
+            let args = args
+                .iter()
+                .map(|a| do_codegen_internal(a, ctx, supporting_stmts))
+                .collect::<Result<Vec<_>, _>>()?;
+            let num_args = args.len();
+
+            let args_array = match num_args {
+                0 => CExpr::Ident("NULL".into()),
+                n => {
+                    let args_name = ctx.gen_var();
+
+                    supporting_stmts.push(Rc::new(CStmt::Decl(CDecl::Var {
+                        name: args_name.to_owned().into(),
+                        typ: CType::Arr(Rc::new(object_type()), Some(n)),
+                        init: Some(CExpr::InitList(args)),
+                    })));
+
+                    CExpr::Ident(args_name.into())
+                }
+            };
+
+            CExpr::MacroCall {
+                name: "call_closure".into(),
+                args: vec![
+                    Rc::new(f),
+                    Rc::new(CExpr::LitUInt(num_args)),
+                    Rc::new(args_array),
+                    Rc::new(do_codegen_internal(k, ctx, supporting_stmts)?),
+                    Rc::new(CExpr::LitStr(ctx.source.location(*site).into())),
+                ],
+            }
+        }
+        LExpr::Loop(Ignore(id), name, bindings) => {
+            // the environment and cells of the loop are made once here, each
+            // iteration just updates the cells and enters the loop again
+            let lambda = ctx.lambdas.get(id).ok_or_else(|| {
+                CompileError::Internal(format!("reference to unknown loop {}", id))
+            })?;
+
+            let name = resolve_free_var(name)?;
+            let bound = std::iter::once(Ok(name.clone()))
+                .chain(bindings.iter().map(|(v, _)| resolve_free_var(v).cloned()))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let current_env = Rc::new(CExpr::Ident("env".into()));
+            let env_expr = lambda.make_env_code(&current_env, &bound, ctx, supporting_stmts);
+            let closure = Rc::new(lambda.closure_code(env_expr.clone(), ctx, supporting_stmts)?);
+            let env_access = Rc::new(lambda.generate_env_cast(env_expr));
+
+            let mut values = vec![closure.clone()];
+            for (_, e) in bindings {
+                values.push(Rc::new(do_codegen_internal(e, ctx, supporting_stmts)?));
+            }
+
+            for (var, value) in bound.iter().zip(values) {
+                if lambda.freevars.contains(var) {
+                    bind_cell(&env_access, var, value, ctx, supporting_stmts);
+                }
+            }
+
+            CExpr::MacroCall {
+                name: "call_closure_one".into(),
+                args: vec![closure, Rc::new(CExpr::Ident("NULL".into()))],
+            }
+        }
+        LExpr::Recur(name, vars, args) => {
+            // every new value is read before any are assigned, as the steps can
+            // refer to each of the loop's variables
+            let mut tmps = Vec::new();
+            for e in args {
+                let value = do_codegen_internal(e, ctx, supporting_stmts)?;
+                let tmp_name = ctx.gen_var();
+
+                supporting_stmts.push(Rc::new(CStmt::Decl(CDecl::Var {
+                    name: tmp_name.to_owned().into(),
+                    typ: object_type(),
+                    init: Some(value),
+                })));
+
+                tmps.push(CExpr::Ident(tmp_name.into()));
+            }
+
+            for (var, tmp) in vars.iter().zip(tmps) {
+                supporting_stmts.push(Rc::new(CStmt::Expr(CExpr::BinOp {
+                    op: "=".into(),
+                    left: Rc::new(cell_value(var)?),
+                    right: Rc::new(tmp),
+                })));
+            }
+
+            if Some(resolve_free_var(name)?) == ctx.current_loop.as_ref() {
+                // the stack is only cleared by a gc, which needs the loop to be
+                // entered again through its closure
+                supporting_stmts.push(Rc::new(CStmt::If {
+                    cond: CExpr::FunCallOp {
+                        expr: Rc::new(CExpr::Ident("stack_check".into())),
+                        params: vec![],
+                    },
+                    ift: Rc::new(CStmt::Continue),
+                    iff: Rc::new(CStmt::Block(vec![])),
+                }));
+            }
+
+            CExpr::MacroCall {
+                name: "call_closure_one".into(),
+                args: vec![
+                    Rc::new(cell_value(name)?),
+                    Rc::new(CExpr::Ident("NULL".into())),
+                ],
+            }
+        }
+        LExpr::BuiltinCall(Ignore(i), args) => {
+            let (_, runtime_name, result) = direct_builtin(i)
+                .ok_or_else(|| CompileError::Internal(format!("{} can't be called directly", i)))?;
+            let args = args
+                .iter()
+                .map(|a| Ok(Rc::new(do_codegen_internal(a, ctx, supporting_stmts)?)))
+                .collect::<Result<Vec<_>, CompileError>>()?;
+            let call = CExpr::FunCallOp {
+                expr: Rc::new(CExpr::Ident(runtime_name.into())),
+                params: args,
+            };
+            let dest = ctx.gen_var();
+
+            let stmt = match result {
+                DirectResult::Value(typ) => CStmt::Expr(CExpr::MacroCall {
+                    name: "OBJECT_BUILTIN_RESULT_NEW".into(),
+                    args: vec![
+                        Rc::new(CExpr::Ident(dest.to_owned().into())),
+                        Rc::new(typ),
+                        Rc::new(call),
+                    ],
+                }),
+                DirectResult::Pointer => CStmt::Decl(CDecl::Var {
+                    name: dest.to_owned().into(),
+                    typ: object_type(),
+                    init: Some(call),
+                }),
+            };
+
+            // the call is made here rather than where its result is used, so
+            // calls happen in the order they were written
+            supporting_stmts.push(Rc::new(stmt));
+
+            CExpr::Ident(dest.into())
+        }
+        LExpr::Let(bindings, body) => {
+            let env_expr = Rc::new(CExpr::Ident("env".into()));
+
+            let mut values = Vec::new();
+            for (v, e) in bindings {
+                let value = Rc::new(do_codegen_internal(e, ctx, supporting_stmts)?);
+
+                if let Some(v) = v {
+                    values.push((resolve_free_var(v)?, value));
+                }
+            }
+
+            for (v, value) in values {
+                bind_cell(&env_expr, v, value, ctx, supporting_stmts);
+            }
+
+            do_codegen_internal(body, ctx, supporting_stmts)?
+        }
+    };
+
+    Ok(expr)
 }
@@ -6,15 +6,33 @@ use termcolor::{Color, ColorSpec, WriteColor};
 
 use std::{io::Result, rc::Rc};
 
-use crate::{expr::Expr, flat_expr::FExpr, literals::Literal, utils::clone_rc};
+use crate::{
+    codegen::is_direct_builtin,
+    diagnostics::Span,
+    expr::{Expr, LamInfo},
+    flat_expr::FExpr,
+    literals::Literal,
+    utils::clone_rc,
+};
+
+/// The name, continuation and variables of a loop
+pub type LoopPattern = (Binder<String>, Binder<String>, Vec<Binder<String>>);
+
+/// The parameters of a lambda, the parameter taking the rest of the arguments
+/// if it is variadic, and the continuation
+pub type LamPattern = (Vec<Binder<String>>, Option<Binder<String>>, Binder<String>);
 
 #[derive(Debug, Clone, BoundTerm)]
 pub enum AExp {
-    Lam2(Scope<Binder<String>, Scope<Binder<String>, Rc<CExp>>>),
+    Lam(Scope<LamPattern, Rc<CExp>>, Ignore<LamInfo>),
+    /// A continuation, which takes just the value returned to it
     Lam1(Scope<Binder<String>, Rc<CExp>>),
     Var(Var<String>),
     BuiltinIdent(Ignore<String>),
     Lit(Ignore<Literal>),
+    /// A call of a builtin that returns straight away without calling any
+    /// procedure, so it needs no continuation
+    BuiltinCall(Ignore<String>, Vec<Rc<AExp>>),
 }
 
 impl AExp {
@@ -24,26 +42,16 @@ impl AExp {
         D::Doc: Clone,
     {
         match self {
-            AExp::Lam2(s) => {
+            AExp::Lam1(s) => {
                 let Scope {
                     unsafe_pattern: pat,
-                    unsafe_body:
-                        Scope {
-                            unsafe_pattern: cont,
-                            unsafe_body: body,
-                        },
+                    unsafe_body: body,
                 } = &s;
 
                 let pat_pret = allocator
                     .as_string(pat)
                     .annotate(ColorSpec::new().set_fg(Some(Color::Green)).clone());
-                let cont_pret = allocator
-                    .as_string(cont)
-                    .annotate(ColorSpec::new().set_fg(Some(Color::Red)).clone());
-                let args_pret = pat_pret
-                    .append(allocator.space())
-                    .append(cont_pret)
-                    .parens();
+                let args_pret = pat_pret.append(allocator.space()).parens();
                 let body_pret = allocator.line_().append(body.pretty(allocator));
 
                 allocator
@@ -57,16 +65,30 @@ impl AExp {
                     .group()
                     .parens()
             }
-            AExp::Lam1(s) => {
+            AExp::Lam(s, _) => {
                 let Scope {
-                    unsafe_pattern: pat,
+                    unsafe_pattern: (params, rest, cont),
                     unsafe_body: body,
                 } = &s;
 
-                let pat_pret = allocator
-                    .as_string(pat)
-                    .annotate(ColorSpec::new().set_fg(Some(Color::Green)).clone());
-                let args_pret = pat_pret.append(allocator.space()).parens();
+                let param_pret = |p: &Binder<String>, color| {
+                    allocator
+                        .as_string(p)
+                        .annotate(ColorSpec::new().set_fg(Some(color)).clone())
+                };
+                let rest_pret = rest
+                    .iter()
+                    .flat_map(|r| vec![allocator.text("."), param_pret(r, Color::Green)]);
+                let args_pret = allocator
+                    .intersperse(
+                        params
+                            .iter()
+                            .map(|p| param_pret(p, Color::Green))
+                            .chain(rest_pret)
+                            .chain(std::iter::once(param_pret(cont, Color::Red))),
+                        allocator.space(),
+                    )
+                    .parens();
                 let body_pret = allocator.line_().append(body.pretty(allocator));
 
                 allocator
@@ -83,28 +105,43 @@ impl AExp {
             AExp::Var(s) => allocator.as_string(s),
             AExp::BuiltinIdent(Ignore(i)) => allocator.as_string(i),
             AExp::Lit(Ignore(l)) => l.pretty(allocator),
+            AExp::BuiltinCall(Ignore(i), args) => {
+                let args_pret = allocator.concat(
+                    args.iter()
+                        .map(|e| allocator.space().append(e.pretty(allocator))),
+                );
+
+                allocator
+                    .as_string(i)
+                    .annotate(ColorSpec::new().set_fg(Some(Color::Blue)).clone())
+                    .append(args_pret)
+                    .group()
+                    .parens()
+            }
         }
     }
 
     pub fn into_fexpr(self) -> FExpr {
         match self {
-            AExp::Lam2(s) => {
+            AExp::Lam1(s) => {
                 let (pat, body) = s.unbind();
-                let (cont, body) = body.unbind();
 
-                FExpr::LamTwo(Scope::new(
-                    pat,
-                    Scope::new(cont, Rc::new(clone_rc(body).into_fexpr())),
-                ))
+                FExpr::LamOne(Scope::new(pat, Rc::new(clone_rc(body).into_fexpr())))
             }
-            AExp::Lam1(s) => {
+            AExp::Lam(s, info) => {
                 let (pat, body) = s.unbind();
 
-                FExpr::LamOne(Scope::new(pat, Rc::new(clone_rc(body).into_fexpr())))
+                FExpr::Lam(Scope::new(pat, Rc::new(clone_rc(body).into_fexpr())), info)
             }
             AExp::BuiltinIdent(s) => FExpr::BuiltinIdent(s),
             AExp::Var(s) => FExpr::Var(s),
             AExp::Lit(l) => FExpr::Lit(l),
+            AExp::BuiltinCall(s, args) => FExpr::BuiltinCall(
+                s,
+                args.into_iter()
+                    .map(|e| Rc::new(clone_rc(e).into_fexpr()))
+                    .collect(),
+            ),
         }
     }
 }
@@ -113,8 +150,18 @@ impl AExp {
 pub enum CExp {
     If(Rc<AExp>, Rc<CExp>, Rc<CExp>),
     SetThen(Var<String>, Rc<AExp>, Rc<CExp>),
+    /// Return a value to a continuation
     Call1(Rc<AExp>, Rc<AExp>),
-    Call2(Rc<AExp>, Rc<AExp>, Rc<AExp>),
+    /// Call a function with its arguments and a continuation, the span is the
+    /// call site
+    Call(Rc<AExp>, Vec<Rc<AExp>>, Rc<AExp>, Ignore<Span>),
+    /// The inits of the loop's variables, the continuation of the loop, then
+    /// the loop's name, continuation and variables
+    Loop(Vec<Rc<AExp>>, Rc<AExp>, Scope<LoopPattern, Rc<CExp>>),
+    Recur(Var<String>, Vec<Var<String>>, Vec<Rc<AExp>>),
+    /// Bind each variable to its value, a lambda applied where it's written
+    /// doesn't need a closure of its own
+    Let(Vec<Rc<AExp>>, Scope<Vec<Binder<String>>, Rc<CExp>>),
 }
 
 impl CExp {
@@ -162,15 +209,17 @@ impl CExp {
                     .parens()
             }
 
-            CExp::Call2(f, v, c) => {
+            CExp::Call(f, args, c, _) => {
                 let f_pret = f.pretty(allocator);
-                let v_pret = v.pretty(allocator);
+                let args_pret = allocator.concat(
+                    args.iter()
+                        .map(|e| allocator.space().append(e.pretty(allocator))),
+                );
                 let c_pret = c.pretty(allocator);
 
                 f_pret
                     .annotate(ColorSpec::new().set_fg(Some(Color::Blue)).clone())
-                    .append(allocator.space())
-                    .append(v_pret)
+                    .append(args_pret)
                     .append(allocator.space())
                     .append(c_pret)
                     .group()
@@ -188,6 +237,92 @@ impl CExp {
                     .group()
                     .parens()
             }
+
+            CExp::Loop(inits, cont, s) => {
+                let Scope {
+                    unsafe_pattern: (name, k, vars),
+                    unsafe_body: body,
+                } = &s;
+
+                let binding = |v: &Binder<String>, e: &Rc<AExp>, color| {
+                    allocator
+                        .as_string(v)
+                        .annotate(ColorSpec::new().set_fg(Some(color)).clone())
+                        .append(allocator.space())
+                        .append(e.pretty(allocator))
+                        .group()
+                        .parens()
+                };
+                let bindings_pret = allocator
+                    .intersperse(
+                        std::iter::once(binding(k, cont, Color::Red)).chain(
+                            vars.iter()
+                                .zip(inits)
+                                .map(|(v, e)| binding(v, e, Color::Green)),
+                        ),
+                        allocator.space(),
+                    )
+                    .parens();
+
+                allocator
+                    .text("loop")
+                    .annotate(ColorSpec::new().set_fg(Some(Color::Magenta)).clone())
+                    .append(allocator.space())
+                    .append(allocator.as_string(name))
+                    .append(allocator.space())
+                    .append(bindings_pret)
+                    .append(allocator.line())
+                    .append(body.pretty(allocator))
+                    .nest(1)
+                    .group()
+                    .parens()
+            }
+
+            CExp::Recur(name, _, args) => allocator
+                .text("recur")
+                .annotate(ColorSpec::new().set_fg(Some(Color::Magenta)).clone())
+                .append(allocator.space())
+                .append(allocator.as_string(name))
+                .append(allocator.space())
+                .append(
+                    allocator
+                        .intersperse(args.iter().map(|e| e.pretty(allocator)), allocator.space()),
+                )
+                .group()
+                .parens(),
+
+            CExp::Let(values, s) => {
+                let Scope {
+                    unsafe_pattern: vars,
+                    unsafe_body: body,
+                } = &s;
+
+                let bindings_pret = allocator
+                    .intersperse(
+                        vars.iter().zip(values).map(|(v, e)| {
+                            allocator
+                                .as_string(v)
+                                .annotate(ColorSpec::new().set_fg(Some(Color::Green)).clone())
+                                .append(allocator.space())
+                                .append(e.pretty(allocator))
+                                .group()
+                                .parens()
+                        }),
+                        allocator.space(),
+                    )
+                    .parens();
+
+                allocator
+                    .text("let")
+                    .annotate(ColorSpec::new().set_fg(Some(Color::Magenta)).clone())
+                    .append(allocator.space())
+                    .append(bindings_pret)
+                    .append(allocator.line())
+                    .append(body.pretty(allocator))
+                    .nest(1)
+                    .group()
+                    .parens()
+            }
         }
     }
 
@@ -206,10 +341,13 @@ impl CExp {
                 Rc::new(clone_rc(v).into_fexpr()),
                 Rc::new(clone_rc(c).into_fexpr()),
             ),
-            CExp::Call2(f, v, c) => FExpr::CallTwo(
+            CExp::Call(f, args, c, site) => FExpr::Call(
                 Rc::new(clone_rc(f).into_fexpr()),
-                Rc::new(clone_rc(v).into_fexpr()),
+                args.into_iter()
+                    .map(|e| Rc::new(clone_rc(e).into_fexpr()))
+                    .collect(),
                 Rc::new(clone_rc(c).into_fexpr()),
+                site,
             ),
             CExp::Call1(f, v) => FExpr::CallOne(
                 Rc::new(clone_rc(f).into_fexpr()),
@@ -220,13 +358,43 @@ impl CExp {
                 Rc::new(clone_rc(ift).into_fexpr()),
                 Rc::new(clone_rc(iff).into_fexpr()),
             ),
+            CExp::Loop(inits, cont, s) => {
+                let (pat, body) = s.unbind();
+
+                FExpr::Loop(
+                    inits
+                        .into_iter()
+                        .map(|e| Rc::new(clone_rc(e).into_fexpr()))
+                        .collect(),
+                    Rc::new(clone_rc(cont).into_fexpr()),
+                    Scope::new(pat, Rc::new(clone_rc(body).into_fexpr())),
+                )
+            }
+            CExp::Recur(n, vars, args) => FExpr::Recur(
+                n,
+                vars,
+                args.into_iter()
+                    .map(|e| Rc::new(clone_rc(e).into_fexpr()))
+                    .collect(),
+            ),
+            CExp::Let(values, s) => {
+                let (vars, body) = s.unbind();
+
+                FExpr::Let(
+                    values
+                        .into_iter()
+                        .map(|e| Rc::new(clone_rc(e).into_fexpr()))
+                        .collect(),
+                    Scope::new(vars, Rc::new(clone_rc(body).into_fexpr())),
+                )
+            }
         }
     }
 }
 
 fn t_k(expr: Expr, fk: &dyn Fn(Rc<AExp>) -> CExp) -> CExp {
     match expr {
-        Expr::Lam(_) | Expr::Var(_) | Expr::Lit(_) | Expr::BuiltinIdent(_) => fk(Rc::new(m(expr))),
+        Expr::Lam(..) | Expr::Var(_) | Expr::Lit(_) | Expr::BuiltinIdent(_) => fk(Rc::new(m(expr))),
         Expr::Set(n, e) => t_k(clone_rc(e), &|e| {
             CExp::SetThen(
                 n.clone(),
@@ -248,25 +416,93 @@ fn t_k(expr: Expr, fk: &dyn Fn(Rc<AExp>) -> CExp) -> CExp {
                 )
             })
         }
-        Expr::App(f, e) => {
+        Expr::App(f, args, site) => match direct_call(&f, &args) {
+            Some(Direct::Builtin(name)) => t_k_all(&args, &|args| {
+                fk(Rc::new(AExp::BuiltinCall(name.clone(), args)))
+            }),
+            Some(Direct::Let(s)) => {
+                let (vars, body) = s.unbind();
+
+                t_k_all(&args, &|values| {
+                    CExp::Let(
+                        values,
+                        Scope::new(vars.clone(), Rc::new(t_k(clone_rc(body.clone()), fk))),
+                    )
+                })
+            }
+            None => {
+                let rv_v = FreeVar::fresh_named("rv");
+                let cont = Rc::new(AExp::Lam1(Scope::new(
+                    Binder(rv_v.clone()),
+                    Rc::new(fk(Rc::new(AExp::Var(Var::Free(rv_v))))),
+                )));
+
+                t_k(clone_rc(f), &|f| {
+                    t_k_all(&args, &|args| {
+                        CExp::Call(f.clone(), args, cont.clone(), site)
+                    })
+                })
+            }
+        },
+        Expr::Loop(..) => {
             let rv_v = FreeVar::fresh_named("rv");
             let cont = Rc::new(AExp::Lam1(Scope::new(
                 Binder(rv_v.clone()),
                 Rc::new(fk(Rc::new(AExp::Var(Var::Free(rv_v))))),
             )));
 
-            t_k(clone_rc(f), &|f| {
-                t_k(clone_rc(e.clone()), &|e| {
-                    CExp::Call2(f.clone(), e, cont.clone())
-                })
-            })
+            t_c(expr, cont)
         }
+        // a recur never returns, so there is nothing to continue with
+        Expr::Recur(..) => t_c(expr, Rc::new(AExp::Lit(Ignore(Literal::Void)))),
     }
 }
 
+/// A call that can be made without passing a continuation
+enum Direct {
+    Builtin(Ignore<String>),
+    /// A lambda applied where it's written, with the right number of
+    /// arguments, is just a binding of its parameters
+    Let(Scope<Vec<Binder<String>>, Rc<Expr>>),
+}
+
+fn direct_call(f: &Expr, args: &[Rc<Expr>]) -> Option<Direct> {
+    match f {
+        Expr::BuiltinIdent(Ignore(name)) if is_direct_builtin(name, args.len()) => {
+            Some(Direct::Builtin(Ignore(name.clone())))
+        }
+        Expr::Lam(s, _) => {
+            let ((params, rest), body) = s.clone().unbind();
+
+            if rest.is_none() && params.len() == args.len() {
+                Some(Direct::Let(Scope::new(params, body)))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Convert each expression in turn, `fk` is given the results of all of them
+fn t_k_all(exprs: &[Rc<Expr>], fk: &dyn Fn(Vec<Rc<AExp>>) -> CExp) -> CExp {
+    fn go(exprs: &[Rc<Expr>], done: Vec<Rc<AExp>>, fk: &dyn Fn(Vec<Rc<AExp>>) -> CExp) -> CExp {
+        match exprs {
+            [] => fk(done),
+            [first, rest @ ..] => t_k(clone_rc(first.clone()), &|e| {
+                let mut done = done.clone();
+                done.push(e);
+                go(rest, done, fk)
+            }),
+        }
+    }
+
+    go(exprs, Vec::new(), fk)
+}
+
 pub fn t_c(expr: Expr, c: Rc<AExp>) -> CExp {
     match expr {
-        e @ Expr::Lam(_) | e @ Expr::Var(_) | e @ Expr::Lit(_) | e @ Expr::BuiltinIdent(_) => {
+        e @ Expr::Lam(..) | e @ Expr::Var(_) | e @ Expr::Lit(_) | e @ Expr::BuiltinIdent(_) => {
             CExp::Call1(c, Rc::new(m(e)))
         }
         Expr::Set(n, e) => t_k(clone_rc(e), &|e| {
@@ -279,6 +515,15 @@ pub fn t_c(expr: Expr, c: Rc<AExp>) -> CExp {
                 )),
             )
         }),
+        // a variable can be given to both branches as it is, anything else is
+        // bound first so it isn't written out twice
+        Expr::If(cond, ift, iff) if matches!(*c, AExp::Var(_)) => t_k(clone_rc(cond), &|cond| {
+            CExp::If(
+                cond,
+                Rc::new(t_c(clone_rc(ift.clone()), c.clone())),
+                Rc::new(t_c(clone_rc(iff.clone()), c.clone())),
+            )
+        }),
         Expr::If(cond, ift, iff) => {
             let k = FreeVar::fresh_named("k");
             CExp::Call1(
@@ -301,21 +546,61 @@ pub fn t_c(expr: Expr, c: Rc<AExp>) -> CExp {
                 c,
             )
         }
-        Expr::App(f, e) => t_k(clone_rc(f), &|f| {
-            t_k(e.as_ref().clone(), &|e| {
-                CExp::Call2(f.clone(), e, c.clone())
+        Expr::App(f, args, site) => match direct_call(&f, &args) {
+            Some(Direct::Builtin(name)) => t_k_all(&args, &|args| {
+                CExp::Call1(c.clone(), Rc::new(AExp::BuiltinCall(name.clone(), args)))
+            }),
+            Some(Direct::Let(s)) => {
+                let (vars, body) = s.unbind();
+
+                t_k_all(&args, &|values| {
+                    CExp::Let(
+                        values,
+                        Scope::new(
+                            vars.clone(),
+                            Rc::new(t_c(clone_rc(body.clone()), c.clone())),
+                        ),
+                    )
+                })
+            }
+            None => t_k(clone_rc(f), &|f| {
+                t_k_all(&args, &|args| CExp::Call(f.clone(), args, c.clone(), site))
+            }),
+        },
+        Expr::Loop(inits, s) => {
+            let ((name, vars), body) = s.unbind();
+            // every iteration continues with the continuation of the loop
+            let k = FreeVar::fresh_named("k");
+            let body = Rc::new(t_c(
+                clone_rc(body),
+                Rc::new(AExp::Var(Var::Free(k.clone()))),
+            ));
+
+            t_k_all(&inits, &|inits| {
+                CExp::Loop(
+                    inits,
+                    c.clone(),
+                    Scope::new(
+                        (name.clone(), Binder(k.clone()), vars.clone()),
+                        body.clone(),
+                    ),
+                )
             })
-        }),
+        }
+        // the continuation is the same as the loop's, so the recur can ignore it
+        Expr::Recur(name, vars, args) => {
+            t_k_all(&args, &|args| CExp::Recur(name.clone(), vars.clone(), args))
+        }
     }
 }
 
 pub fn m(expr: Expr) -> AExp {
     match expr {
-        Expr::Lam(s) => {
-            let (p, t) = s.unbind();
+        Expr::Lam(s, info) => {
+            let ((params, rest), t) = s.unbind();
             let k = FreeVar::fresh_named("k");
             let body = t_c(clone_rc(t), Rc::new(AExp::Var(Var::Free(k.clone()))));
-            AExp::Lam2(Scope::new(p, Scope::new(Binder(k), Rc::new(body))))
+            AExp::Lam(Scope::new((params, rest, Binder(k)), Rc::new(body)), info)
         }
         Expr::Var(v) => AExp::Var(v),
         Expr::BuiltinIdent(v) => AExp::BuiltinIdent(v),
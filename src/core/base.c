@@ -7,63 +7,98 @@
 #include <sys/resource.h>
 
 #include "base.h"
+#include "builtin.h"
 #include "common.h"
 #include "gc.h"
 #include "hash_table.h"
 #include "vec.h"
 
-static bool stack_check(void);
-
 static struct thunk *current_thunk;
+struct obj *wind_stack = NULL;
+struct obj *handler_stack = NULL;
 static void *stack_initial;
 static jmp_buf setjmp_env_buf;
 
-void call_closure_one(struct obj *rator, struct obj *rand) {
-  if (rator->tag != OBJ_CLOSURE) {
-    RUNTIME_ERROR("Called object (%p) was not a closure but was: %d", rator,
-                  rator->tag);
+// Finish an error message about a call with where the call was made, if it's
+// known, and raise it. The message is freed.
+static void __attribute__((noreturn)) call_error_at(char *message,
+                                                    const char *site) {
+  char *full;
+
+  if (site != NULL) {
+    ALLOC_SPRINTF(full, "%s (%s)", message, site);
+  } else {
+    ALLOC_SPRINTF(full, "%s", message);
   }
+  free(message);
 
-  struct closure_obj *closure = (struct closure_obj *)rator;
+  // the error keeps a copy of its message, so this one can live on the stack
+  char *on_stack = strcpy(alloca(strlen(full) + 1), full);
+  free(full);
 
-  if (closure->size != CLOSURE_ONE) {
-    printf("Trying to call: %p\n", closure->fn_1);
-    RUNTIME_ERROR("Called a closure that takes two args with one arg");
-  }
+  raise_error(on_stack, 0, NULL);
+}
 
-  if (stack_check()) {
-    closure->fn_1(rand, closure->env);
+// the result is malloc'd
+static char *closure_name(const struct lambda_info *info) {
+  char *res;
+
+  if (info->name != NULL) {
+    ALLOC_SPRINTF(res, "%s", info->name);
+  } else if (info->location != NULL) {
+    ALLOC_SPRINTF(res, "lambda at %s", info->location);
   } else {
-    // TODO: move to our own gc allocator?
-    struct thunk *thnk_heap = malloc(sizeof(struct thunk));
-    thnk_heap->closr = closure;
-    thnk_heap->one.rand = rand;
-    run_minor_gc(thnk_heap);
+    ALLOC_SPRINTF(res, "anonymous procedure");
   }
+
+  return res;
 }
 
-void call_closure_two(struct obj *rator, struct obj *rand, struct obj *cont) {
-  if (rator->tag != OBJ_CLOSURE) {
-    RUNTIME_ERROR("Called object (%p) was not a closure but was: %d", rator,
-                  rator->tag);
+// `site` is the `file:line:col` of the call, or NULL if the call isn't in
+// the source, such as returning to a continuation
+void call_closure(struct obj *rator, size_t nargs, struct obj **rands,
+                  struct obj *cont, const char *site) {
+  if (rator == NULL || rator->tag != OBJ_CLOSURE) {
+    char *shown = obj_to_string_internal(rator);
+    char *message;
+    ALLOC_SPRINTF(message, "attempted to call %s, which isn't a procedure",
+                  shown);
+    free(shown);
+    call_error_at(message, site);
   }
 
   struct closure_obj *closure = (struct closure_obj *)rator;
-
-  if (closure->size != CLOSURE_TWO) {
-    RUNTIME_ERROR("Called a closure that takes one arg with two args");
+  const struct lambda_info *info = closure->info;
+
+  if (info->variadic ? nargs < info->arity : nargs != info->arity) {
+    char *name = closure_name(info);
+    char *message;
+    ALLOC_SPRINTF(message, "%s: expected %s%zu argument%s, got %zu", name,
+                  info->variadic ? "at least " : "", info->arity,
+                  info->arity == 1 ? "" : "s", nargs);
+    free(name);
+    call_error_at(message, site);
   }
 
   if (stack_check()) {
-    closure->fn_2(rand, cont, closure->env);
+    closure->fn(nargs, rands, cont, closure->env);
   } else {
     // TODO: move to our own gc allocator?
-    struct thunk *thnk_heap = malloc(sizeof(struct thunk));
+    struct thunk *thnk_heap =
+        malloc(sizeof(struct thunk) + nargs * sizeof(struct obj *));
     thnk_heap->closr = closure;
-    thnk_heap->two.rand = rand;
-    thnk_heap->two.cont = cont;
+    thnk_heap->cont = cont;
+    thnk_heap->nargs = nargs;
+    memcpy(thnk_heap->rands, rands, nargs * sizeof(struct obj *));
     run_minor_gc(thnk_heap);
   }
+
+  __builtin_unreachable();
+}
+
+// Return `rand` to the continuation `rator`
+void call_closure_one(struct obj *rator, struct obj *rand) {
+  call_closure(rator, 1, &rand, NULL, NULL);
 }
 
 static size_t get_stack_limit(void) {
@@ -84,7 +119,7 @@ static void *stack_ptr(void) { return __builtin_frame_address(0); }
 /*
  * Are we above the stack limit
  */
-static bool stack_check(void) {
+bool stack_check(void) {
   // buffer area at the end of the stack since idk how accurate this is
   // so reserve 256K for anything we might do after getting to the 'limit'
   static size_t stack_buffer = 1024 * 256;
@@ -108,20 +143,14 @@ void scheme_start(struct thunk *initial_thunk) {
 
   DEBUG_FPRINTF(stderr, "bouncing\n");
 
-  if (current_thunk->closr->size == CLOSURE_ONE) {
-    struct closure_obj *closr = current_thunk->closr;
-    struct obj *rand = current_thunk->one.rand;
-    struct env_obj *env = current_thunk->closr->env;
-    free(current_thunk);
-    closr->fn_1(rand, env);
-  } else {
-    struct closure_obj *closr = current_thunk->closr;
-    struct obj *rand = current_thunk->two.rand;
-    struct obj *cont = current_thunk->two.cont;
-    struct env_obj *env = current_thunk->closr->env;
-    free(current_thunk);
-    closr->fn_2(rand, cont, env);
-  }
+  // the arguments are copied onto the stack so the thunk can be freed
+  struct closure_obj *closr = current_thunk->closr;
+  struct obj *cont = current_thunk->cont;
+  size_t nargs = current_thunk->nargs;
+  struct obj **rands = alloca(nargs * sizeof(struct obj *));
+  memcpy(rands, current_thunk->rands, nargs * sizeof(struct obj *));
+  free(current_thunk);
+  closr->fn(nargs, rands, cont, closr->env);
 
   RUNTIME_ERROR("Control flow returned from trampoline function.");
 }
@@ -148,21 +177,11 @@ struct obj object_base_new(enum object_tag tag) {
   };
 }
 
-struct closure_obj object_closure_one_new(void (*fn)(struct obj *,
-                                                     struct env_obj *),
-                                          struct env_obj *env) {
-  return (struct closure_obj){.base = object_base_new(OBJ_CLOSURE),
-                              .size = CLOSURE_ONE,
-                              .fn_1 = fn,
-                              .env = env};
-}
-
-struct closure_obj object_closure_two_new(void (*fn)(struct obj *, struct obj *,
-                                                     struct env_obj *),
-                                          struct env_obj *env) {
+struct closure_obj object_closure_new(closure_fn fn, struct env_obj *env,
+                                      const struct lambda_info *info) {
   return (struct closure_obj){.base = object_base_new(OBJ_CLOSURE),
-                              .size = CLOSURE_TWO,
-                              .fn_2 = fn,
+                              .info = info,
+                              .fn = fn,
                               .env = env};
 }
 
@@ -170,11 +189,30 @@ struct int_obj object_int_obj_new(int64_t val) {
   return (struct int_obj){.base = object_base_new(OBJ_INT), .val = val};
 }
 
+struct float_obj object_float_obj_new(double val) {
+  return (struct float_obj){.base = object_base_new(OBJ_FLOAT), .val = val};
+}
+
+struct bool_obj object_bool_obj_new(bool val) {
+  return (struct bool_obj){.base = object_base_new(OBJ_BOOL), .val = val};
+}
+
+struct char_obj object_char_obj_new(uint32_t val) {
+  return (struct char_obj){.base = object_base_new(OBJ_CHAR), .val = val};
+}
+
 struct cons_obj object_cons_obj_new(struct obj *car, struct obj *cdr) {
   return (struct cons_obj){
       .base = object_base_new(OBJ_CONS), .car = car, .cdr = cdr};
 }
 
+struct error_obj object_error_obj_new(struct obj *message,
+                                      struct obj *irritants) {
+  return (struct error_obj){.base = object_base_new(OBJ_ERROR),
+                            .message = message,
+                            .irritants = irritants};
+}
+
 static size_t hash_string(const char *buf, size_t len) {
   size_t hash = 14695981039346656037ull;
 
@@ -196,6 +234,54 @@ static size_t hash_combine(size_t a, size_t b) {
   return hash;
 }
 
+// The key of the symbol table, names can contain NULs so carry their length
+struct symbol_name {
+  const char *buf;
+  size_t len;
+};
+
+static bool symbol_name_eq(struct symbol_name a, struct symbol_name b) {
+  return a.len == b.len && memcmp(a.buf, b.buf, a.len) == 0;
+}
+
+static size_t hash_symbol_name(struct symbol_name n) {
+  return hash_string(n.buf, n.len);
+}
+
+DEFINE_HASH(struct symbol_name, struct symbol_obj *, symbol_table);
+MAKE_HASH(struct symbol_name, struct symbol_obj *, hash_symbol_name,
+          symbol_name_eq, symbol_table);
+
+static struct hash_table_symbol_table *symbol_table;
+
+// Find the symbol with the given name, creating it if this is the first time
+// we've seen it. Symbols are never freed.
+struct symbol_obj *object_symbol_obj_intern(const char *name, size_t len) {
+  if (!symbol_table)
+    symbol_table = hash_table_symbol_table_new();
+
+  struct symbol_obj **existing = hash_table_symbol_table_lookup(
+      symbol_table, (struct symbol_name){.buf = name, .len = len});
+  if (existing)
+    return *existing;
+
+  // keep a null byte on the end so the name can still be printed with %s
+  char *buf = malloc(len + 1);
+  memcpy(buf, name, len);
+  buf[len] = '\0';
+
+  struct symbol_obj *sym = malloc(sizeof(struct symbol_obj));
+  sym->base = object_base_new(OBJ_SYMBOL);
+  sym->base.on_stack = false;
+  sym->name = buf;
+  sym->len = len;
+
+  hash_table_symbol_table_insert(
+      symbol_table, (struct symbol_name){.buf = sym->name, .len = len}, sym);
+
+  return sym;
+}
+
 size_t hash_obj_impl(struct obj *obj) {
   if (!obj) {
     return 0;
@@ -204,10 +290,23 @@ size_t hash_obj_impl(struct obj *obj) {
   switch (obj->tag) {
   case OBJ_INT:
     return hash_table_default_size_t_hash_fun(((struct int_obj *)obj)->val);
+  case OBJ_BOOL:
+    return hash_table_default_size_t_hash_fun(((struct bool_obj *)obj)->val);
+  case OBJ_CHAR:
+    return hash_table_default_size_t_hash_fun(((struct char_obj *)obj)->val);
+  case OBJ_FLOAT: {
+    double val = ((struct float_obj *)obj)->val;
+    // 0.0 and -0.0 compare equal so they need to hash the same
+    if (val == 0.0)
+      val = 0.0;
+    return hash_string((const char *)&val, sizeof(val));
+  }
   case OBJ_STR: {
     struct string_obj *str_obj = (struct string_obj *)obj;
     return hash_string(str_obj->buf, str_obj->len);
   }
+  case OBJ_SYMBOL:
+    return hash_table_default_size_t_hash_fun((size_t)obj);
   case OBJ_CONS: {
     struct cons_obj *cons_obj = (struct cons_obj *)obj;
     size_t a = hash_obj_impl(cons_obj->car);
@@ -228,7 +327,7 @@ size_t hash_obj_impl(struct obj *obj) {
   case OBJ_CELL:
     return hash_obj_impl(((struct cell_obj *)obj)->val);
   default:
-    RUNTIME_ERROR("Unhashable type: %d", obj->tag);
+    RAISE_ERROR("Unhashable hash table key:", obj);
   }
 }
 
@@ -245,6 +344,12 @@ bool eq_obj_impl(struct obj *a, struct obj *b) {
   switch (a->tag) {
   case OBJ_INT:
     return ((struct int_obj *)a)->val == ((struct int_obj *)b)->val;
+  case OBJ_BOOL:
+    return ((struct bool_obj *)a)->val == ((struct bool_obj *)b)->val;
+  case OBJ_CHAR:
+    return ((struct char_obj *)a)->val == ((struct char_obj *)b)->val;
+  case OBJ_FLOAT:
+    return ((struct float_obj *)a)->val == ((struct float_obj *)b)->val;
   case OBJ_STR: {
     struct string_obj *str_obj_a = (struct string_obj *)a;
     struct string_obj *str_obj_b = (struct string_obj *)b;
@@ -252,8 +357,10 @@ bool eq_obj_impl(struct obj *a, struct obj *b) {
     if (str_obj_a->len != str_obj_b->len)
       return false;
 
-    return strncmp(str_obj_a->buf, str_obj_b->buf, str_obj_a->len) == 0;
+    return memcmp(str_obj_a->buf, str_obj_b->buf, str_obj_a->len) == 0;
   }
+  case OBJ_SYMBOL:
+    return a == b;
   case OBJ_CONS: {
     struct cons_obj *cons_obj_a = (struct cons_obj *)a;
     struct cons_obj *cons_obj_b = (struct cons_obj *)b;
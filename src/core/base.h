@@ -1,6 +1,7 @@
 #ifndef SOMESCHEME_H
 #define SOMESCHEME_H
 
+#include <math.h>
 #include <stdbool.h>
 #include <stdlib.h>
 
@@ -10,19 +11,25 @@
 
 #define NUM_ARGS(...) (sizeof((size_t[]){__VA_ARGS__}) / sizeof(size_t))
 
-#define OBJECT_STRING_OBJ_NEW(NAME, S)                                         \
+// S is LEN bytes long, not including a null byte. The string may contain
+// nulls itself, so the length always comes from LEN
+#define OBJECT_STRING_OBJ_NEW_LEN(NAME, S, LEN)                                \
   struct obj *(NAME);                                                          \
   do {                                                                         \
-    size_t len = strlen(S) + 1;                                                \
+    size_t len = (LEN) + 1;                                                    \
     /* we keep the null byte */                                                \
     struct string_obj *new_obj = alloca(sizeof(struct string_obj) + len);      \
     new_obj->base = object_base_new(OBJ_STR);                                  \
     new_obj->len = len;                                                        \
-    memcpy((char *)&new_obj->buf, (S), len);                                   \
+    memcpy((char *)&new_obj->buf, (S), len - 1);                               \
+    ((char *)&new_obj->buf)[len - 1] = '\0';                                   \
     TOUCH_OBJECT(new_obj, "string_obj_new");                                   \
     (NAME) = (struct obj *)new_obj;                                            \
   } while (0)
 
+#define OBJECT_STRING_OBJ_NEW(NAME, S)                                         \
+  OBJECT_STRING_OBJ_NEW_LEN(NAME, S, strlen(S))
+
 #define OBJECT_INT_OBJ_NEW(NAME, n)                                            \
   struct obj *(NAME);                                                          \
   do {                                                                         \
@@ -32,6 +39,37 @@
     (NAME) = (struct obj *)new_obj;                                            \
   } while (0)
 
+#define OBJECT_FLOAT_OBJ_NEW(NAME, f)                                          \
+  struct obj *(NAME);                                                          \
+  do {                                                                         \
+    struct float_obj *new_obj = alloca(sizeof(struct float_obj));              \
+    *new_obj = object_float_obj_new((f));                                      \
+    TOUCH_OBJECT(new_obj, "float_obj_new");                                    \
+    (NAME) = (struct obj *)new_obj;                                            \
+  } while (0)
+
+// symbols are interned and live outside the gc, so nothing is allocated here
+#define OBJECT_SYMBOL_OBJ_NEW(NAME, S, LEN)                                    \
+  struct obj *(NAME) = (struct obj *)object_symbol_obj_intern((S), (LEN))
+
+#define OBJECT_CHAR_OBJ_NEW(NAME, c)                                           \
+  struct obj *(NAME);                                                          \
+  do {                                                                         \
+    struct char_obj *new_obj = alloca(sizeof(struct char_obj));                \
+    *new_obj = object_char_obj_new((c));                                       \
+    TOUCH_OBJECT(new_obj, "char_obj_new");                                     \
+    (NAME) = (struct obj *)new_obj;                                            \
+  } while (0)
+
+#define OBJECT_BOOL_OBJ_NEW(NAME, b)                                           \
+  struct obj *(NAME);                                                          \
+  do {                                                                         \
+    struct bool_obj *new_obj = alloca(sizeof(struct bool_obj));                \
+    *new_obj = object_bool_obj_new((b));                                       \
+    TOUCH_OBJECT(new_obj, "bool_obj_new");                                     \
+    (NAME) = (struct obj *)new_obj;                                            \
+  } while (0)
+
 #define ENV_STRUCT(T)                                                          \
   struct {                                                                     \
     struct obj base;                                                           \
@@ -49,22 +87,27 @@
     (NAME) = (struct env_obj *)new_env;                                        \
   } while (0)
 
-#define OBJECT_CLOSURE_ONE_NEW(NAME, FN, ENV)                                  \
+// INFO is the closure's entry in the lambda_infos table
+#define OBJECT_CLOSURE_NEW(NAME, FN, ENV, INFO)                                \
   struct obj *(NAME);                                                          \
   do {                                                                         \
     struct closure_obj *new_obj = alloca(sizeof(struct closure_obj));          \
-    *new_obj = object_closure_one_new((FN), (ENV));                            \
-    TOUCH_OBJECT(new_obj, "closure_one_new");                                  \
+    *new_obj = object_closure_new((FN), (ENV), (INFO));                        \
+    TOUCH_OBJECT(new_obj, "closure_new");                                      \
     (NAME) = (struct obj *)new_obj;                                            \
   } while (0)
 
-#define OBJECT_CLOSURE_TWO_NEW(NAME, FN, ENV)                                  \
-  struct obj *(NAME);                                                          \
+// a list of the LEN objects starting at ARGS, built back to front
+#define OBJECT_ARGS_LIST_NEW(NAME, ARGS, LEN)                                  \
+  struct obj *(NAME) = NULL;                                                   \
   do {                                                                         \
-    struct closure_obj *new_obj = alloca(sizeof(struct closure_obj));          \
-    *new_obj = object_closure_two_new((FN), (ENV));                            \
-    TOUCH_OBJECT(new_obj, "closure_two_new");                                  \
-    (NAME) = (struct obj *)new_obj;                                            \
+    struct obj **args_start = (ARGS);                                          \
+    for (size_t i = (LEN); i > 0; i--) {                                       \
+      struct cons_obj *new_obj = alloca(sizeof(struct cons_obj));              \
+      *new_obj = object_cons_obj_new(args_start[i - 1], (NAME));               \
+      TOUCH_OBJECT(new_obj, "args_list_new");                                  \
+      (NAME) = (struct obj *)new_obj;                                          \
+    }                                                                          \
   } while (0)
 
 #define OBJECT_CELL_OBJ_NEW(NAME, VAL)                                         \
@@ -96,11 +139,6 @@
   } while (0)
 #endif // DEBUG_TOUCH
 
-enum __attribute__((__packed__)) closure_size {
-  CLOSURE_ONE = 0,
-  CLOSURE_TWO,
-};
-
 enum __attribute__((__packed__)) object_tag {
   OBJ_CLOSURE = 1,
   ENV_OBJ,
@@ -109,9 +147,14 @@ enum __attribute__((__packed__)) object_tag {
   OBJ_CONS,
   OBJ_CELL,
   OBJ_HT,
+  OBJ_BOOL,
+  OBJ_FLOAT,
+  OBJ_SYMBOL,
+  OBJ_CHAR,
+  OBJ_ERROR,
 };
 
-#define LAST_OBJ_TYPE OBJ_HT
+#define LAST_OBJ_TYPE OBJ_ERROR
 
 enum __attribute__((__packed__)) gc_mark_type { WHITE = 0, GREY, BLACK };
 
@@ -143,13 +186,31 @@ struct cons_obj {
   struct obj *cdr;
 };
 
+// Every closure is given its arguments as an array, along with the
+// continuation to return to. Continuations themselves take one argument and
+// are given a NULL continuation.
+typedef void (*closure_fn)(size_t, struct obj **, struct obj *,
+                           struct env_obj *);
+
+// What is known about a lambda or builtin at compile time. Codegen emits the
+// lambda_infos table with an entry for each one in the program.
+struct lambda_info {
+  // NULL for a lambda that isn't bound to a name
+  const char *name;
+  // a variadic closure takes any number of arguments after the first `arity`,
+  // which are passed to it as a list
+  size_t arity;
+  bool variadic;
+  // `file:line:col` of the lambda, NULL if it isn't in the source
+  const char *location;
+};
+
+extern const struct lambda_info lambda_infos[];
+
 struct closure_obj {
   struct obj base;
-  enum closure_size size;
-  union {
-    void (*fn_1)(struct obj *, struct env_obj *);
-    void (*fn_2)(struct obj *, struct obj *, struct env_obj *);
-  };
+  const struct lambda_info *info;
+  closure_fn fn;
   struct env_obj *env;
 };
 
@@ -158,12 +219,44 @@ struct int_obj {
   int64_t val;
 };
 
+struct float_obj {
+  struct obj base;
+  double val;
+};
+
+struct bool_obj {
+  struct obj base;
+  bool val;
+};
+
+// a unicode code point
+struct char_obj {
+  struct obj base;
+  uint32_t val;
+};
+
 struct string_obj {
   struct obj base;
   size_t len;
   const char buf[];
 };
 
+// There is only ever one symbol_obj for each name, so symbols can be compared
+// by pointer
+struct symbol_obj {
+  struct obj base;
+  // the length of the name, which like a string can contain NULs
+  size_t len;
+  const char *name;
+};
+
+// The condition raised by `error`, and by builtins given bad arguments
+struct error_obj {
+  struct obj base;
+  struct obj *message;
+  // a list of the objects the error is about
+  struct obj *irritants;
+};
 
 DEFINE_HASH(struct obj *, struct obj *, obj);
 
@@ -172,34 +265,43 @@ struct ht_obj {
   struct hash_table_obj *ht;
 };
 
+// The frames of the dynamic-winds control is inside of, innermost first, as a
+// list of (before . after) pairs. It outlives every stack frame, so the gc
+// treats it as a root.
+extern struct obj *wind_stack;
+
+// The handlers installed by with-exception-handler, innermost first, as a
+// list. Like the wind stack it is a gc root.
+extern struct obj *handler_stack;
+
+// A call that is waiting to be made once the stack has been reset
 struct thunk {
   struct closure_obj *closr;
-  union {
-    struct {
-      struct obj *rand;
-    } one;
-    struct {
-      struct obj *rand;
-      struct obj *cont;
-    } two;
-  };
+  struct obj *cont;
+  size_t nargs;
+  struct obj *rands[];
 };
 
+void call_closure(struct obj *, size_t, struct obj **, struct obj *,
+                  const char *);
 void call_closure_one(struct obj *, struct obj *);
-void call_closure_two(struct obj *, struct obj *, struct obj *);
+// Whether there is room on the stack to keep going without a gc, a loop checks
+// this before jumping back to its start
+bool stack_check(void);
 void scheme_start(struct thunk *);
 void run_minor_gc(struct thunk *);
 
 struct obj object_base_new(enum object_tag);
-struct closure_obj object_closure_one_new(void (*)(struct obj *,
-                                                   struct env_obj *),
-                                          struct env_obj *);
-struct closure_obj object_closure_two_new(void (*)(struct obj *, struct obj *,
-                                                   struct env_obj *),
-                                          struct env_obj *);
+struct closure_obj object_closure_new(closure_fn, struct env_obj *,
+                                      const struct lambda_info *);
 struct int_obj object_int_obj_new(int64_t);
+struct float_obj object_float_obj_new(double);
+struct bool_obj object_bool_obj_new(bool);
+struct char_obj object_char_obj_new(uint32_t);
 struct cons_obj object_cons_obj_new(struct obj *, struct obj *);
+struct error_obj object_error_obj_new(struct obj *, struct obj *);
 struct ht_obj object_ht_obj_new(void);
+struct symbol_obj *object_symbol_obj_intern(const char *, size_t);
 
 bool eq_obj_impl(struct obj *, struct obj *);
 
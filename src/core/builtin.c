@@ -3,13 +3,17 @@
 #include "common.h"
 #include "gc.h"
 #include <stdbool.h>
+#include <stdlib.h>
+#include <string.h>
 
+// The operators are named the same as the builtins, so errors use OP as the
+// builtin's name
 #define MAKE_INT_BINOP(NAME, OP)                                               \
   struct int_obj object_int_obj_##NAME(struct obj *lhs, struct obj *rhs) {     \
-    if (lhs->tag != OBJ_INT)                                                   \
-      RUNTIME_ERROR("Left operand to binary " #NAME " not of integer type");   \
-    if (rhs->tag != OBJ_INT)                                                   \
-      RUNTIME_ERROR("Right operand to binary " #NAME " not of integer type");  \
+    if (lhs == NULL || lhs->tag != OBJ_INT)                                    \
+      RAISE_ERROR("Left operand to " #OP " not of integer type:", lhs);        \
+    if (rhs == NULL || rhs->tag != OBJ_INT)                                    \
+      RAISE_ERROR("Right operand to " #OP " not of integer type:", rhs);       \
                                                                                \
     struct int_obj *lhs_int = (struct int_obj *)lhs;                           \
     struct int_obj *rhs_int = (struct int_obj *)rhs;                           \
@@ -17,27 +21,95 @@
     return object_int_obj_new(lhs_int->val OP rhs_int->val);                   \
   } MAKE_TWO_ARG_FROM_BUILTIN(NAME, object_int_obj_##NAME, struct int_obj)
 
-MAKE_INT_BINOP(add, +);
-MAKE_INT_BINOP(sub, -);
-MAKE_INT_BINOP(mul, *);
-MAKE_INT_BINOP(div, /);
+static bool obj_is_number(struct obj *obj) {
+  return obj && (obj->tag == OBJ_INT || obj->tag == OBJ_FLOAT);
+}
+
+static double obj_number_as_double(struct obj *obj) {
+  if (obj->tag == OBJ_INT)
+    return (double)((struct int_obj *)obj)->val;
+
+  return ((struct float_obj *)obj)->val;
+}
+
+#define CHECK_NUMERIC_OPERANDS(OP, LHS, RHS)                                   \
+  do {                                                                         \
+    if (!obj_is_number(LHS))                                                   \
+      RAISE_ERROR("Left operand to " #OP " not of numeric type:", LHS);        \
+    if (!obj_is_number(RHS))                                                   \
+      RAISE_ERROR("Right operand to " #OP " not of numeric type:", RHS);       \
+  } while (0)
+
+#define MAKE_NUM_BINOP(NAME, OP)                                               \
+  union num_obj object_num_obj_##NAME(struct obj *lhs, struct obj *rhs) {      \
+    CHECK_NUMERIC_OPERANDS(OP, lhs, rhs);                                      \
+                                                                               \
+    if (lhs->tag == OBJ_INT && rhs->tag == OBJ_INT)                            \
+      return (union num_obj){.int_obj = object_int_obj_new(                    \
+                                 ((struct int_obj *)lhs)->val OP(              \
+                                     (struct int_obj *)rhs)                    \
+                                     ->val)};                                  \
+                                                                               \
+    return (union num_obj){.float_obj = object_float_obj_new(                  \
+                               obj_number_as_double(lhs)                       \
+                                   OP obj_number_as_double(rhs))};             \
+  } MAKE_TWO_ARG_FROM_BUILTIN(NAME, object_num_obj_##NAME, union num_obj)
+
+#define MAKE_NUM_CMP(NAME, OP)                                                 \
+  struct bool_obj object_num_obj_##NAME(struct obj *lhs, struct obj *rhs) {    \
+    CHECK_NUMERIC_OPERANDS(OP, lhs, rhs);                                      \
+                                                                               \
+    /* compare ints directly so large values don't lose precision */           \
+    if (lhs->tag == OBJ_INT && rhs->tag == OBJ_INT)                            \
+      return object_bool_obj_new(((struct int_obj *)lhs)->val OP(              \
+                                     (struct int_obj *)rhs)                    \
+                                     ->val);                                   \
+                                                                               \
+    return object_bool_obj_new(obj_number_as_double(lhs)                       \
+                                   OP obj_number_as_double(rhs));              \
+  } MAKE_TWO_ARG_FROM_BUILTIN(NAME, object_num_obj_##NAME, struct bool_obj)
+
+MAKE_NUM_BINOP(add, +);
+MAKE_NUM_BINOP(sub, -);
+MAKE_NUM_BINOP(mul, *);
 MAKE_INT_BINOP(xor, ^);
-MAKE_INT_BINOP(lt, <);
-MAKE_INT_BINOP(leq, <=);
-MAKE_INT_BINOP(gt, >);
-MAKE_INT_BINOP(geq, >=);
+MAKE_NUM_CMP(lt, <);
+MAKE_NUM_CMP(leq, <=);
+MAKE_NUM_CMP(gt, >);
+MAKE_NUM_CMP(geq, >=);
+
+union num_obj object_num_obj_div(struct obj *lhs, struct obj *rhs) {
+  CHECK_NUMERIC_OPERANDS(/, lhs, rhs);
+
+  if (lhs->tag == OBJ_INT && rhs->tag == OBJ_INT) {
+    struct int_obj *lhs_int = (struct int_obj *)lhs;
+    struct int_obj *rhs_int = (struct int_obj *)rhs;
+
+    if (rhs_int->val == 0)
+      RAISE_ERROR("Divide by zero:", lhs, rhs);
+
+    return (union num_obj){
+        .int_obj = object_int_obj_new(lhs_int->val / rhs_int->val)};
+  }
+
+  return (union num_obj){.float_obj = object_float_obj_new(
+                             obj_number_as_double(lhs) /
+                             obj_number_as_double(rhs))};
+}
+
+MAKE_TWO_ARG_FROM_BUILTIN(div, object_num_obj_div, union num_obj);
 
 struct int_obj object_int_obj_mod(struct obj *lhs, struct obj *rhs) {
-  if (lhs->tag != OBJ_INT)
-    RUNTIME_ERROR("Left operand to binary mod not of integer type");
-  if (rhs->tag != OBJ_INT)
-    RUNTIME_ERROR("Right operand to binary mod not of integer type");
+  if (lhs == NULL || lhs->tag != OBJ_INT)
+    RAISE_ERROR("Left operand to % not of integer type:", lhs);
+  if (rhs == NULL || rhs->tag != OBJ_INT)
+    RAISE_ERROR("Right operand to % not of integer type:", rhs);
 
   struct int_obj *lhs_int = (struct int_obj *)lhs;
   struct int_obj *rhs_int = (struct int_obj *)rhs;
 
   if (rhs_int->val == 0)
-    RUNTIME_ERROR("Divide by zero (%d %% %d)", lhs_int->val, rhs_int->val);
+    RAISE_ERROR("Divide by zero:", lhs, rhs);
 
   return object_int_obj_new(lhs_int->val % rhs_int->val);
 }
@@ -46,27 +118,606 @@ MAKE_TWO_ARG_FROM_BUILTIN(mod, object_int_obj_mod, struct int_obj);
 
 MAKE_TWO_ARG_FROM_BUILTIN(cons, object_cons_obj_new, struct cons_obj);
 
-int exit_inner() { exit(0); }
+// Copies the cells of the first list onto the stack, the last one pointing to
+// the second list
+void append_k(size_t nargs, struct obj **args, struct obj *k,
+              struct env_obj *env) {
+  struct obj *lhs = args[0];
+  struct obj *v = args[1];
+  size_t len = 0;
+
+  for (struct obj *c = lhs; c != NULL; c = ((struct cons_obj *)c)->cdr) {
+    if (c->tag != OBJ_CONS)
+      RAISE_ERROR("Left operand to append not a list:", lhs);
+    len++;
+  }
+
+  if (len == 0)
+    call_closure_one(k, v);
+
+  struct cons_obj *cells = alloca(sizeof(struct cons_obj) * len);
+  struct obj *c = lhs;
+
+  for (size_t i = 0; i < len; i++) {
+    struct obj *next = i + 1 < len ? (struct obj *)&cells[i + 1] : v;
+    cells[i] = object_cons_obj_new(((struct cons_obj *)c)->car, next);
+    c = ((struct cons_obj *)c)->cdr;
+  }
+
+  call_closure_one(k, (struct obj *)cells);
+
+  __builtin_unreachable();
+}
+
+// the continuation of the whole program
+void exit_k(size_t nargs, struct obj **args, struct obj *k,
+            struct env_obj *env) {
+  exit(0);
+}
+
+// the closures the runtime makes itself are all continuations
+static const struct lambda_info continuation_info = {.name = "continuation",
+                                                     .arity = 1};
+
+#define CAR(O) (((struct cons_obj *)(O))->car)
+#define CDR(O) (((struct cons_obj *)(O))->cdr)
+
+static size_t list_length(struct obj *l) {
+  size_t len = 0;
+
+  for (; l != NULL; l = CDR(l)) {
+    len++;
+  }
+
+  return len;
+}
+
+struct wind_to_env {
+  struct obj *target;
+  struct obj *k;
+  struct obj *value;
+  // the frame whose before has just run, NULL after running an after
+  struct obj *entering;
+};
+
+static void wind_to(struct obj *, struct obj *, struct obj *)
+    __attribute__((noreturn));
+
+static void wind_step_k(size_t nargs, struct obj **args, struct obj *cont,
+                        struct env_obj *env) {
+  struct wind_to_env *e = (struct wind_to_env *)&env->env;
+
+  if (e->entering != NULL) {
+    wind_stack = e->entering;
+  }
+
+  wind_to(e->target, e->k, e->value);
+}
+
+// Move from the current wind stack to `target` one frame at a time, running
+// the after of each frame left and then the before of each frame entered, and
+// finally return `v` to `k`. The stacks share the frames they have in common,
+// so those can be found by comparing pointers.
+static void wind_to(struct obj *target, struct obj *k, struct obj *v) {
+  if (wind_stack == target) {
+    call_closure_one(k, v);
+  }
+
+  struct obj *here = wind_stack;
+  struct obj *there = target;
+  size_t here_len = list_length(here);
+  size_t there_len = list_length(there);
+
+  for (; here_len > there_len; here_len--) {
+    here = CDR(here);
+  }
+  for (; there_len > here_len; there_len--) {
+    there = CDR(there);
+  }
+  while (here != there) {
+    here = CDR(here);
+    there = CDR(there);
+  }
+
+  OBJECT_ENV_OBJ_NEW(step_env, struct wind_to_env);
+  struct wind_to_env *e = (struct wind_to_env *)&step_env->env;
+  e->target = target;
+  e->k = k;
+  e->value = v;
+  OBJECT_CLOSURE_NEW(step, wind_step_k, step_env, &continuation_info);
+
+  if (wind_stack != here) {
+    // an after runs outside of its frame
+    struct obj *frame = CAR(wind_stack);
+    wind_stack = CDR(wind_stack);
+    call_closure(CDR(frame), 0, NULL, step, NULL);
+  }
+
+  // the outermost frame of `target` that we aren't in yet, its before runs
+  // outside of it too
+  struct obj *entering = target;
+  while (CDR(entering) != wind_stack) {
+    entering = CDR(entering);
+  }
+
+  e->entering = entering;
+  call_closure(CAR(CAR(entering)), 0, NULL, step, NULL);
+
+  __builtin_unreachable();
+}
+
+struct continuation_env {
+  struct obj *k;
+  // the wind stack and handlers when the continuation was captured
+  struct obj *winds;
+  struct obj *handlers;
+};
+
+static void continuation_k(size_t nargs, struct obj **args, struct obj *cont,
+                           struct env_obj *env) {
+  struct continuation_env *e = (struct continuation_env *)&env->env;
+
+  handler_stack = e->handlers;
+  wind_to(e->winds, e->k, args[0]);
+}
+
+// `f` is given a continuation that ignores the continuation it is called
+// with, so calling it abandons whatever the caller would have done next. It
+// first winds back to the dynamic-winds `call/cc` was called inside of, and
+// puts back the exception handlers that were installed then.
+void call_cc_k(size_t nargs, struct obj **args, struct obj *k,
+               struct env_obj *env) {
+  struct obj *f = args[0];
+
+  OBJECT_ENV_OBJ_NEW(cont_env, struct continuation_env);
+  struct continuation_env *e = (struct continuation_env *)&cont_env->env;
+  e->k = k;
+  e->winds = wind_stack;
+  e->handlers = handler_stack;
+  OBJECT_CLOSURE_NEW(cont, continuation_k, cont_env, &continuation_info);
+
+  call_closure(f, 1, &cont, k, NULL);
+
+  __builtin_unreachable();
+}
+
+struct dynamic_wind_env {
+  struct obj *before;
+  struct obj *thunk;
+  struct obj *after;
+  struct obj *k;
+  // what the thunk returned, given to `k` once the after has run
+  struct obj *result;
+};
+
+static void dynamic_wind_after_done_k(size_t nargs, struct obj **args,
+                                      struct obj *cont, struct env_obj *env) {
+  struct dynamic_wind_env *e = (struct dynamic_wind_env *)&env->env;
+
+  call_closure_one(e->k, e->result);
+
+  __builtin_unreachable();
+}
+
+static void dynamic_wind_thunk_done_k(size_t nargs, struct obj **args,
+                                      struct obj *cont, struct env_obj *env) {
+  struct dynamic_wind_env *e = (struct dynamic_wind_env *)&env->env;
+
+  wind_stack = CDR(wind_stack);
+  e->result = args[0];
+  OBJECT_CLOSURE_NEW(after_done, dynamic_wind_after_done_k, env,
+                     &continuation_info);
+
+  call_closure(e->after, 0, NULL, after_done, NULL);
+
+  __builtin_unreachable();
+}
+
+static void dynamic_wind_before_done_k(size_t nargs, struct obj **args,
+                                       struct obj *cont, struct env_obj *env) {
+  struct dynamic_wind_env *e = (struct dynamic_wind_env *)&env->env;
+
+  struct cons_obj frame = object_cons_obj_new(e->before, e->after);
+  struct cons_obj winds = object_cons_obj_new((struct obj *)&frame, wind_stack);
+  wind_stack = (struct obj *)&winds;
+  OBJECT_CLOSURE_NEW(thunk_done, dynamic_wind_thunk_done_k, env,
+                     &continuation_info);
+
+  call_closure(e->thunk, 0, NULL, thunk_done, NULL);
+
+  __builtin_unreachable();
+}
+
+// (dynamic-wind before thunk after), the frame is pushed once before has
+// returned and popped as soon as thunk returns
+void dynamic_wind_k(size_t nargs, struct obj **args, struct obj *k,
+                    struct env_obj *env) {
+  OBJECT_ENV_OBJ_NEW(wind_env, struct dynamic_wind_env);
+  struct dynamic_wind_env *e = (struct dynamic_wind_env *)&wind_env->env;
+  e->before = args[0];
+  e->thunk = args[1];
+  e->after = args[2];
+  e->k = k;
+  OBJECT_CLOSURE_NEW(before_done, dynamic_wind_before_done_k, wind_env,
+                     &continuation_info);
+
+  call_closure(e->before, 0, NULL, before_done, NULL);
+
+  __builtin_unreachable();
+}
+
+// A condition raised with no handler to catch it ends the program
+static void __attribute__((noreturn)) uncaught_exception(struct obj *condition) {
+  char *shown = obj_to_string_internal(condition);
+
+  if (condition != NULL && condition->tag == OBJ_ERROR) {
+    fprintf(stderr, "%s\n", shown);
+  } else {
+    fprintf(stderr, "uncaught exception: %s\n", shown);
+  }
+
+  free(shown);
+  exit(1);
+}
+
+struct handler_env {
+  // the handler stack to put back before returning to k
+  struct obj *handlers;
+  struct obj *k;
+};
+
+static void handler_restore_k(size_t nargs, struct obj **args,
+                              struct obj *cont, struct env_obj *env) {
+  struct handler_env *e = (struct handler_env *)&env->env;
+
+  handler_stack = e->handlers;
+  call_closure_one(e->k, args[0]);
+
+  __builtin_unreachable();
+}
+
+// (with-exception-handler handler thunk), the handler is installed for as long
+// as thunk runs
+void with_exception_handler_k(size_t nargs, struct obj **args, struct obj *k,
+                              struct env_obj *env) {
+  struct obj *handler = args[0];
+  struct obj *thunk = args[1];
+
+  OBJECT_ENV_OBJ_NEW(restore_env, struct handler_env);
+  struct handler_env *e = (struct handler_env *)&restore_env->env;
+  e->handlers = handler_stack;
+  e->k = k;
+  OBJECT_CLOSURE_NEW(restore, handler_restore_k, restore_env,
+                     &continuation_info);
+
+  struct cons_obj handlers = object_cons_obj_new(handler, handler_stack);
+  handler_stack = (struct obj *)&handlers;
+
+  call_closure(thunk, 0, NULL, restore, NULL);
+
+  __builtin_unreachable();
+}
+
+struct raise_env {
+  struct obj *condition;
+};
+
+static void raise_handler_returned_k(size_t nargs, struct obj **args,
+                                     struct obj *cont, struct env_obj *env) {
+  struct raise_env *e = (struct raise_env *)&env->env;
+
+  RAISE_ERROR("exception handler returned from non-continuable raise:",
+              e->condition);
+}
+
+// Call the innermost handler with `condition`, the handler runs with the
+// handlers outside of it installed so that it can raise to them
+static void scheme_raise(struct obj *condition) __attribute__((noreturn));
+static void scheme_raise(struct obj *condition) {
+  if (handler_stack == NULL) {
+    uncaught_exception(condition);
+  }
+
+  struct obj *handler = CAR(handler_stack);
+  handler_stack = CDR(handler_stack);
+
+  OBJECT_ENV_OBJ_NEW(raise_env, struct raise_env);
+  struct raise_env *e = (struct raise_env *)&raise_env->env;
+  e->condition = condition;
+  OBJECT_CLOSURE_NEW(returned, raise_handler_returned_k, raise_env,
+                     &continuation_info);
+
+  call_closure(handler, 1, &condition, returned, NULL);
+
+  __builtin_unreachable();
+}
+
+void raise_error(const char *message, size_t nirritants,
+                 struct obj **irritants) {
+  OBJECT_STRING_OBJ_NEW(message_str, message);
+  OBJECT_ARGS_LIST_NEW(irritant_list, irritants, nirritants);
+  struct error_obj error = object_error_obj_new(message_str, irritant_list);
+
+  scheme_raise((struct obj *)&error);
+}
+
+void raise_k(size_t nargs, struct obj **args, struct obj *k,
+             struct env_obj *env) {
+  scheme_raise(args[0]);
+}
+
+// Whatever the handler returns is returned from the raise, with the handlers
+// put back as they were
+void raise_continuable_k(size_t nargs, struct obj **args, struct obj *k,
+                         struct env_obj *env) {
+  struct obj *condition = args[0];
+
+  if (handler_stack == NULL) {
+    uncaught_exception(condition);
+  }
+
+  OBJECT_ENV_OBJ_NEW(restore_env, struct handler_env);
+  struct handler_env *e = (struct handler_env *)&restore_env->env;
+  e->handlers = handler_stack;
+  e->k = k;
+  OBJECT_CLOSURE_NEW(restore, handler_restore_k, restore_env,
+                     &continuation_info);
+
+  struct obj *handler = CAR(handler_stack);
+  handler_stack = CDR(handler_stack);
+
+  call_closure(handler, 1, &condition, restore, NULL);
+
+  __builtin_unreachable();
+}
+
+// (error message irritant...)
+void error_k(size_t nargs, struct obj **args, struct obj *k,
+             struct env_obj *env) {
+  OBJECT_ARGS_LIST_NEW(irritants, args + 1, nargs - 1);
+  struct error_obj error = object_error_obj_new(args[0], irritants);
+
+  scheme_raise((struct obj *)&error);
+}
+
+void is_error_object_k(size_t nargs, struct obj **args, struct obj *k,
+                       struct env_obj *env) {
+  struct obj *v = args[0];
+  _Bool r = v != NULL && v->tag == OBJ_ERROR;
+
+  OBJECT_BOOL_OBJ_NEW(res, r);
+
+  call_closure_one(k, res);
+
+  __builtin_unreachable();
+}
+
+void error_object_message_k(size_t nargs, struct obj **args, struct obj *k,
+                            struct env_obj *env) {
+  struct obj *v = args[0];
+  if (v == NULL || v->tag != OBJ_ERROR)
+    RAISE_ERROR("Argument to error-object-message not an error object:", v);
+
+  call_closure_one(k, ((struct error_obj *)v)->message);
 
-MAKE_ZERO_ARG_FROM_BUILTIN(exit, exit_inner, int);
+  __builtin_unreachable();
+}
+
+void error_object_irritants_k(size_t nargs, struct obj **args, struct obj *k,
+                              struct env_obj *env) {
+  struct obj *v = args[0];
+  if (v == NULL || v->tag != OBJ_ERROR)
+    RAISE_ERROR("Argument to error-object-irritants not an error object:", v);
+
+  call_closure_one(k, ((struct error_obj *)v)->irritants);
+
+  __builtin_unreachable();
+}
+
+// Encode a code point as utf-8, returns the number of bytes written
+static size_t utf8_encode(uint32_t c, char out[4]) {
+  if (c < 0x80) {
+    out[0] = c;
+    return 1;
+  }
+
+  if (c < 0x800) {
+    out[0] = 0xC0 | (c >> 6);
+    out[1] = 0x80 | (c & 0x3F);
+    return 2;
+  }
+
+  if (c < 0x10000) {
+    out[0] = 0xE0 | (c >> 12);
+    out[1] = 0x80 | ((c >> 6) & 0x3F);
+    out[2] = 0x80 | (c & 0x3F);
+    return 3;
+  }
+
+  out[0] = 0xF0 | (c >> 18);
+  out[1] = 0x80 | ((c >> 12) & 0x3F);
+  out[2] = 0x80 | ((c >> 6) & 0x3F);
+  out[3] = 0x80 | (c & 0x3F);
+  return 4;
+}
+
+// Decode the code point starting at buf, returns the number of bytes it took
+// up. Invalid bytes are decoded as themselves so no input is lost.
+static size_t utf8_decode(const unsigned char *buf, size_t len, uint32_t *c) {
+  size_t n;
+
+  if (buf[0] < 0x80) {
+    *c = buf[0];
+    return 1;
+  } else if ((buf[0] & 0xE0) == 0xC0) {
+    *c = buf[0] & 0x1F;
+    n = 2;
+  } else if ((buf[0] & 0xF0) == 0xE0) {
+    *c = buf[0] & 0x0F;
+    n = 3;
+  } else if ((buf[0] & 0xF8) == 0xF0) {
+    *c = buf[0] & 0x07;
+    n = 4;
+  } else {
+    *c = buf[0];
+    return 1;
+  }
+
+  if (n > len) {
+    *c = buf[0];
+    return 1;
+  }
+
+  for (size_t i = 1; i < n; i++) {
+    if ((buf[i] & 0xC0) != 0x80) {
+      *c = buf[0];
+      return 1;
+    }
+    *c = (*c << 6) | (buf[i] & 0x3F);
+  }
+
+  return n;
+}
+
+static char *char_to_string(uint32_t c) {
+  char buf[4];
+  size_t len = utf8_encode(c, buf);
+  char *res = malloc(len + 1);
+
+  memcpy(res, buf, len);
+  res[len] = '\0';
+
+  return res;
+}
+
+// Print the shortest representation that reads back as the same value, and
+// make sure it still looks like a float. Only very large and very small values
+// are printed with an exponent.
+static char *float_to_string(double val) {
+  char *res;
+
+  if (isnan(val)) {
+    ALLOC_SPRINTF(res, "+nan.0");
+    return res;
+  }
+
+  if (isinf(val)) {
+    ALLOC_SPRINTF(res, "%cinf.0", val > 0 ? '+' : '-');
+    return res;
+  }
+
+  // 17 significant digits always read back as the same double
+  int precision = 1;
+  for (;; precision++) {
+    ALLOC_SPRINTF(res, "%.*e", precision - 1, val);
+
+    if (precision == 17 || strtod(res, NULL) == val)
+      break;
+
+    free(res);
+  }
+
+  int exponent = atoi(strchr(res, 'e') + 1);
+  free(res);
+
+  if (exponent >= -5 && exponent < 17) {
+    // as many decimals as it takes to show all the significant digits
+    int decimals = precision - 1 - exponent;
+    ALLOC_SPRINTF(res, "%.*f", decimals > 0 ? decimals : 0, val);
+  } else {
+    ALLOC_SPRINTF(res, "%.*g", precision, val);
+  }
+
+  if (!strpbrk(res, ".e")) {
+    char *with_point;
+    ALLOC_SPRINTF(with_point, "%s.0", res);
+    free(res);
+    res = with_point;
+  }
+
+  return res;
+}
+
+static char *append_string(char *s, const char *sep, const char *tail) {
+  char *res;
+  ALLOC_SPRINTF(res, "%s%s%s", s, sep, tail);
+  free(s);
+  return res;
+}
+
+// Prints (a b c) for proper lists and (a b . c) for improper ones
+static char *cons_to_string(struct obj *val) {
+  char *res;
+  ALLOC_SPRINTF(res, "(");
+
+  const char *sep = "";
+
+  while (val && val->tag == OBJ_CONS) {
+    struct cons_obj *cons = (struct cons_obj *)val;
+    char *elem = obj_to_string_internal(cons->car);
+
+    res = append_string(res, sep, elem);
+    free(elem);
+
+    sep = " ";
+    val = cons->cdr;
+  }
+
+  if (val) {
+    char *tail = obj_to_string_internal(val);
+
+    res = append_string(res, " . ", tail);
+    free(tail);
+  }
+
+  return append_string(res, "", ")");
+}
+
+// Prints the message followed by each irritant
+static char *error_to_string(struct obj *val) {
+  struct error_obj *error = (struct error_obj *)val;
+  char *res = obj_to_string_internal(error->message);
+
+  for (struct obj *l = error->irritants; l != NULL; l = CDR(l)) {
+    char *irritant = obj_to_string_internal(CAR(l));
+
+    res = append_string(res, " ", irritant);
+    free(irritant);
+  }
+
+  return res;
+}
 
 char *obj_to_string_internal(struct obj *val) {
   char *res;
 
   if (!val) {
     ALLOC_SPRINTF(res, "()");
+    return res;
   }
 
   switch (val->tag) {
   case OBJ_CONS:
-    ALLOC_SPRINTF(res, "cons");
+    res = cons_to_string(val);
     break;
   case OBJ_CLOSURE:
-    ALLOC_SPRINTF(res, "closure|%p", (void *)((struct closure_obj *)val)->fn_1);
+    ALLOC_SPRINTF(res, "closure|%p", (void *)((struct closure_obj *)val)->fn);
     break;
   case OBJ_INT:
     ALLOC_SPRINTF(res, "%ld", ((struct int_obj *)val)->val);
     break;
+  case OBJ_FLOAT:
+    res = float_to_string(((struct float_obj *)val)->val);
+    break;
+  case OBJ_SYMBOL:
+    ALLOC_SPRINTF(res, "%s", ((struct symbol_obj *)val)->name);
+    break;
+  case OBJ_CHAR:
+    res = char_to_string(((struct char_obj *)val)->val);
+    break;
+  case OBJ_BOOL:
+    ALLOC_SPRINTF(res, "%s", ((struct bool_obj *)val)->val ? "#t" : "#f");
+    break;
   case OBJ_STR:
     ALLOC_SPRINTF(res, "%s", ((struct string_obj *)val)->buf);
     break;
@@ -75,6 +726,9 @@ char *obj_to_string_internal(struct obj *val) {
   case OBJ_HT:
     ALLOC_SPRINTF(res, "hash table");
     break;
+  case OBJ_ERROR:
+    res = error_to_string(val);
+    break;
   default:
     RUNTIME_ERROR("Unexpected object tag to to_string: %d", val->tag);
   }
@@ -82,7 +736,14 @@ char *obj_to_string_internal(struct obj *val) {
   return res;
 }
 
-void to_string_k(struct obj *v, struct obj *k, struct env_obj *env) {
+void to_string_k(size_t nargs, struct obj **args, struct obj *k,
+                 struct env_obj *env) {
+  struct obj *v = args[0];
+
+  // strings can contain nulls, so don't round trip them through a C string
+  if (v && v->tag == OBJ_STR)
+    call_closure_one(k, v);
+
   char *res = obj_to_string_internal(v);
 
   OBJECT_STRING_OBJ_NEW(result_str, res);
@@ -94,84 +755,170 @@ void to_string_k(struct obj *v, struct obj *k, struct env_obj *env) {
   __builtin_unreachable();
 }
 
-void display_k(struct obj *v, struct obj *k, struct env_obj *env) {
-  char *res = obj_to_string_internal(v);
-
-  printf("%s\n", res);
+struct obj *display_inner(struct obj *v) {
+  if (v && v->tag == OBJ_STR) {
+    struct string_obj *str = (struct string_obj *)v;
+    fwrite(str->buf, 1, str->len - 1, stdout);
+    putchar('\n');
+  } else {
+    char *res = obj_to_string_internal(v);
 
-  free(res);
+    printf("%s\n", res);
 
-  call_closure_one(k, NULL);
+    free(res);
+  }
 
-  __builtin_unreachable();
+  return NULL;
 }
 
+MAKE_ONE_ARG_FROM_BUILTIN_EXPLICIT_RETURN(display, display_inner);
+
+// Only #f is false, everything else (including 0, "" and '()) is true
 _Bool obj_is_truthy(struct obj *obj) {
+  if (!obj)
+    return true;
+
   switch (obj->tag) {
-  case OBJ_INT:
-    return ((struct int_obj *)obj)->val != 0;
-  case OBJ_STR:
-    return ((struct string_obj *)obj)->len != 0;
+  case OBJ_BOOL:
+    return ((struct bool_obj *)obj)->val;
+  case OBJ_CELL:
+    return obj_is_truthy(((struct cell_obj *)obj)->val);
   default:
     return true;
   }
 }
 
-void car_k(struct obj *cons, struct obj *k, struct env_obj *env) {
-  struct obj *car = ((struct cons_obj *)cons)->car;
+struct obj *car_inner(struct obj *cons) {
+  if (cons == NULL || cons->tag != OBJ_CONS)
+    RAISE_ERROR("Argument to car not a pair:", cons);
+
+  return ((struct cons_obj *)cons)->car;
+}
 
-  call_closure_one(k, car);
+MAKE_ONE_ARG_FROM_BUILTIN_EXPLICIT_RETURN(car, car_inner);
 
-  __builtin_unreachable();
+struct obj *cdr_inner(struct obj *cons) {
+  if (cons == NULL || cons->tag != OBJ_CONS)
+    RAISE_ERROR("Argument to cdr not a pair:", cons);
+
+  return ((struct cons_obj *)cons)->cdr;
 }
 
-void cdr_k(struct obj *cons, struct obj *k, struct env_obj *env) {
-  struct obj *cdr = ((struct cons_obj *)cons)->cdr;
+MAKE_ONE_ARG_FROM_BUILTIN_EXPLICIT_RETURN(cdr, cdr_inner);
 
-  call_closure_one(k, cdr);
+struct bool_obj is_cons_inner(struct obj *v) {
+  return object_bool_obj_new(v != NULL && v->tag == OBJ_CONS);
+}
 
-  __builtin_unreachable();
+MAKE_ONE_ARG_FROM_BUILTIN(is_cons, is_cons_inner, struct bool_obj);
+
+struct bool_obj is_null_inner(struct obj *v) {
+  return object_bool_obj_new(v == NULL);
 }
 
-void is_cons_k(struct obj *v, struct obj *k, struct env_obj *env) {
-  _Bool r = v->tag == OBJ_CONS;
+MAKE_ONE_ARG_FROM_BUILTIN(is_null, is_null_inner, struct bool_obj);
 
-  OBJECT_INT_OBJ_NEW(res, r);
+struct bool_obj is_char_inner(struct obj *v) {
+  return object_bool_obj_new(v != NULL && v->tag == OBJ_CHAR);
+}
 
-  call_closure_one(k, res);
+MAKE_ONE_ARG_FROM_BUILTIN(is_char, is_char_inner, struct bool_obj);
+
+struct int_obj char_to_integer_inner(struct obj *v) {
+  if (v == NULL || v->tag != OBJ_CHAR)
+    RAISE_ERROR("Argument to char->integer not a char:", v);
+
+  return object_int_obj_new(((struct char_obj *)v)->val);
+}
+
+MAKE_ONE_ARG_FROM_BUILTIN(char_to_integer, char_to_integer_inner,
+                          struct int_obj);
+
+struct char_obj integer_to_char_inner(struct obj *v) {
+  if (v == NULL || v->tag != OBJ_INT)
+    RAISE_ERROR("Argument to integer->char not an integer:", v);
+
+  int64_t val = ((struct int_obj *)v)->val;
+
+  if (val < 0 || val > 0x10FFFF || (val >= 0xD800 && val <= 0xDFFF))
+    RAISE_ERROR("Argument to integer->char not a unicode scalar value:", v);
+
+  return object_char_obj_new(val);
 }
 
-void is_null_k(struct obj *v, struct obj *k, struct env_obj *env) {
-  _Bool r = v == NULL;
+MAKE_ONE_ARG_FROM_BUILTIN(integer_to_char, integer_to_char_inner,
+                          struct char_obj);
 
-  OBJECT_INT_OBJ_NEW(res, r);
+struct bool_obj char_eq_inner(struct obj *a, struct obj *b) {
+  if (a == NULL || a->tag != OBJ_CHAR)
+    RAISE_ERROR("Left operand to char=? not a char:", a);
+  if (b == NULL || b->tag != OBJ_CHAR)
+    RAISE_ERROR("Right operand to char=? not a char:", b);
+
+  return object_bool_obj_new(((struct char_obj *)a)->val ==
+                             ((struct char_obj *)b)->val);
+}
+
+MAKE_TWO_ARG_FROM_BUILTIN(char_eq, char_eq_inner, struct bool_obj);
+
+struct bool_obj is_symbol_inner(struct obj *v) {
+  return object_bool_obj_new(v != NULL && v->tag == OBJ_SYMBOL);
+}
+
+MAKE_ONE_ARG_FROM_BUILTIN(is_symbol, is_symbol_inner, struct bool_obj);
+
+void symbol_to_string_k(size_t nargs, struct obj **args, struct obj *k,
+                        struct env_obj *env) {
+  struct obj *v = args[0];
+  if (v == NULL || v->tag != OBJ_SYMBOL)
+    RAISE_ERROR("Argument to symbol->string not a symbol:", v);
+
+  struct symbol_obj *sym = (struct symbol_obj *)v;
+  OBJECT_STRING_OBJ_NEW_LEN(res, sym->name, sym->len);
 
   call_closure_one(k, res);
+
+  __builtin_unreachable();
 }
 
-void string_concat_k(struct obj *v, struct obj *k, struct env_obj *env) {
-  OBJECT_ENV_OBJ_NEW(tmp_env, struct unary_env);
-  tmp_env->env[0] = v;
-  struct closure_obj func_2_clos =
-      object_closure_two_new(string_concat_k_2, tmp_env);
+void string_to_symbol_k(size_t nargs, struct obj **args, struct obj *k,
+                        struct env_obj *env) {
+  struct obj *v = args[0];
+  if (v == NULL || v->tag != OBJ_STR)
+    RAISE_ERROR("Argument to string->symbol not a string:", v);
+
+  // the length of a string counts its null byte
+  struct string_obj *str = (struct string_obj *)v;
+  OBJECT_SYMBOL_OBJ_NEW(res, str->buf, str->len - 1);
 
-  call_closure_one(k, (struct obj *)&func_2_clos);
+  call_closure_one(k, res);
 
   __builtin_unreachable();
 }
 
-static char *convert_to_str(struct obj *v) {
+// Returns the bytes of v as a string, the length is written to len as the
+// result may contain nulls
+static char *convert_to_str(struct obj *v, size_t *len) {
   char *res;
 
   switch (v->tag) {
   case OBJ_INT:
     ALLOC_SPRINTF(res, "%c", (int)((struct int_obj *)v)->val);
+    *len = 1;
     break;
-  case OBJ_STR:
-    ALLOC_SPRINTF(res, "%s", ((struct string_obj *)v)->buf);
+  case OBJ_CHAR:
+    res = char_to_string(((struct char_obj *)v)->val);
+    *len = strlen(res);
     break;
+  case OBJ_STR: {
+    struct string_obj *str = (struct string_obj *)v;
+    *len = str->len - 1;
+    res = malloc(*len);
+    memcpy(res, str->buf, *len);
+    break;
+  }
   case OBJ_CELL:
-    res = convert_to_str(((struct cell_obj *)v)->val);
+    res = convert_to_str(((struct cell_obj *)v)->val, len);
     break;
   default:
     RUNTIME_ERROR("Unexpected object tag to convert_to_str: %d", v->tag);
@@ -180,17 +927,37 @@ static char *convert_to_str(struct obj *v) {
   return res;
 }
 
-void string_concat_k_2(struct obj *v, struct obj *k, struct env_obj *env) {
-  char *lhs = convert_to_str(env->env[0]);
-  char *rhs = convert_to_str(v);
+// Whether convert_to_str can convert v
+static bool obj_is_string_like(struct obj *v) {
+  if (v != NULL && v->tag == OBJ_CELL)
+    return obj_is_string_like(((struct cell_obj *)v)->val);
 
-  char *res;
-  ALLOC_SPRINTF(res, "%s%s", lhs, rhs);
+  return v != NULL &&
+         (v->tag == OBJ_INT || v->tag == OBJ_CHAR || v->tag == OBJ_STR);
+}
+
+void string_concat_k(size_t nargs, struct obj **args, struct obj *k,
+                     struct env_obj *env) {
+  if (!obj_is_string_like(args[0]))
+    RAISE_ERROR("Left operand to string-concat not a string, char or integer:",
+                args[0]);
+  if (!obj_is_string_like(args[1]))
+    RAISE_ERROR(
+        "Right operand to string-concat not a string, char or integer:",
+        args[1]);
+
+  size_t lhs_len, rhs_len;
+  char *lhs = convert_to_str(args[0], &lhs_len);
+  char *rhs = convert_to_str(args[1], &rhs_len);
+
+  char *res = malloc(lhs_len + rhs_len);
+  memcpy(res, lhs, lhs_len);
+  memcpy(res + lhs_len, rhs, rhs_len);
 
   free(lhs);
   free(rhs);
 
-  OBJECT_STRING_OBJ_NEW(result_str, res);
+  OBJECT_STRING_OBJ_NEW_LEN(result_str, res, lhs_len + rhs_len);
 
   free(res);
 
@@ -199,13 +966,12 @@ void string_concat_k_2(struct obj *v, struct obj *k, struct env_obj *env) {
   __builtin_unreachable();
 }
 
-struct ht_obj ht_new_inner(struct obj *always_void) {
-  return object_ht_obj_new();
-}
-
-MAKE_ONE_ARG_FROM_BUILTIN(ht_new, ht_new_inner, struct ht_obj);
+MAKE_ZERO_ARG_FROM_BUILTIN(ht_new, object_ht_obj_new, struct ht_obj);
 
 struct obj *ht_set_inner(struct obj *ht_obj, struct obj *k, struct obj *v) {
+  if (ht_obj == NULL || ht_obj->tag != OBJ_HT)
+    RAISE_ERROR("First argument to ht-set! not a hash table:", ht_obj);
+
   struct ht_obj *ht = (struct ht_obj *)ht_obj;
 
   hash_table_obj_insert(ht->ht, k, v);
@@ -215,17 +981,23 @@ struct obj *ht_set_inner(struct obj *ht_obj, struct obj *k, struct obj *v) {
 
 MAKE_THREE_ARG_FROM_BUILTIN_EXPLICIT_RETURN(ht_set, ht_set_inner);
 
-struct int_obj ht_del_inner(struct obj *ht_obj, struct obj *k) {
+struct bool_obj ht_del_inner(struct obj *ht_obj, struct obj *k) {
+  if (ht_obj == NULL || ht_obj->tag != OBJ_HT)
+    RAISE_ERROR("First argument to ht-del! not a hash table:", ht_obj);
+
   struct ht_obj *ht = (struct ht_obj *)ht_obj;
 
   bool ret = hash_table_obj_delete(ht->ht, k);
 
-  return object_int_obj_new(ret);
+  return object_bool_obj_new(ret);
 }
 
-MAKE_TWO_ARG_FROM_BUILTIN(ht_del, ht_del_inner, struct int_obj);
+MAKE_TWO_ARG_FROM_BUILTIN(ht_del, ht_del_inner, struct bool_obj);
 
 struct obj *ht_get_inner(struct obj *ht_obj, struct obj *k) {
+  if (ht_obj == NULL || ht_obj->tag != OBJ_HT)
+    RAISE_ERROR("First argument to ht-get not a hash table:", ht_obj);
+
   struct ht_obj *ht = (struct ht_obj *)ht_obj;
 
   struct obj **ret = hash_table_obj_lookup(ht->ht, k);
@@ -239,6 +1011,9 @@ struct obj *ht_get_inner(struct obj *ht_obj, struct obj *k) {
 MAKE_TWO_ARG_FROM_BUILTIN_EXPLICIT_RETURN(ht_get, ht_get_inner);
 
 struct obj *ht_keys_inner(struct obj *ht_obj) {
+  if (ht_obj == NULL || ht_obj->tag != OBJ_HT)
+    RAISE_ERROR("Argument to ht-keys not a hash table:", ht_obj);
+
   struct ht_obj *ht = (struct ht_obj *)ht_obj;
   struct cons_obj *c = NULL;
 
@@ -253,25 +1028,46 @@ struct obj *ht_keys_inner(struct obj *ht_obj) {
 
 MAKE_ONE_ARG_FROM_BUILTIN_EXPLICIT_RETURN(ht_keys, ht_keys_inner);
 
-struct int_obj eq_inner(struct obj *a, struct obj *b) {
-  return object_int_obj_new(eq_obj_impl(a, b));
+struct bool_obj eq_inner(struct obj *a, struct obj *b) {
+  return object_bool_obj_new(eq_obj_impl(a, b));
 }
 
-MAKE_TWO_ARG_FROM_BUILTIN(eq, eq_inner, struct int_obj);
+MAKE_TWO_ARG_FROM_BUILTIN(eq, eq_inner, struct bool_obj);
 
-struct obj *string_chars_innner(struct obj *string_obj) {
-  struct string_obj *str = (struct string_obj *)string_obj;
-  struct cons_obj *c = NULL;
+// Builds a list of the characters in a string, decoding it as utf-8. The
+// trailing NUL byte isn't part of the string so it's left out.
+void string_chars_k(size_t nargs, struct obj **args, struct obj *k,
+                    struct env_obj *env) {
+  struct obj *v = args[0];
+  if (v == NULL || v->tag != OBJ_STR)
+    RAISE_ERROR("Argument to string-chars not a string:", v);
 
-  for (size_t i = 0; i < str->len; i++) {
-    struct cons_obj *c2 = gc_malloc(sizeof(struct cons_obj));
-    struct int_obj *chr = gc_malloc(sizeof(struct int_obj));
-    *chr = object_int_obj_new(str->buf[str->len - (i + 1)]);
-    *c2 = object_cons_obj_new((struct obj *)chr, (struct obj *)c);
-    c = c2;
+  struct string_obj *str = (struct string_obj *)v;
+  const unsigned char *buf = (const unsigned char *)str->buf;
+  size_t len = str->len - 1;
+  size_t num_chars = 0;
+
+  for (size_t i = 0; i < len; num_chars++) {
+    uint32_t c;
+    i += utf8_decode(&buf[i], len - i, &c);
   }
 
-  return (struct obj *)c;
-}
+  if (num_chars == 0)
+    call_closure_one(k, NULL);
+
+  struct cons_obj *cells = alloca(sizeof(struct cons_obj) * num_chars);
+  struct char_obj *chars = alloca(sizeof(struct char_obj) * num_chars);
 
-MAKE_ONE_ARG_FROM_BUILTIN_EXPLICIT_RETURN(string_chars, string_chars_innner);
+  for (size_t i = 0, n = 0; i < len; n++) {
+    uint32_t c;
+    i += utf8_decode(&buf[i], len - i, &c);
+
+    chars[n] = object_char_obj_new(c);
+    struct obj *next = n + 1 < num_chars ? (struct obj *)&cells[n + 1] : NULL;
+    cells[n] = object_cons_obj_new((struct obj *)&chars[n], next);
+  }
+
+  call_closure_one(k, (struct obj *)cells);
+
+  __builtin_unreachable();
+}
@@ -3,11 +3,15 @@
 
 #include "base.h"
 
-#define DEFINE_ZERO_ARG_FROM_BUILTIN(NAME)                                     \
-  void NAME##_k(struct obj *, struct env_obj *) __attribute__((noreturn))
+// Builtins are closures like any other, they get their arguments as an array
+// and the closure's arity means there are always as many as they expect
+#define DEFINE_BUILTIN(NAME)                                                   \
+  void NAME##_k(size_t, struct obj **, struct obj *, struct env_obj *)         \
+      __attribute__((noreturn))
 
 #define MAKE_ZERO_ARG_FROM_BUILTIN(NAME, INNER, TYPE)                          \
-  void NAME##_k(struct obj *k, struct env_obj *env) {                          \
+  void NAME##_k(size_t nargs, struct obj **args, struct obj *k,                \
+                struct env_obj *env) {                                         \
     TYPE result = (INNER)();                                                   \
                                                                                \
     call_closure_one(k, (struct obj *)&result);                                \
@@ -15,50 +19,30 @@
     __builtin_unreachable();                                                   \
   }
 
-#define DEFINE_ONE_ARG_FROM_BUILTIN(NAME)                                      \
-  void NAME##_k(struct obj *, struct obj *, struct env_obj *)
-
 #define MAKE_ONE_ARG_FROM_BUILTIN(NAME, INNER, TYPE)                           \
-  void NAME##_k(struct obj *v, struct obj *k, struct env_obj *env) {           \
-    TYPE result = (INNER)(v);                                                  \
+  void NAME##_k(size_t nargs, struct obj **args, struct obj *k,                \
+                struct env_obj *env) {                                         \
+    TYPE result = (INNER)(args[0]);                                            \
                                                                                \
     call_closure_one(k, (struct obj *)&result);                                \
                                                                                \
     __builtin_unreachable();                                                   \
   }
+
 #define MAKE_ONE_ARG_FROM_BUILTIN_EXPLICIT_RETURN(NAME, INNER)                 \
-  void NAME##_k(struct obj *v, struct obj *k, struct env_obj *env) {           \
-    struct obj *result = (INNER)(v);                                           \
+  void NAME##_k(size_t nargs, struct obj **args, struct obj *k,                \
+                struct env_obj *env) {                                         \
+    struct obj *result = (INNER)(args[0]);                                     \
                                                                                \
     call_closure_one(k, result);                                               \
                                                                                \
     __builtin_unreachable();                                                   \
   }
 
-#define DEFINE_TWO_ARG_FROM_BUILTIN(NAME)                                      \
-  void NAME##_k(struct obj *, struct obj *, struct env_obj *)                  \
-      __attribute__((noreturn));                                               \
-  void NAME##_k_2(struct obj *, struct obj *, struct env_obj *)                \
-      __attribute__((noreturn))
-
-struct unary_env {
-  struct obj *val;
-};
-
 #define MAKE_TWO_ARG_FROM_BUILTIN(NAME, INNER, TYPE)                           \
-  void NAME##_k(struct obj *v, struct obj *k, struct env_obj *env) {           \
-    OBJECT_ENV_OBJ_NEW(tmp_env, struct unary_env);                             \
-    tmp_env->env[0] = v;                                                       \
-    struct closure_obj func_2_clos =                                           \
-        object_closure_two_new(NAME##_k_2, tmp_env);                           \
-                                                                               \
-    call_closure_one(k, (struct obj *)&func_2_clos);                           \
-                                                                               \
-    __builtin_unreachable();                                                   \
-  }                                                                            \
-  void NAME##_k_2(struct obj *v, struct obj *k, struct env_obj *env) {         \
-                                                                               \
-    TYPE result = (INNER)(env->env[0], v);                                     \
+  void NAME##_k(size_t nargs, struct obj **args, struct obj *k,                \
+                struct env_obj *env) {                                         \
+    TYPE result = (INNER)(args[0], args[1]);                                   \
                                                                                \
     call_closure_one(k, (struct obj *)&result);                                \
                                                                                \
@@ -66,59 +50,19 @@ struct unary_env {
   }
 
 #define MAKE_TWO_ARG_FROM_BUILTIN_EXPLICIT_RETURN(NAME, INNER)                 \
-  void NAME##_k(struct obj *v, struct obj *k, struct env_obj *env) {           \
-    OBJECT_ENV_OBJ_NEW(tmp_env, struct unary_env);                             \
-    tmp_env->env[0] = v;                                                       \
-    struct closure_obj func_2_clos =                                           \
-        object_closure_two_new(NAME##_k_2, tmp_env);                           \
-                                                                               \
-    call_closure_one(k, (struct obj *)&func_2_clos);                           \
-                                                                               \
-    __builtin_unreachable();                                                   \
-  }                                                                            \
-  void NAME##_k_2(struct obj *v, struct obj *k, struct env_obj *env) {         \
-                                                                               \
-    struct obj *result = (INNER)(env->env[0], v);                              \
+  void NAME##_k(size_t nargs, struct obj **args, struct obj *k,                \
+                struct env_obj *env) {                                         \
+    struct obj *result = (INNER)(args[0], args[1]);                            \
                                                                                \
     call_closure_one(k, result);                                               \
                                                                                \
     __builtin_unreachable();                                                   \
   }
 
-#define DEFINE_THREE_ARG_FROM_BUILTIN(NAME)                                    \
-  void NAME##_k(struct obj *, struct obj *, struct env_obj *)                  \
-      __attribute__((noreturn));                                               \
-  void NAME##_k_2(struct obj *, struct obj *, struct env_obj *)                \
-      __attribute__((noreturn));                                               \
-  void NAME##_k_3(struct obj *, struct obj *, struct env_obj *)                \
-      __attribute__((noreturn))
-
-struct binary_env {
-  struct obj *a;
-  struct obj *b;
-};
-
 #define MAKE_THREE_ARG_FROM_BUILTIN_EXPLICIT_RETURN(NAME, INNER)               \
-  void NAME##_k(struct obj *v, struct obj *k, struct env_obj *env) {           \
-    OBJECT_ENV_OBJ_NEW(tmp_env, struct binary_env);                            \
-    tmp_env->env[0] = v;                                                       \
-    struct closure_obj func_2_clos =                                           \
-        object_closure_two_new(NAME##_k_2, tmp_env);                           \
-                                                                               \
-    call_closure_one(k, (struct obj *)&func_2_clos);                           \
-                                                                               \
-    __builtin_unreachable();                                                   \
-  }                                                                            \
-  void NAME##_k_2(struct obj *v, struct obj *k, struct env_obj *env) {         \
-    env->env[1] = v;                                                           \
-    struct closure_obj func_3_clos = object_closure_two_new(NAME##_k_3, env);  \
-                                                                               \
-    call_closure_one(k, (struct obj *)&func_3_clos);                           \
-                                                                               \
-    __builtin_unreachable();                                                   \
-  }                                                                            \
-  void NAME##_k_3(struct obj *v, struct obj *k, struct env_obj *env) {         \
-    struct obj *result = (INNER)(env->env[0], env->env[1], v);                 \
+  void NAME##_k(size_t nargs, struct obj **args, struct obj *k,                \
+                struct env_obj *env) {                                         \
+    struct obj *result = (INNER)(args[0], args[1], args[2]);                   \
                                                                                \
     call_closure_one(k, result);                                               \
                                                                                \
@@ -126,41 +70,126 @@ struct binary_env {
   }
 
 // builtin operations
-DEFINE_TWO_ARG_FROM_BUILTIN(add);
-DEFINE_TWO_ARG_FROM_BUILTIN(sub);
-DEFINE_TWO_ARG_FROM_BUILTIN(mul);
-DEFINE_TWO_ARG_FROM_BUILTIN(div);
-DEFINE_TWO_ARG_FROM_BUILTIN(mod);
-DEFINE_TWO_ARG_FROM_BUILTIN(xor);
-DEFINE_TWO_ARG_FROM_BUILTIN(lt);
-DEFINE_TWO_ARG_FROM_BUILTIN(leq);
-DEFINE_TWO_ARG_FROM_BUILTIN(gt);
-DEFINE_TWO_ARG_FROM_BUILTIN(geq);
+DEFINE_BUILTIN(add);
+DEFINE_BUILTIN(sub);
+DEFINE_BUILTIN(mul);
+DEFINE_BUILTIN(div);
+DEFINE_BUILTIN(mod);
+DEFINE_BUILTIN(xor);
+DEFINE_BUILTIN(lt);
+DEFINE_BUILTIN(leq);
+DEFINE_BUILTIN(gt);
+DEFINE_BUILTIN(geq);
+
+DEFINE_BUILTIN(cons);
+DEFINE_BUILTIN(append);
+
+DEFINE_BUILTIN(string_concat);
+DEFINE_BUILTIN(string_chars);
+
+DEFINE_BUILTIN(exit);
+DEFINE_BUILTIN(call_cc);
+DEFINE_BUILTIN(dynamic_wind);
+
+DEFINE_BUILTIN(with_exception_handler);
+DEFINE_BUILTIN(raise);
+DEFINE_BUILTIN(raise_continuable);
+DEFINE_BUILTIN(error);
+DEFINE_BUILTIN(is_error_object);
+DEFINE_BUILTIN(error_object_message);
+DEFINE_BUILTIN(error_object_irritants);
+
+DEFINE_BUILTIN(to_string);
+DEFINE_BUILTIN(display);
+
+DEFINE_BUILTIN(is_cons);
+DEFINE_BUILTIN(is_null);
+DEFINE_BUILTIN(car);
+DEFINE_BUILTIN(cdr);
+
+DEFINE_BUILTIN(ht_new);
+DEFINE_BUILTIN(ht_set);
+DEFINE_BUILTIN(ht_del);
+DEFINE_BUILTIN(ht_get);
+DEFINE_BUILTIN(ht_keys);
+
+DEFINE_BUILTIN(eq);
+
+DEFINE_BUILTIN(is_char);
+DEFINE_BUILTIN(char_to_integer);
+DEFINE_BUILTIN(integer_to_char);
+DEFINE_BUILTIN(char_eq);
+
+DEFINE_BUILTIN(is_symbol);
+DEFINE_BUILTIN(symbol_to_string);
+DEFINE_BUILTIN(string_to_symbol);
 
-DEFINE_TWO_ARG_FROM_BUILTIN(cons);
-
-DEFINE_TWO_ARG_FROM_BUILTIN(string_concat);
-DEFINE_ONE_ARG_FROM_BUILTIN(string_chars);
-
-DEFINE_ZERO_ARG_FROM_BUILTIN(exit);
-
-DEFINE_ONE_ARG_FROM_BUILTIN(to_string);
-DEFINE_ONE_ARG_FROM_BUILTIN(display);
-
-DEFINE_ONE_ARG_FROM_BUILTIN(is_cons);
-DEFINE_ONE_ARG_FROM_BUILTIN(is_null);
-DEFINE_ONE_ARG_FROM_BUILTIN(car);
-DEFINE_ONE_ARG_FROM_BUILTIN(cdr);
-
-DEFINE_ONE_ARG_FROM_BUILTIN(ht_new);
-DEFINE_THREE_ARG_FROM_BUILTIN(ht_set);
-DEFINE_TWO_ARG_FROM_BUILTIN(ht_del);
-DEFINE_TWO_ARG_FROM_BUILTIN(ht_get);
-DEFINE_TWO_ARG_FROM_BUILTIN(ht_get);
-DEFINE_ONE_ARG_FROM_BUILTIN(ht_keys);
+_Bool obj_is_truthy(struct obj *);
 
-DEFINE_TWO_ARG_FROM_BUILTIN(eq);
+// the result of an arithmetic builtin, it's an int if both operands were
+// ints and a float otherwise
+union num_obj {
+  struct obj base;
+  struct int_obj int_obj;
+  struct float_obj float_obj;
+};
 
-_Bool obj_is_truthy(struct obj *);
+// The builtins that always return straight away and never call a procedure,
+// the compiler calls these directly instead of passing them a continuation
+union num_obj object_num_obj_add(struct obj *, struct obj *);
+union num_obj object_num_obj_sub(struct obj *, struct obj *);
+union num_obj object_num_obj_mul(struct obj *, struct obj *);
+union num_obj object_num_obj_div(struct obj *, struct obj *);
+struct int_obj object_int_obj_mod(struct obj *, struct obj *);
+struct int_obj object_int_obj_xor(struct obj *, struct obj *);
+struct bool_obj object_num_obj_lt(struct obj *, struct obj *);
+struct bool_obj object_num_obj_leq(struct obj *, struct obj *);
+struct bool_obj object_num_obj_gt(struct obj *, struct obj *);
+struct bool_obj object_num_obj_geq(struct obj *, struct obj *);
+
+struct bool_obj is_cons_inner(struct obj *);
+struct bool_obj is_null_inner(struct obj *);
+struct obj *car_inner(struct obj *);
+struct obj *cdr_inner(struct obj *);
+
+struct obj *ht_set_inner(struct obj *, struct obj *, struct obj *);
+struct bool_obj ht_del_inner(struct obj *, struct obj *);
+struct obj *ht_get_inner(struct obj *, struct obj *);
+
+struct bool_obj eq_inner(struct obj *, struct obj *);
+
+struct bool_obj is_char_inner(struct obj *);
+struct int_obj char_to_integer_inner(struct obj *);
+struct char_obj integer_to_char_inner(struct obj *);
+struct bool_obj char_eq_inner(struct obj *, struct obj *);
+
+struct bool_obj is_symbol_inner(struct obj *);
+
+struct obj *display_inner(struct obj *);
+
+// Put the result of a builtin called directly, which returns an object of
+// type TYPE by value, on the stack
+#define OBJECT_BUILTIN_RESULT_NEW(NAME, TYPE, CALL)                            \
+  struct obj *(NAME);                                                          \
+  do {                                                                         \
+    TYPE *new_obj = alloca(sizeof(TYPE));                                      \
+    *new_obj = (CALL);                                                         \
+    TOUCH_OBJECT(new_obj, "builtin_result_new");                               \
+    (NAME) = (struct obj *)new_obj;                                            \
+  } while (0)
+
+// Raise an error object as `(error message irritant...)` would, the message is
+// copied so it can be on the stack. A handler can't return to the raise.
+void raise_error(const char *, size_t, struct obj **) __attribute__((noreturn));
+
+#define RAISE_ERROR(MESSAGE, ...)                                              \
+  do {                                                                         \
+    struct obj *irritants[] = {__VA_ARGS__};                                   \
+    raise_error((MESSAGE), sizeof(irritants) / sizeof(irritants[0]),           \
+                irritants);                                                    \
+  } while (0)
+
+// how `display` shows an object, the result is malloc'd
+char *obj_to_string_internal(struct obj *);
 
 #endif // SOMESCHEME_BUILTIN_H
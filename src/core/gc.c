@@ -38,15 +38,30 @@ static struct gc_funcs gc_func_map[] = {
     [OBJ_INT] = (struct gc_funcs){.toheap = toheap_int_obj,
                                   .mark = gc_mark_noop,
                                   .free = gc_free_noop},
+    [OBJ_BOOL] = (struct gc_funcs){.toheap = toheap_bool_obj,
+                                   .mark = gc_mark_noop,
+                                   .free = gc_free_noop},
+    [OBJ_FLOAT] = (struct gc_funcs){.toheap = toheap_float_obj,
+                                    .mark = gc_mark_noop,
+                                    .free = gc_free_noop},
+    [OBJ_CHAR] = (struct gc_funcs){.toheap = toheap_char_obj,
+                                   .mark = gc_mark_noop,
+                                   .free = gc_free_noop},
     [OBJ_STR] =
         (struct gc_funcs){
             .toheap = toheap_string_obj,
             .mark = gc_mark_noop,
             .free = gc_free_noop,
         },
+    [OBJ_SYMBOL] = (struct gc_funcs){.toheap = toheap_symbol_obj,
+                                     .mark = gc_mark_noop,
+                                     .free = gc_free_noop},
     [OBJ_HT] = (struct gc_funcs){.toheap = toheap_ht,
                                  .mark = mark_ht,
                                  .free = free_ht},
+    [OBJ_ERROR] = (struct gc_funcs){.toheap = toheap_error,
+                                    .mark = mark_error,
+                                    .free = gc_free_noop},
 };
 
 // This does nothing, the gc will call free() on the object if it was heap
@@ -157,6 +172,43 @@ void mark_cons(struct obj *cons_obj, struct gc_context *ctx) {
   }
 }
 
+struct obj *toheap_error(struct obj *error_obj, struct gc_context *ctx) {
+  struct error_obj *error = (struct error_obj *)error_obj;
+
+  if (error->base.on_stack) {
+    TOUCH_OBJECT(error, "toheap_error");
+    struct error_obj *heap_error = gc_malloc(sizeof(struct error_obj));
+    *heap_error = *error;
+    error = heap_error;
+  }
+
+  if (error->message) {
+    struct ptr_toupdate_pair p = {.toupdate = (struct obj **)&error->message,
+                                  .on_stack = (struct obj *)error->message};
+    queue_ptr_toupdate_pair_enqueue(&ctx->pointers_toupdate, p);
+  }
+
+  if (error->irritants) {
+    struct ptr_toupdate_pair p = {.toupdate = (struct obj **)&error->irritants,
+                                  .on_stack = (struct obj *)error->irritants};
+    queue_ptr_toupdate_pair_enqueue(&ctx->pointers_toupdate, p);
+  }
+
+  return (struct obj *)error;
+}
+
+void mark_error(struct obj *error_obj, struct gc_context *ctx) {
+  struct error_obj *error = (struct error_obj *)error_obj;
+
+  if (error->message) {
+    maybe_mark_grey_and_queue(ctx, error->message);
+  }
+
+  if (error->irritants) {
+    maybe_mark_grey_and_queue(ctx, error->irritants);
+  }
+}
+
 struct obj *toheap_cell(struct obj *cell_obj, struct gc_context *ctx) {
   struct cell_obj *cell = (struct cell_obj *)cell_obj;
 
@@ -262,6 +314,45 @@ struct obj *toheap_int_obj(struct obj *obj, struct gc_context *ctx) {
   return (struct obj *)intobj;
 }
 
+struct obj *toheap_bool_obj(struct obj *obj, struct gc_context *ctx) {
+  struct bool_obj *boolobj = (struct bool_obj *)obj;
+
+  if (obj->on_stack) {
+    TOUCH_OBJECT(obj, "toheap_bool");
+    struct bool_obj *heap_boolobj = gc_malloc(sizeof(struct bool_obj));
+    memcpy(heap_boolobj, boolobj, sizeof(struct bool_obj));
+    boolobj = heap_boolobj;
+  }
+
+  return (struct obj *)boolobj;
+}
+
+struct obj *toheap_float_obj(struct obj *obj, struct gc_context *ctx) {
+  struct float_obj *floatobj = (struct float_obj *)obj;
+
+  if (obj->on_stack) {
+    TOUCH_OBJECT(obj, "toheap_float");
+    struct float_obj *heap_floatobj = gc_malloc(sizeof(struct float_obj));
+    memcpy(heap_floatobj, floatobj, sizeof(struct float_obj));
+    floatobj = heap_floatobj;
+  }
+
+  return (struct obj *)floatobj;
+}
+
+struct obj *toheap_char_obj(struct obj *obj, struct gc_context *ctx) {
+  struct char_obj *charobj = (struct char_obj *)obj;
+
+  if (obj->on_stack) {
+    TOUCH_OBJECT(obj, "toheap_char");
+    struct char_obj *heap_charobj = gc_malloc(sizeof(struct char_obj));
+    memcpy(heap_charobj, charobj, sizeof(struct char_obj));
+    charobj = heap_charobj;
+  }
+
+  return (struct obj *)charobj;
+}
+
 struct obj *toheap_string_obj(struct obj *obj, struct gc_context *ctx) {
   struct string_obj *strobj = (struct string_obj *)obj;
 
@@ -279,6 +370,11 @@ struct obj *toheap_string_obj(struct obj *obj, struct gc_context *ctx) {
   return (struct obj *)strobj;
 }
 
+// symbols are interned outside of the gc heap and never move
+struct obj *toheap_symbol_obj(struct obj *obj, struct gc_context *ctx) {
+  return obj;
+}
+
 struct gc_context gc_make_context(void) {
   return (struct gc_context){
       .grey_nodes = queue_gc_grey_nodes_new(10),
@@ -339,20 +435,19 @@ void gc_minor(struct gc_context *ctx, struct thunk *thnk) {
   // initially mark the closure and it's arguments to be applied
   thnk->closr = (struct closure_obj *)gc_toheap(ctx, (struct obj *)thnk->closr);
 
-  switch (thnk->closr->size) {
-  case CLOSURE_ONE:
-    if (thnk->one.rand != NULL) {
-      thnk->one.rand = gc_toheap(ctx, thnk->one.rand);
+  for (size_t i = 0; i < thnk->nargs; i++) {
+    if (thnk->rands[i] != NULL) {
+      thnk->rands[i] = gc_toheap(ctx, thnk->rands[i]);
     }
-    break;
-  case CLOSURE_TWO:
-    if (thnk->two.rand != NULL) {
-      thnk->two.rand = gc_toheap(ctx, thnk->two.rand);
-    }
-    if (thnk->two.cont != NULL) {
-      thnk->two.cont = gc_toheap(ctx, thnk->two.cont);
-    }
-    break;
+  }
+  if (thnk->cont != NULL) {
+    thnk->cont = gc_toheap(ctx, thnk->cont);
+  }
+  if (wind_stack != NULL) {
+    wind_stack = gc_toheap(ctx, wind_stack);
+  }
+  if (handler_stack != NULL) {
+    handler_stack = gc_toheap(ctx, handler_stack);
   }
 
   // work through each pointer that needs to be updated
@@ -390,21 +485,22 @@ void gc_major(struct gc_context *ctx, struct thunk *thnk) {
   gc_mark_obj(ctx, &thnk->closr->base);
   num_marked++;
 
-  switch (thnk->closr->size) {
-  case CLOSURE_ONE:
-    if (thnk->one.rand)
-      gc_mark_obj(ctx, thnk->one.rand);
-    num_marked++;
-    break;
-  case CLOSURE_TWO:
-    if (thnk->two.rand)
-      gc_mark_obj(ctx, thnk->two.rand);
-    if (thnk->two.cont)
-      gc_mark_obj(ctx, thnk->two.cont);
+  for (size_t i = 0; i < thnk->nargs; i++) {
+    if (thnk->rands[i])
+      gc_mark_obj(ctx, thnk->rands[i]);
     num_marked++;
-    num_marked++;
-    break;
   }
+  if (thnk->cont)
+    gc_mark_obj(ctx, thnk->cont);
+  num_marked++;
+
+  if (wind_stack)
+    gc_mark_obj(ctx, wind_stack);
+  num_marked++;
+
+  if (handler_stack)
+    gc_mark_obj(ctx, handler_stack);
+  num_marked++;
 
   while (queue_gc_grey_nodes_len(&ctx->grey_nodes) > 0) {
     struct obj *next_obj = queue_gc_grey_nodes_dequeue(&ctx->grey_nodes);
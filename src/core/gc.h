@@ -67,14 +67,25 @@ void mark_env(struct obj *, struct gc_context *);
 
 struct obj *toheap_int_obj(struct obj *, struct gc_context *);
 
+struct obj *toheap_bool_obj(struct obj *, struct gc_context *);
+
+struct obj *toheap_float_obj(struct obj *, struct gc_context *);
+
+struct obj *toheap_char_obj(struct obj *, struct gc_context *);
+
 struct obj *toheap_string_obj(struct obj *, struct gc_context *);
 
+struct obj *toheap_symbol_obj(struct obj *, struct gc_context *);
+
 struct obj *toheap_cell(struct obj *, struct gc_context *);
 void mark_cell(struct obj *, struct gc_context *);
 
 struct obj *toheap_cons(struct obj *, struct gc_context *);
 void mark_cons(struct obj *, struct gc_context *);
 
+struct obj *toheap_error(struct obj *, struct gc_context *);
+void mark_error(struct obj *, struct gc_context *);
+
 struct obj *toheap_ht(struct obj *, struct gc_context *);
 void mark_ht(struct obj *, struct gc_context *);
 void free_ht(struct obj *);
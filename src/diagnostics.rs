@@ -0,0 +1,343 @@
+use std::io::Result;
+
+use termcolor::{Color, ColorSpec, WriteColor};
+
+/// A byte range into the source text of the program being compiled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    /// The smallest span covering both `self` and `other`
+    pub fn to(self, other: Span) -> Span {
+        Span::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+impl<'i> From<pest::Span<'i>> for Span {
+    fn from(span: pest::Span<'i>) -> Self {
+        Span::new(span.start(), span.end())
+    }
+}
+
+/// The program text and the name it should be reported under.
+pub struct SourceFile<'a> {
+    pub name: String,
+    pub text: &'a str,
+}
+
+impl<'a> SourceFile<'a> {
+    pub fn new(name: impl Into<String>, text: &'a str) -> Self {
+        Self {
+            name: name.into(),
+            text,
+        }
+    }
+
+    /// 1-based line and column of a byte offset
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let offset = offset.min(self.text.len());
+        let before = &self.text[..offset];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        let col = self.text[line_start..offset].chars().count() + 1;
+
+        (line, col)
+    }
+
+    /// The text of the (1-based) line, without its line terminator
+    fn line_text(&self, line: usize) -> &'a str {
+        self.text
+            .split('\n')
+            .nth(line - 1)
+            .unwrap_or("")
+            .trim_end_matches('\r')
+    }
+
+    /// Format a span as `file:line:col`
+    pub fn location(&self, span: Span) -> String {
+        let (line, col) = self.line_col(span.start);
+        format!("{}:{}:{}", self.name, line, col)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone)]
+struct Label {
+    span: Span,
+    message: String,
+    primary: bool,
+}
+
+#[derive(Debug, Clone)]
+enum Note {
+    Note(String),
+    Help(String),
+}
+
+/// A message about the program, rendered rustc-style with the offending source
+/// underlined.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    severity: Severity,
+    message: String,
+    labels: Vec<Label>,
+    notes: Vec<Note>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            message: message.into(),
+            labels: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, message)
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, message)
+    }
+
+    /// Underline `span` with `^` and the given explanation
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+            primary: true,
+        });
+        self
+    }
+
+    /// Underline `span` with `-`, for spans that give context to the primary one
+    pub fn with_secondary_label(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+            primary: false,
+        });
+        self
+    }
+
+    pub fn with_note(mut self, message: impl Into<String>) -> Self {
+        self.notes.push(Note::Note(message.into()));
+        self
+    }
+
+    pub fn with_help(mut self, message: impl Into<String>) -> Self {
+        self.notes.push(Note::Help(message.into()));
+        self
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn emit(&self, source: &SourceFile, mut out: impl WriteColor) -> Result<()> {
+        let (header, header_colour) = match self.severity {
+            Severity::Error => ("error", Color::Red),
+            Severity::Warning => ("warning", Color::Yellow),
+        };
+        let gutter_colour = ColorSpec::new()
+            .set_fg(Some(Color::Blue))
+            .set_bold(true)
+            .clone();
+
+        out.set_color(ColorSpec::new().set_fg(Some(header_colour)).set_bold(true))?;
+        write!(out, "{}", header)?;
+        out.set_color(ColorSpec::new().set_bold(true))?;
+        writeln!(out, ": {}", self.message)?;
+        out.reset()?;
+
+        let mut labels = self.labels.iter().collect::<Vec<_>>();
+        labels.sort_by_key(|l| (l.span.start, !l.primary));
+
+        let last_line = labels
+            .iter()
+            .map(|l| source.line_col(l.span.start).0)
+            .max()
+            .unwrap_or(1);
+        let gutter_width = last_line.to_string().len();
+
+        if let Some(primary) = labels.iter().find(|l| l.primary).or_else(|| labels.first()) {
+            out.set_color(&gutter_colour)?;
+            write!(out, "{:w$}--> ", "", w = gutter_width)?;
+            out.reset()?;
+            writeln!(out, "{}", source.location(primary.span))?;
+        }
+
+        if !labels.is_empty() {
+            out.set_color(&gutter_colour)?;
+            writeln!(out, "{:w$} |", "", w = gutter_width)?;
+            out.reset()?;
+        }
+
+        let mut lines = labels.iter().peekable();
+
+        // each line is printed once, with the underlines of all of its labels
+        while let Some(&first) = lines.next() {
+            let line = source.line_col(first.span.start).0;
+            let mut group = vec![first];
+            while let Some(&label) = lines.next_if(|l| source.line_col(l.span.start).0 == line) {
+                group.push(label);
+            }
+
+            let text = source.line_text(line);
+            let columns = group
+                .iter()
+                .map(|label| {
+                    let col = source.line_col(label.span.start).1 - 1;
+                    let remaining_on_line = text.chars().count().saturating_sub(col);
+                    let width = source.text
+                        [label.span.start..label.span.end.min(source.text.len())]
+                        .chars()
+                        .take_while(|&c| c != '\n')
+                        .count()
+                        .min(remaining_on_line)
+                        .max(1);
+
+                    (col, width)
+                })
+                .collect::<Vec<_>>();
+            let colour = |label: &Label| {
+                if label.primary {
+                    header_colour
+                } else {
+                    Color::Blue
+                }
+            };
+
+            out.set_color(&gutter_colour)?;
+            write!(out, "{:>w$} | ", line, w = gutter_width)?;
+            out.reset()?;
+            writeln!(out, "{}", text)?;
+
+            // where labels overlap the earlier one keeps its underline
+            let mut underlines = Vec::new();
+            let mut end = 0;
+            for (label, &(col, width)) in group.iter().zip(&columns) {
+                let start = col.max(end);
+                end = end.max(col + width);
+                if start < end {
+                    let marker = if label.primary { "^" } else { "-" };
+                    underlines.push((start, marker.repeat(end - start), colour(label)));
+                }
+            }
+
+            // the last label's message goes after the underlines, the others
+            // hang below their underlines
+            let (last, rest) = group.split_last().unwrap();
+            if !last.message.is_empty() {
+                underlines.push((end + 1, last.message.clone(), colour(last)));
+            }
+
+            out.set_color(&gutter_colour)?;
+            write!(out, "{:w$} | ", "", w = gutter_width)?;
+            write_row(&mut out, text, &underlines)?;
+
+            let hanging = rest
+                .iter()
+                .zip(&columns)
+                .filter(|(label, _)| !label.message.is_empty())
+                .map(|(label, &(col, _))| (col, label))
+                .collect::<Vec<_>>();
+
+            if !hanging.is_empty() {
+                let bars = |labels: &[(usize, &&Label)]| {
+                    labels
+                        .iter()
+                        .map(|&(col, label)| (col, "|".to_owned(), colour(label)))
+                        .collect::<Vec<_>>()
+                };
+
+                out.set_color(&gutter_colour)?;
+                write!(out, "{:w$} | ", "", w = gutter_width)?;
+                write_row(&mut out, text, &bars(&hanging))?;
+
+                for i in (0..hanging.len()).rev() {
+                    let (col, label) = hanging[i];
+                    let mut row = bars(&hanging[..i]);
+                    row.push((col, label.message.clone(), colour(label)));
+
+                    out.set_color(&gutter_colour)?;
+                    write!(out, "{:w$} | ", "", w = gutter_width)?;
+                    write_row(&mut out, text, &row)?;
+                }
+            }
+        }
+
+        if !self.notes.is_empty() && !labels.is_empty() {
+            out.set_color(&gutter_colour)?;
+            writeln!(out, "{:w$} |", "", w = gutter_width)?;
+            out.reset()?;
+        }
+
+        for note in &self.notes {
+            let (kind, message) = match note {
+                Note::Note(m) => ("note", m),
+                Note::Help(m) => ("help", m),
+            };
+
+            out.set_color(&gutter_colour)?;
+            write!(out, "{:w$} = ", "", w = gutter_width)?;
+            out.set_color(ColorSpec::new().set_bold(true))?;
+            write!(out, "{}", kind)?;
+            out.reset()?;
+            writeln!(out, ": {}", message)?;
+        }
+
+        writeln!(out)?;
+
+        Ok(())
+    }
+}
+
+/// Write `segments` under the source line `text`, each at its column in its
+/// own colour, keeping tabs in the padding so they line up with the source
+fn write_row(
+    out: &mut impl WriteColor,
+    text: &str,
+    segments: &[(usize, String, Color)],
+) -> Result<()> {
+    let mut written = 0;
+    let mut current = None;
+
+    for (col, segment, colour) in segments {
+        let padding = text
+            .chars()
+            .chain(std::iter::repeat(' '))
+            .skip(written)
+            .take(col.saturating_sub(written))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect::<String>();
+
+        if current != Some(colour) {
+            out.set_color(ColorSpec::new().set_fg(Some(*colour)).set_bold(true))?;
+            current = Some(colour);
+        }
+        write!(out, "{}{}", padding, segment)?;
+        written += padding.chars().count() + segment.chars().count();
+    }
+
+    out.reset()?;
+    writeln!(out)
+}
@@ -0,0 +1,252 @@
+use std::fmt;
+
+use crate::diagnostics::{Diagnostic, Span};
+use crate::parse::is_hex_character;
+
+/// Errors that stop a program from being compiled.
+///
+/// Everything except `Internal` is a mistake in the program being compiled,
+/// `Internal` means the compiler itself is broken.
+#[derive(Debug, Clone)]
+pub enum CompileError {
+    Syntax {
+        message: String,
+        span: Span,
+    },
+    UnboundVariable {
+        name: String,
+        span: Span,
+        suggestion: Option<String>,
+    },
+    UndefinedSet {
+        name: String,
+        span: Span,
+        suggestion: Option<String>,
+    },
+    IntegerOutOfRange {
+        literal: String,
+        span: Span,
+    },
+    /// A token that starts like a number but isn't one
+    InvalidNumber {
+        literal: String,
+        span: Span,
+    },
+    InvalidCharacter {
+        literal: String,
+        span: Span,
+    },
+    /// A `\uXXXX` escape in a string that isn't a unicode scalar value
+    InvalidStringEscape {
+        escape: String,
+        span: Span,
+    },
+    UnknownBuiltin {
+        name: String,
+    },
+    NoMatchingRule {
+        name: String,
+        span: Span,
+    },
+    /// The transformer of a `define-macro` failed while running at compile
+    /// time
+    MacroFailed {
+        name: String,
+        message: String,
+        /// Where the transformer failed
+        span: Span,
+        /// The use of the macro being expanded
+        usage: Span,
+    },
+    Internal(String),
+}
+
+impl CompileError {
+    pub fn is_internal(&self) -> bool {
+        matches!(self, CompileError::Internal(_))
+    }
+
+    /// Where in the program this error is, if it is about one place
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            CompileError::Syntax { span, .. }
+            | CompileError::UnboundVariable { span, .. }
+            | CompileError::UndefinedSet { span, .. }
+            | CompileError::IntegerOutOfRange { span, .. }
+            | CompileError::InvalidNumber { span, .. }
+            | CompileError::InvalidCharacter { span, .. }
+            | CompileError::InvalidStringEscape { span, .. }
+            | CompileError::NoMatchingRule { span, .. }
+            | CompileError::MacroFailed { span, .. } => Some(*span),
+            CompileError::UnknownBuiltin { .. } | CompileError::Internal(_) => None,
+        }
+    }
+
+    /// Move the span of this error, for errors found in text that doesn't
+    /// come straight from the program, like the expansion of a macro
+    pub fn map_span(self, f: impl Fn(Span) -> Span) -> CompileError {
+        match self {
+            CompileError::Syntax { message, span } => CompileError::Syntax {
+                message,
+                span: f(span),
+            },
+            CompileError::UnboundVariable {
+                name,
+                span,
+                suggestion,
+            } => CompileError::UnboundVariable {
+                name,
+                span: f(span),
+                suggestion,
+            },
+            CompileError::UndefinedSet {
+                name,
+                span,
+                suggestion,
+            } => CompileError::UndefinedSet {
+                name,
+                span: f(span),
+                suggestion,
+            },
+            CompileError::IntegerOutOfRange { literal, span } => CompileError::IntegerOutOfRange {
+                literal,
+                span: f(span),
+            },
+            CompileError::InvalidNumber { literal, span } => CompileError::InvalidNumber {
+                literal,
+                span: f(span),
+            },
+            CompileError::InvalidCharacter { literal, span } => CompileError::InvalidCharacter {
+                literal,
+                span: f(span),
+            },
+            CompileError::InvalidStringEscape { escape, span } => {
+                CompileError::InvalidStringEscape {
+                    escape,
+                    span: f(span),
+                }
+            }
+            CompileError::NoMatchingRule { name, span } => CompileError::NoMatchingRule {
+                name,
+                span: f(span),
+            },
+            CompileError::MacroFailed {
+                name,
+                message,
+                span,
+                usage,
+            } => CompileError::MacroFailed {
+                name,
+                message,
+                span: f(span),
+                usage: f(usage),
+            },
+            CompileError::UnknownBuiltin { .. } | CompileError::Internal(_) => self,
+        }
+    }
+
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        match self {
+            CompileError::Syntax { message, span } => {
+                Diagnostic::error("syntax error").with_label(*span, message.clone())
+            }
+            CompileError::UnboundVariable {
+                name,
+                span,
+                suggestion,
+            } => with_suggestion(
+                Diagnostic::error(format!("unbound variable `{}`", name))
+                    .with_label(*span, "not found in this scope"),
+                suggestion,
+            ),
+            CompileError::UndefinedSet {
+                name,
+                span,
+                suggestion,
+            } => with_suggestion(
+                Diagnostic::error(format!("cannot `set!` undefined variable `{}`", name))
+                    .with_label(*span, "assigned here")
+                    .with_note("`set!` can only change variables that already exist, use `define` to create one"),
+                suggestion,
+            ),
+            CompileError::IntegerOutOfRange { literal, span } => {
+                Diagnostic::error(format!("integer literal `{}` is out of range", literal))
+                    .with_label(*span, "doesn't fit in a 64 bit integer")
+                    .with_note(format!(
+                        "integers must be between {} and {}",
+                        i64::MIN,
+                        i64::MAX
+                    ))
+            }
+            CompileError::InvalidNumber { literal, span } => {
+                Diagnostic::error(format!("invalid number literal `{}`", literal))
+                    .with_label(*span, "not a valid number")
+                    .with_note("numbers are integers like `42`, `-7` or `#xff`, or floats like `1.5` or `2e10`")
+            }
+            CompileError::InvalidCharacter { literal, span }
+                if is_hex_character(&literal[2..]) =>
+            {
+                Diagnostic::error(format!("invalid character `{}`", literal))
+                    .with_label(*span, "not a unicode scalar value")
+                    .with_note("characters must be between 0 and 10FFFF in hex, excluding surrogates")
+            }
+            CompileError::InvalidCharacter { literal, span } => {
+                Diagnostic::error(format!("invalid character `{}`", literal))
+                    .with_label(*span, "unknown character name")
+                    .with_note(
+                        "characters are a single character, a name like `#\\space`, or a hex code like `#\\x41`",
+                    )
+            }
+            CompileError::InvalidStringEscape { escape, span } => {
+                Diagnostic::error(format!("invalid string escape `{}`", escape))
+                    .with_label(
+                        *span,
+                        format!(
+                            "`{}` is not a Unicode scalar value (surrogates and values above 10FFFF are not allowed)",
+                            escape
+                        ),
+                    )
+            }
+            CompileError::UnknownBuiltin { name } => {
+                Diagnostic::error(format!("unknown builtin `{}`", name))
+            }
+            CompileError::NoMatchingRule { name, span } => {
+                Diagnostic::error(format!("no rule of the macro `{}` matches this use", name))
+                    .with_label(*span, "doesn't match any of the patterns")
+            }
+            CompileError::MacroFailed {
+                name,
+                message,
+                span,
+                usage,
+            } => Diagnostic::error(format!("expanding the macro `{}` failed", name))
+                .with_label(*span, message.clone())
+                .with_secondary_label(*usage, "while expanding this"),
+            CompileError::Internal(message) => {
+                Diagnostic::error(format!("internal compiler error: {}", message))
+                    .with_note("this is a bug in the compiler, not in your program")
+            }
+        }
+    }
+}
+
+fn with_suggestion(diagnostic: Diagnostic, suggestion: &Option<String>) -> Diagnostic {
+    match suggestion {
+        Some(s) => diagnostic.with_help(format!("did you mean `{}`?", s)),
+        None => diagnostic,
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_diagnostic().message())
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+impl From<fmt::Error> for CompileError {
+    fn from(e: fmt::Error) -> Self {
+        CompileError::Internal(e.to_string())
+    }
+}
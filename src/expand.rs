@@ -0,0 +1,777 @@
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::base_expr::{BExpr, BExprBody, BExprBodyExpr, CondClause, Transformer};
+use crate::diagnostics::Span;
+use crate::error::CompileError;
+use crate::interp::Procedure;
+use crate::literals::Literal;
+use crate::parse;
+use crate::syntax::{split_mark, strip_marks, Datum};
+use crate::syntax_rules::SyntaxRules;
+use crate::utils::clone_rc;
+
+/// How many macro uses can be expanded in one program before we assume a
+/// macro is expanding into itself forever
+const MAX_EXPANSIONS: usize = 100_000;
+
+/// How many expansions can be nested inside each other, each nested expansion
+/// takes some of the compiler's stack
+const MAX_EXPANSION_DEPTH: usize = 10_000;
+
+/// Expand every macro used in the program `expr`, whose text is `source`.
+///
+/// The expansion of a macro is parsed as program text again. Identifiers the
+/// macro's template introduced carry a mark naming the expansion they came
+/// from: a marked identifier bound inside the expansion is renamed to a fresh
+/// variable, so it can't capture anything at the use, and any other marked
+/// identifier means what it meant where the macro was defined. A binding in
+/// the program that would shadow a variable is renamed too, so a macro used
+/// under it still refers to the variable it meant.
+pub fn expand(expr: BExpr, source: &str) -> Result<BExpr, CompileError> {
+    let mut expander = Expander {
+        frames: vec![Frame::default()],
+        marks: Vec::new(),
+        fresh: 0,
+        depth: 0,
+    };
+
+    let expr = expander.prepare(expr, source, &|s| s);
+
+    expander.expand_expr(expr, 0)
+}
+
+#[derive(Default)]
+struct Frame {
+    parent: Option<usize>,
+    names: HashMap<String, Binding>,
+}
+
+#[derive(Clone)]
+enum Binding {
+    /// A variable, with the name it has in the expanded program
+    Var(String),
+    Macro(Rc<Macro>),
+}
+
+struct Macro {
+    name: String,
+    transformer: Transformer,
+    /// The frame the macro was defined in
+    env: usize,
+}
+
+/// Where the identifiers marked by one expansion get their meaning from
+struct Mark {
+    env: usize,
+}
+
+enum Meaning {
+    Var(String),
+    Macro(Rc<Macro>),
+    /// Not bound anywhere, this is left for the scope checker to report
+    Free(String),
+}
+
+struct Expander {
+    /// Every scope seen so far, a macro refers to the scope it was defined in
+    /// by its index here
+    frames: Vec<Frame>,
+    marks: Vec<Mark>,
+    fresh: usize,
+    depth: usize,
+}
+
+impl Expander {
+    fn new_frame(&mut self, parent: usize) -> usize {
+        self.frames.push(Frame {
+            parent: Some(parent),
+            names: HashMap::new(),
+        });
+
+        self.frames.len() - 1
+    }
+
+    /// Bind `name` as a variable in `frame`, giving the name it has in the
+    /// expanded program. No two variables in scope of each other have the same
+    /// name there, so a macro's reference to a variable can't be captured by a
+    /// binding around the macro's use.
+    fn bind(&mut self, frame: usize, name: &str) -> String {
+        let unmarked = strip_marks(name) == name;
+
+        // defining a name again in the same body is the same variable
+        if let (true, Some(Binding::Var(renamed))) = (unmarked, self.frames[frame].names.get(name))
+        {
+            return renamed.clone();
+        }
+
+        let renamed = if unmarked && !self.in_scope(name, frame) {
+            name.to_owned()
+        } else {
+            // the space means this can't clash with a name in the program
+            self.fresh += 1;
+            format!("{} {}", strip_marks(name), self.fresh)
+        };
+
+        self.frames[frame]
+            .names
+            .insert(name.to_owned(), Binding::Var(renamed.clone()));
+
+        renamed
+    }
+
+    fn define_macro(&mut self, frame: usize, name: String, transformer: Transformer, env: usize) {
+        let mac = Macro {
+            name: name.clone(),
+            transformer,
+            env,
+        };
+
+        self.frames[frame]
+            .names
+            .insert(name, Binding::Macro(Rc::new(mac)));
+    }
+
+    /// Whether a variable named `renamed` in the expanded program is in scope
+    /// in `frame`
+    fn in_scope(&self, renamed: &str, frame: usize) -> bool {
+        let mut frame = Some(frame);
+
+        while let Some(f) = frame {
+            let bound = self.frames[f].names.values().any(|b| match b {
+                Binding::Var(n) => n == renamed,
+                Binding::Macro(_) => false,
+            });
+
+            if bound {
+                return true;
+            }
+
+            frame = self.frames[f].parent;
+        }
+
+        false
+    }
+
+    fn lookup(&self, name: &str, frame: usize) -> Option<(usize, &Binding)> {
+        let mut frame = Some(frame);
+
+        while let Some(f) = frame {
+            if let Some(b) = self.frames[f].names.get(name) {
+                return Some((f, b));
+            }
+
+            frame = self.frames[f].parent;
+        }
+
+        None
+    }
+
+    /// What the identifier `name` means when used in `frame`
+    fn resolve(&self, name: &str, frame: usize) -> Meaning {
+        if let Some((_, b)) = self.lookup(name, frame) {
+            return match b {
+                Binding::Var(n) => Meaning::Var(n.clone()),
+                Binding::Macro(m) => Meaning::Macro(m.clone()),
+            };
+        }
+
+        match split_mark(name) {
+            Some((name, mark)) if mark < self.marks.len() => {
+                self.resolve(name, self.marks[mark].env)
+            }
+            _ => Meaning::Free(strip_marks(name).to_owned()),
+        }
+    }
+
+    /// The macro a use of `datum` would expand, if its head is a macro
+    fn macro_of(&self, datum: &Datum, frame: usize) -> Option<Rc<Macro>> {
+        let name = match datum {
+            Datum::List(elems, _, _) => elems.first().and_then(Datum::as_symbol)?,
+            _ => return None,
+        };
+
+        match self.resolve(name, frame) {
+            Meaning::Macro(m) => Some(m),
+            _ => None,
+        }
+    }
+
+    /// Expand one use of a macro, giving the definition or expression it
+    /// expands into
+    fn expand_use(
+        &mut self,
+        datum: &Datum,
+        mac: &Macro,
+        depth: usize,
+    ) -> Result<BExprBodyExpr, CompileError> {
+        let expansion = self.expand_datum(datum, mac, depth)?;
+
+        self.read_expansion(&expansion)
+    }
+
+    /// Expand one use of a macro, giving the datum it expands into
+    fn expand_datum(
+        &mut self,
+        datum: &Datum,
+        mac: &Macro,
+        depth: usize,
+    ) -> Result<Datum, CompileError> {
+        if depth > MAX_EXPANSION_DEPTH {
+            return Err(CompileError::Syntax {
+                message: format!(
+                    "expanding `{}` nested more than {} macros deep, it might expand into itself forever",
+                    strip_marks(&mac.name),
+                    MAX_EXPANSION_DEPTH
+                ),
+                span: datum.span(),
+            });
+        }
+
+        // every expansion makes a mark, so this counts them
+        if self.marks.len() >= MAX_EXPANSIONS {
+            return Err(CompileError::Syntax {
+                message: format!(
+                    "expanding `{}` made more than {} macro expansions, it might expand into itself forever",
+                    strip_marks(&mac.name),
+                    MAX_EXPANSIONS
+                ),
+                span: datum.span(),
+            });
+        }
+
+        let mark = self.marks.len();
+        self.marks.push(Mark { env: mac.env });
+
+        let expansion = match &mac.transformer {
+            Transformer::Rules(rules) => rules.expand(&mac.name, datum, mark)?,
+            Transformer::Procedure(procedure) => {
+                let args = match datum {
+                    Datum::List(elems, _, _) => &elems[1..],
+                    _ => unreachable!("a macro use is always a list"),
+                };
+
+                procedure
+                    .call(args, datum.span())
+                    .map_err(|e| CompileError::MacroFailed {
+                        name: strip_marks(&mac.name).to_owned(),
+                        message: e.message,
+                        span: e.span,
+                        usage: datum.span(),
+                    })?
+            }
+        };
+
+        Ok(expansion)
+    }
+
+    /// Read the definition or expression a macro expanded into
+    fn read_expansion(&mut self, expansion: &Datum) -> Result<BExprBodyExpr, CompileError> {
+        let (text, spans) = expansion.render();
+        let map = |s| spans.map(s);
+
+        let item = parse::parse_expansion(&text).map_err(|e| e.map_span(map))?;
+
+        Ok(self.prepare_item(item, &text, &map))
+    }
+
+    /// Get a freshly parsed expression ready for expansion.
+    ///
+    /// Applications of an identifier become `MacroUse`s holding the
+    /// application as a datum, read from `text`, as whether the identifier
+    /// names a macro is only known once it is expanded where it is used. The
+    /// spans of the expression are moved by `map` from `text` to the program.
+    fn prepare(&mut self, expr: BExpr, text: &str, map: &dyn Fn(Span) -> Span) -> BExpr {
+        expr.rewrite(&|e| {
+            let e = match e {
+                BExpr::App(f, args, s) => match &*f {
+                    BExpr::Var(..) => {
+                        let datum = parse::read_syntax(&text[s.start..s.end])
+                            .expect("an application is always a datum")
+                            .map_spans(&|d| map(Span::new(d.start + s.start, d.end + s.start)));
+
+                        BExpr::MacroUse(
+                            Rc::new(datum),
+                            Some(Rc::new(BExpr::App(f, args, map(s)))),
+                            s,
+                        )
+                    }
+                    _ => BExpr::App(f, args, s),
+                },
+                BExpr::MacroUse(datum, app, s) => {
+                    BExpr::MacroUse(Rc::new(clone_rc(datum).map_spans(&map)), app, s)
+                }
+                BExpr::Lam(params, rest, body, s) => {
+                    BExpr::Lam(params, rest, map_body(body, map), s)
+                }
+                BExpr::Let(b, body, s) => BExpr::Let(b, map_body(body, map), s),
+                BExpr::LetStar(b, body, s) => BExpr::LetStar(b, map_body(body, map), s),
+                BExpr::LetRec(b, body, s) => BExpr::LetRec(b, map_body(body, map), s),
+                BExpr::NamedLet(n, b, body, s) => BExpr::NamedLet(n, b, map_body(body, map), s),
+                BExpr::LetSyntax(b, body, s) => {
+                    BExpr::LetSyntax(map_rules(b, map), map_body(body, map), s)
+                }
+                BExpr::LetRecSyntax(b, body, s) => {
+                    BExpr::LetRecSyntax(map_rules(b, map), map_body(body, map), s)
+                }
+                e => e,
+            };
+
+            e.map_span(map)
+        })
+    }
+
+    /// Read a use of something that isn't a macro as a function call.
+    ///
+    /// The parser only reads a list as a macro use if it isn't a function
+    /// call, which happens when an argument is a macro use that isn't an
+    /// expression on its own, like `(f (my-let ((x 1)) x))`.
+    fn read_application(&mut self, datum: &Datum, span: Span) -> Result<BExpr, CompileError> {
+        let elems = match datum {
+            Datum::List(elems, None, _) => elems,
+            _ => unreachable!("a macro use is always a proper list"),
+        };
+
+        let mut exprs = Vec::new();
+
+        for elem in elems {
+            let (text, spans) = elem.render();
+            let map = |s| spans.map(s);
+            let e = parse::parse_expression(&text).map_err(|e| e.map_span(map))?;
+
+            exprs.push(self.prepare(e, &text, &map));
+        }
+
+        let f = exprs.remove(0);
+
+        Ok(BExpr::App(Rc::new(f), exprs, span))
+    }
+
+    fn prepare_item(
+        &mut self,
+        item: BExprBodyExpr,
+        text: &str,
+        map: &dyn Fn(Span) -> Span,
+    ) -> BExprBodyExpr {
+        match item {
+            BExprBodyExpr::Def(n, e, s) => {
+                BExprBodyExpr::Def(n, self.prepare(e, text, map), map(s))
+            }
+            BExprBodyExpr::DefSyntax(n, transformer, s) => {
+                BExprBodyExpr::DefSyntax(n, map_transformer(transformer, map), map(s))
+            }
+            BExprBodyExpr::Expr(e) => BExprBodyExpr::Expr(self.prepare(e, text, map)),
+        }
+    }
+
+    fn expand_expr(&mut self, expr: BExpr, frame: usize) -> Result<BExpr, CompileError> {
+        let expr = match expr {
+            BExpr::Var(n, s) => match self.resolve(&n, frame) {
+                Meaning::Var(n) | Meaning::Free(n) => BExpr::Var(n, s),
+                Meaning::Macro(_) => return Err(macro_as_value(&n, s)),
+            },
+            // quoted symbols might have been introduced by a macro
+            BExpr::Lit(Literal::Symbol(sym), s) => {
+                BExpr::Lit(Literal::Symbol(strip_marks(&sym).to_owned()), s)
+            }
+            BExpr::Lit(..) | BExpr::BuiltinIdent(..) => expr,
+            BExpr::Set(n, e, s) => {
+                let n = match self.resolve(&n, frame) {
+                    Meaning::Var(n) | Meaning::Free(n) => n,
+                    Meaning::Macro(_) => return Err(macro_as_value(&n, s)),
+                };
+
+                BExpr::Set(n, self.expand_rc(e, frame)?, s)
+            }
+            BExpr::If(c, ift, iff, s) => BExpr::If(
+                self.expand_rc(c, frame)?,
+                self.expand_rc(ift, frame)?,
+                self.expand_rc(iff, frame)?,
+                s,
+            ),
+            BExpr::Let(bindings, body, s) => {
+                let inits = self.expand_inits(bindings, frame)?;
+                let inner = self.new_frame(frame);
+                let bindings = inits
+                    .into_iter()
+                    .map(|(n, e)| (self.bind(inner, &n), e))
+                    .collect();
+
+                BExpr::Let(bindings, self.expand_body(body, inner)?, s)
+            }
+            BExpr::LetStar(bindings, body, s) => {
+                // each binding can see the ones before it
+                let mut inner = frame;
+                let mut new_bindings = Vec::new();
+
+                for (n, e) in bindings {
+                    let e = self.expand_expr(e, inner)?;
+                    inner = self.new_frame(inner);
+                    new_bindings.push((self.bind(inner, &n), e));
+                }
+
+                BExpr::LetStar(new_bindings, self.expand_body(body, inner)?, s)
+            }
+            BExpr::LetRec(bindings, body, s) => {
+                let inner = self.new_frame(frame);
+                let names = bindings
+                    .iter()
+                    .map(|(n, _)| self.bind(inner, n))
+                    .collect::<Vec<_>>();
+                let inits = self.expand_inits(bindings, inner)?;
+                let bindings = names.into_iter().zip(inits).map(|(n, (_, e))| (n, e));
+
+                BExpr::LetRec(bindings.collect(), self.expand_body(body, inner)?, s)
+            }
+            BExpr::NamedLet(name, bindings, body, s) => {
+                let inits = self.expand_inits(bindings, frame)?;
+                let inner = self.new_frame(frame);
+                let name = self.bind(inner, &name);
+                let bindings = inits
+                    .into_iter()
+                    .map(|(n, e)| (self.bind(inner, &n), e))
+                    .collect();
+
+                BExpr::NamedLet(name, bindings, self.expand_body(body, inner)?, s)
+            }
+            BExpr::Lam(params, rest, body, s) => {
+                let inner = self.new_frame(frame);
+                let params = params.iter().map(|p| self.bind(inner, p)).collect();
+                let rest = rest.map(|r| self.bind(inner, &r));
+
+                BExpr::Lam(params, rest, self.expand_body(body, inner)?, s)
+            }
+            BExpr::App(f, args, s) => {
+                BExpr::App(self.expand_rc(f, frame)?, self.expand_all(args, frame)?, s)
+            }
+            BExpr::Cond(clauses, s) => BExpr::Cond(self.expand_clauses(clauses, frame)?, s),
+            BExpr::Case(key, clauses, else_body, s) => {
+                let clauses = clauses
+                    .into_iter()
+                    .map(|(datums, body)| {
+                        Ok((self.expand_all(datums, frame)?, self.expand_all(body, frame)?))
+                    })
+                    .collect::<Result<_, CompileError>>()?;
+                let else_body = else_body
+                    .map(|body| self.expand_all(body, frame))
+                    .transpose()?;
+
+                BExpr::Case(self.expand_rc(key, frame)?, clauses, else_body, s)
+            }
+            BExpr::And(es, s) => BExpr::And(self.expand_all(es, frame)?, s),
+            BExpr::Or(es, s) => BExpr::Or(self.expand_all(es, frame)?, s),
+            BExpr::When(c, es, s) => {
+                BExpr::When(self.expand_rc(c, frame)?, self.expand_all(es, frame)?, s)
+            }
+            BExpr::Unless(c, es, s) => {
+                BExpr::Unless(self.expand_rc(c, frame)?, self.expand_all(es, frame)?, s)
+            }
+            BExpr::Begin(es, s) => BExpr::Begin(self.expand_all(es, frame)?, s),
+            BExpr::Guard(var, clauses, body, s) => {
+                let body = self.expand_body(body, frame)?;
+                let inner = self.new_frame(frame);
+                let var = self.bind(inner, &var);
+
+                BExpr::Guard(var, self.expand_clauses(clauses, inner)?, body, s)
+            }
+            BExpr::Do(bindings, test, result, commands, s) => {
+                let inits = bindings
+                    .iter()
+                    .map(|(_, init, _)| self.expand_expr(init.clone(), frame))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let inner = self.new_frame(frame);
+                let names = bindings
+                    .iter()
+                    .map(|(n, _, _)| self.bind(inner, n))
+                    .collect::<Vec<_>>();
+                let steps = bindings
+                    .into_iter()
+                    .map(|(_, _, step)| step.map(|e| self.expand_expr(e, inner)).transpose())
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                let bindings = names
+                    .into_iter()
+                    .zip(inits)
+                    .zip(steps)
+                    .map(|((n, init), step)| (n, init, step))
+                    .collect();
+
+                BExpr::Do(
+                    bindings,
+                    self.expand_rc(test, inner)?,
+                    self.expand_all(result, inner)?,
+                    self.expand_all(commands, inner)?,
+                    s,
+                )
+            }
+            BExpr::MacroUse(datum, app, s) => match self.macro_of(&datum, frame) {
+                Some(mac) => match self.expand_use(&datum, &mac, self.depth)? {
+                    BExprBodyExpr::Expr(e) => {
+                        self.depth += 1;
+                        let e = self.expand_expr(e, frame);
+                        self.depth -= 1;
+
+                        e?
+                    }
+                    item => {
+                        return Err(CompileError::Syntax {
+                            message: format!(
+                                "this use of `{}` expands into a definition, which is only allowed in a body",
+                                strip_marks(&mac.name)
+                            ),
+                            span: item.span(),
+                        })
+                    }
+                },
+                None => match app {
+                    Some(app) => self.expand_expr(clone_rc(app), frame)?,
+                    None => {
+                        let app = self.read_application(&datum, s)?;
+                        self.expand_expr(app, frame)?
+                    }
+                },
+            },
+            BExpr::LetSyntax(bindings, body, s) => {
+                let inner = self.new_frame(frame);
+
+                for (n, rules) in bindings {
+                    self.define_macro(inner, n, Transformer::Rules(rules), frame);
+                }
+
+                BExpr::Let(Vec::new(), self.expand_body(body, inner)?, s)
+            }
+            BExpr::LetRecSyntax(bindings, body, s) => {
+                let inner = self.new_frame(frame);
+
+                for (n, rules) in bindings {
+                    self.define_macro(inner, n, Transformer::Rules(rules), inner);
+                }
+
+                BExpr::Let(Vec::new(), self.expand_body(body, inner)?, s)
+            }
+            BExpr::Loop(..) | BExpr::Recur(..) => {
+                return Err(CompileError::Internal(
+                    "loops are only made after macros are expanded".to_owned(),
+                ))
+            }
+        };
+
+        Ok(expr)
+    }
+
+    fn expand_rc(&mut self, expr: Rc<BExpr>, frame: usize) -> Result<Rc<BExpr>, CompileError> {
+        Ok(Rc::new(self.expand_expr(clone_rc(expr), frame)?))
+    }
+
+    fn expand_all(&mut self, exprs: Vec<BExpr>, frame: usize) -> Result<Vec<BExpr>, CompileError> {
+        exprs
+            .into_iter()
+            .map(|e| self.expand_expr(e, frame))
+            .collect()
+    }
+
+    fn expand_clauses(
+        &mut self,
+        clauses: Vec<CondClause>,
+        frame: usize,
+    ) -> Result<Vec<CondClause>, CompileError> {
+        clauses
+            .into_iter()
+            .map(|c| match c {
+                CondClause::Test(test, body) => Ok(CondClause::Test(
+                    self.expand_expr(test, frame)?,
+                    self.expand_all(body, frame)?,
+                )),
+                CondClause::Arrow(test, receiver) => Ok(CondClause::Arrow(
+                    self.expand_expr(test, frame)?,
+                    self.expand_expr(receiver, frame)?,
+                )),
+                CondClause::Else(body) => Ok(CondClause::Else(self.expand_all(body, frame)?)),
+            })
+            .collect()
+    }
+
+    fn expand_inits(
+        &mut self,
+        bindings: Vec<(String, BExpr)>,
+        frame: usize,
+    ) -> Result<Vec<(String, BExpr)>, CompileError> {
+        bindings
+            .into_iter()
+            .map(|(n, e)| Ok((n, self.expand_expr(e, frame)?)))
+            .collect()
+    }
+
+    /// Add a definition in a body to the body's frame
+    fn declare(&mut self, item: &BExprBodyExpr, frame: usize) {
+        match item {
+            BExprBodyExpr::Def(n, _, _) => {
+                self.bind(frame, n);
+            }
+            BExprBodyExpr::DefSyntax(n, transformer, _) => {
+                self.define_macro(frame, n.clone(), transformer.clone(), frame)
+            }
+            BExprBodyExpr::Expr(_) => {}
+        }
+    }
+
+    fn expand_body(&mut self, body: BExprBody, frame: usize) -> Result<BExprBody, CompileError> {
+        let frame = self.new_frame(frame);
+        let BExprBody(items, last) = body;
+
+        // definitions are visible across the whole body, including the ones
+        // that macros used in the body expand into
+        let last_span = last.span();
+        let mut pending = items;
+        pending.push(BExprBodyExpr::Expr(clone_rc(last)));
+
+        for item in &pending {
+            self.declare(item, frame);
+        }
+
+        let mut pending = pending
+            .into_iter()
+            .rev()
+            .map(|item| (item, self.depth))
+            .collect::<Vec<_>>();
+        let mut expanded = Vec::new();
+
+        while let Some((item, depth)) = pending.pop() {
+            let mac = match &item {
+                BExprBodyExpr::Expr(BExpr::MacroUse(datum, _, _)) => {
+                    self.macro_of(datum, frame).map(|m| (datum.clone(), m))
+                }
+                _ => None,
+            };
+
+            match mac {
+                Some((datum, mac)) => {
+                    let expansion = self.expand_datum(&datum, &mac, depth)?;
+
+                    // a `begin` in a body is spliced into it, so one macro
+                    // can expand into several definitions
+                    let mut parts = Vec::new();
+                    splice_begins(&expansion, &mut parts);
+
+                    for part in parts.into_iter().rev() {
+                        let item = self.read_expansion(part)?;
+                        self.declare(&item, frame);
+                        pending.push((item, depth + 1));
+                    }
+                }
+                None => expanded.push(item),
+            }
+        }
+
+        // the body can be empty if it was a macro that expanded into `(begin)`
+        match expanded.last() {
+            Some(BExprBodyExpr::Expr(_)) => {}
+            item => {
+                return Err(CompileError::Syntax {
+                    message: "a body has to end with an expression".to_owned(),
+                    span: item.map_or(last_span, BExprBodyExpr::span),
+                })
+            }
+        }
+
+        let mut items = Vec::new();
+
+        for item in expanded {
+            match item {
+                BExprBodyExpr::Def(n, e, s) => {
+                    let n = match self.frames[frame].names.get(&n) {
+                        Some(Binding::Var(renamed)) => renamed.clone(),
+                        _ => n,
+                    };
+
+                    items.push(BExprBodyExpr::Def(n, self.expand_expr(e, frame)?, s));
+                }
+                BExprBodyExpr::DefSyntax(..) => {}
+                BExprBodyExpr::Expr(e) => {
+                    items.push(BExprBodyExpr::Expr(self.expand_expr(e, frame)?))
+                }
+            }
+        }
+
+        let last = match items.pop() {
+            Some(BExprBodyExpr::Expr(e)) => e,
+            _ => unreachable!("checked above"),
+        };
+
+        Ok(BExprBody(items, Rc::new(last)))
+    }
+}
+
+/// Add the parts of `datum` to `parts`, taking them out of any `begin`s
+fn splice_begins<'a>(datum: &'a Datum, parts: &mut Vec<&'a Datum>) {
+    match datum {
+        Datum::List(elems, None, _)
+            if elems.first().and_then(Datum::as_symbol) == Some("begin") =>
+        {
+            for elem in &elems[1..] {
+                splice_begins(elem, parts);
+            }
+        }
+        _ => parts.push(datum),
+    }
+}
+
+/// Move the spans of the definitions in a body, `BExpr::rewrite` moves the
+/// spans of everything else
+fn map_body(body: BExprBody, map: &dyn Fn(Span) -> Span) -> BExprBody {
+    let BExprBody(items, last) = body;
+    let items = items
+        .into_iter()
+        .map(|item| match item {
+            BExprBodyExpr::Def(n, e, s) => BExprBodyExpr::Def(n, e, map(s)),
+            BExprBodyExpr::DefSyntax(n, transformer, s) => {
+                BExprBodyExpr::DefSyntax(n, map_transformer(transformer, map), map(s))
+            }
+            item => item,
+        })
+        .collect();
+
+    BExprBody(items, last)
+}
+
+fn map_transformer(transformer: Transformer, map: &dyn Fn(Span) -> Span) -> Transformer {
+    match transformer {
+        Transformer::Rules(rules) => Transformer::Rules(Rc::new(rules.map_spans(&map))),
+        Transformer::Procedure(procedure) => {
+            let body = BExpr::Lam(Vec::new(), None, procedure.body.clone(), procedure.span)
+                .rewrite(&|e| e.map_span(map));
+            let body = match body {
+                BExpr::Lam(_, _, body, _) => body,
+                _ => unreachable!(),
+            };
+
+            Transformer::Procedure(Rc::new(Procedure {
+                params: procedure.params.clone(),
+                rest: procedure.rest.clone(),
+                body,
+                span: map(procedure.span),
+            }))
+        }
+    }
+}
+
+fn map_rules(
+    bindings: Vec<(String, Rc<SyntaxRules>)>,
+    map: &dyn Fn(Span) -> Span,
+) -> Vec<(String, Rc<SyntaxRules>)> {
+    bindings
+        .into_iter()
+        .map(|(n, rules)| (n, Rc::new(rules.map_spans(&map))))
+        .collect()
+}
+
+fn macro_as_value(name: &str, span: Span) -> CompileError {
+    CompileError::Syntax {
+        message: format!(
+            "`{}` is a macro, it can only be used as `({} ...)`",
+            strip_marks(name),
+            strip_marks(name)
+        ),
+        span,
+    }
+}
@@ -7,9 +7,25 @@ use termcolor::{Color, ColorSpec, WriteColor};
 use std::{io::Result, rc::Rc};
 
 use crate::cont_expr;
+use crate::diagnostics::Span;
 use crate::flat_expr;
 use crate::literals::Literal;
 
+/// The name and variables of a loop
+pub type LoopPattern = (Binder<String>, Vec<Binder<String>>);
+
+/// The parameters of a lambda, and the parameter taking a list of any
+/// arguments after them if it is variadic
+pub type LamPattern = (Vec<Binder<String>>, Option<Binder<String>>);
+
+/// What runtime errors call a lambda, and where it was written
+#[derive(Debug, Clone)]
+pub struct LamInfo {
+    /// The variable the lambda was bound to where it was made, if any
+    pub name: Option<String>,
+    pub span: Span,
+}
+
 #[derive(Debug, Clone, BoundTerm)]
 pub enum Expr {
     Var(Var<String>),
@@ -17,8 +33,13 @@ pub enum Expr {
     BuiltinIdent(Ignore<String>),
     If(Rc<Expr>, Rc<Expr>, Rc<Expr>),
     Set(Var<String>, Rc<Expr>),
-    Lam(Scope<Binder<String>, Rc<Expr>>),
-    App(Rc<Expr>, Rc<Expr>),
+    Lam(Scope<LamPattern, Rc<Expr>>, Ignore<LamInfo>),
+    /// The span is the call site, for runtime errors
+    App(Rc<Expr>, Vec<Rc<Expr>>, Ignore<Span>),
+    /// The inits of the loop's variables, and the loop's name and variables
+    Loop(Vec<Rc<Expr>>, Scope<LoopPattern, Rc<Expr>>),
+    /// Jump back to the head of a loop, giving each of its variables a new value
+    Recur(Var<String>, Vec<Var<String>>, Vec<Rc<Expr>>),
 }
 
 impl Expr {
@@ -48,15 +69,25 @@ impl Expr {
                     .group()
                     .parens()
             }
-            Expr::Lam(s) => {
+            Expr::Lam(s, _) => {
                 let Scope {
-                    unsafe_pattern: pat,
+                    unsafe_pattern: (params, rest),
                     unsafe_body: body,
                 } = &s;
 
+                let param_pret = |p: &Binder<String>| {
+                    allocator
+                        .as_string(p)
+                        .annotate(ColorSpec::new().set_fg(Some(Color::Green)).clone())
+                };
+                let rest_pret = rest
+                    .iter()
+                    .flat_map(|r| vec![allocator.text("."), param_pret(r)]);
                 let pat_pret = allocator
-                    .as_string(pat)
-                    .annotate(ColorSpec::new().set_fg(Some(Color::Green)).clone())
+                    .intersperse(
+                        params.iter().map(param_pret).chain(rest_pret),
+                        allocator.space(),
+                    )
                     .parens();
                 let body_pret = allocator.line_().append(body.pretty(allocator));
 
@@ -87,17 +118,65 @@ impl Expr {
                     .group()
                     .parens()
             }
-            Expr::App(f, v) => {
+            Expr::App(f, args, _) => {
                 let f_pret = f.pretty(allocator);
-                let v_pret = v.pretty(allocator);
+                let args_pret = allocator.concat(
+                    args.iter()
+                        .map(|e| allocator.space().append(e.pretty(allocator))),
+                );
 
                 f_pret
                     .annotate(ColorSpec::new().set_fg(Some(Color::Blue)).clone())
+                    .append(args_pret)
+                    .group()
+                    .parens()
+            }
+            Expr::Loop(inits, s) => {
+                let Scope {
+                    unsafe_pattern: (name, vars),
+                    unsafe_body: body,
+                } = &s;
+
+                let bindings_pret = allocator
+                    .intersperse(
+                        vars.iter().zip(inits).map(|(v, e)| {
+                            allocator
+                                .as_string(v)
+                                .annotate(ColorSpec::new().set_fg(Some(Color::Green)).clone())
+                                .append(allocator.space())
+                                .append(e.pretty(allocator))
+                                .group()
+                                .parens()
+                        }),
+                        allocator.space(),
+                    )
+                    .parens();
+
+                allocator
+                    .text("loop")
+                    .annotate(ColorSpec::new().set_fg(Some(Color::Magenta)).clone())
                     .append(allocator.space())
-                    .append(v_pret)
+                    .append(allocator.as_string(name))
+                    .append(allocator.space())
+                    .append(bindings_pret)
+                    .append(allocator.line())
+                    .append(body.pretty(allocator))
+                    .nest(1)
                     .group()
                     .parens()
             }
+            Expr::Recur(name, _, args) => allocator
+                .text("recur")
+                .annotate(ColorSpec::new().set_fg(Some(Color::Magenta)).clone())
+                .append(allocator.space())
+                .append(allocator.as_string(name))
+                .append(allocator.space())
+                .append(
+                    allocator
+                        .intersperse(args.iter().map(|e| e.pretty(allocator)), allocator.space()),
+                )
+                .group()
+                .parens(),
         }
     }
 
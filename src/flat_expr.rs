@@ -1,12 +1,16 @@
 use moniker::BoundTerm;
-use moniker::{Binder, Ignore, Scope, Var};
+use moniker::{Binder, FreeVar, Ignore, Scope, Var};
 
 use pretty::{BoxAllocator, DocAllocator, DocBuilder};
 use termcolor::{Color, ColorSpec, WriteColor};
 
 use std::collections::HashMap;
-use std::{io::Result, rc::Rc};
+use std::{io, rc::Rc};
 
+use crate::cont_expr::{LamPattern, LoopPattern};
+use crate::diagnostics::Span;
+use crate::error::CompileError;
+use crate::expr::LamInfo;
 use crate::lifted_expr::{LExpr, LiftedLambda};
 use crate::literals::Literal;
 use crate::utils::clone_rc;
@@ -15,13 +19,18 @@ use crate::utils::clone_rc;
 pub enum FExpr {
     If(Rc<FExpr>, Rc<FExpr>, Rc<FExpr>),
     LamOne(Scope<Binder<String>, Rc<FExpr>>),
-    LamTwo(Scope<Binder<String>, Scope<Binder<String>, Rc<FExpr>>>),
+    Lam(Scope<LamPattern, Rc<FExpr>>, Ignore<LamInfo>),
     Var(Var<String>),
     Lit(Ignore<Literal>),
     BuiltinIdent(Ignore<String>),
     SetThen(Var<String>, Rc<FExpr>, Rc<FExpr>),
     CallOne(Rc<FExpr>, Rc<FExpr>),
-    CallTwo(Rc<FExpr>, Rc<FExpr>, Rc<FExpr>),
+    /// The span is the call site
+    Call(Rc<FExpr>, Vec<Rc<FExpr>>, Rc<FExpr>, Ignore<Span>),
+    Loop(Vec<Rc<FExpr>>, Rc<FExpr>, Scope<LoopPattern, Rc<FExpr>>),
+    Recur(Var<String>, Vec<Var<String>>, Vec<Rc<FExpr>>),
+    BuiltinCall(Ignore<String>, Vec<Rc<FExpr>>),
+    Let(Vec<Rc<FExpr>>, Scope<Vec<Binder<String>>, Rc<FExpr>>),
 }
 
 struct LiftingCtx {
@@ -111,25 +120,29 @@ impl FExpr {
                     .group()
                     .parens()
             }
-            FExpr::LamTwo(s) => {
+            FExpr::Lam(s, _) => {
                 let Scope {
-                    unsafe_pattern: pat,
-                    unsafe_body:
-                        Scope {
-                            unsafe_pattern: cont,
-                            unsafe_body: body,
-                        },
+                    unsafe_pattern: (params, rest, cont),
+                    unsafe_body: body,
                 } = &s;
 
-                let pat_pret = allocator
-                    .as_string(pat)
-                    .annotate(ColorSpec::new().set_fg(Some(Color::Green)).clone());
-                let cont_pret = allocator
-                    .as_string(cont)
-                    .annotate(ColorSpec::new().set_fg(Some(Color::Red)).clone());
-                let args_pret = pat_pret
-                    .append(allocator.space())
-                    .append(cont_pret)
+                let param_pret = |p: &Binder<String>, color| {
+                    allocator
+                        .as_string(p)
+                        .annotate(ColorSpec::new().set_fg(Some(color)).clone())
+                };
+                let rest_pret = rest
+                    .iter()
+                    .flat_map(|r| vec![allocator.text("."), param_pret(r, Color::Green)]);
+                let args_pret = allocator
+                    .intersperse(
+                        params
+                            .iter()
+                            .map(|p| param_pret(p, Color::Green))
+                            .chain(rest_pret)
+                            .chain(std::iter::once(param_pret(cont, Color::Red))),
+                        allocator.space(),
+                    )
                     .parens();
                 let body_pret = allocator.line_().append(body.pretty(allocator));
 
@@ -158,24 +171,122 @@ impl FExpr {
                     .group()
                     .parens()
             }
-            FExpr::CallTwo(f, v, c) => {
+            FExpr::Call(f, args, c, _) => {
                 let f_pret = f.pretty(allocator);
-                let v_pret = v.pretty(allocator);
+                let args_pret = allocator.concat(
+                    args.iter()
+                        .map(|e| allocator.space().append(e.pretty(allocator))),
+                );
                 let c_pret = c.pretty(allocator);
 
                 f_pret
                     .annotate(ColorSpec::new().set_fg(Some(Color::Blue)).clone())
-                    .append(allocator.space())
-                    .append(v_pret)
+                    .append(args_pret)
                     .append(allocator.space())
                     .append(c_pret)
                     .group()
                     .parens()
             }
+            FExpr::Loop(inits, cont, s) => {
+                let Scope {
+                    unsafe_pattern: (name, k, vars),
+                    unsafe_body: body,
+                } = &s;
+
+                let binding = |v: &Binder<String>, e: &Rc<FExpr>, color| {
+                    allocator
+                        .as_string(v)
+                        .annotate(ColorSpec::new().set_fg(Some(color)).clone())
+                        .append(allocator.space())
+                        .append(e.pretty(allocator))
+                        .group()
+                        .parens()
+                };
+                let bindings_pret = allocator
+                    .intersperse(
+                        std::iter::once(binding(k, cont, Color::Red)).chain(
+                            vars.iter()
+                                .zip(inits)
+                                .map(|(v, e)| binding(v, e, Color::Green)),
+                        ),
+                        allocator.space(),
+                    )
+                    .parens();
+
+                allocator
+                    .text("loop")
+                    .annotate(ColorSpec::new().set_fg(Some(Color::Magenta)).clone())
+                    .append(allocator.space())
+                    .append(allocator.as_string(name))
+                    .append(allocator.space())
+                    .append(bindings_pret)
+                    .append(allocator.line())
+                    .append(body.pretty(allocator))
+                    .nest(1)
+                    .group()
+                    .parens()
+            }
+            FExpr::Recur(name, _, args) => allocator
+                .text("recur")
+                .annotate(ColorSpec::new().set_fg(Some(Color::Magenta)).clone())
+                .append(allocator.space())
+                .append(allocator.as_string(name))
+                .append(allocator.space())
+                .append(
+                    allocator
+                        .intersperse(args.iter().map(|e| e.pretty(allocator)), allocator.space()),
+                )
+                .group()
+                .parens(),
+            FExpr::BuiltinCall(Ignore(i), args) => {
+                let args_pret = allocator.concat(
+                    args.iter()
+                        .map(|e| allocator.space().append(e.pretty(allocator))),
+                );
+
+                allocator
+                    .as_string(i)
+                    .annotate(ColorSpec::new().set_fg(Some(Color::Blue)).clone())
+                    .append(args_pret)
+                    .group()
+                    .parens()
+            }
+            FExpr::Let(values, s) => {
+                let Scope {
+                    unsafe_pattern: vars,
+                    unsafe_body: body,
+                } = &s;
+
+                let bindings_pret = allocator
+                    .intersperse(
+                        vars.iter().zip(values).map(|(v, e)| {
+                            allocator
+                                .as_string(v)
+                                .annotate(ColorSpec::new().set_fg(Some(Color::Green)).clone())
+                                .append(allocator.space())
+                                .append(e.pretty(allocator))
+                                .group()
+                                .parens()
+                        }),
+                        allocator.space(),
+                    )
+                    .parens();
+
+                allocator
+                    .text("let")
+                    .annotate(ColorSpec::new().set_fg(Some(Color::Magenta)).clone())
+                    .append(allocator.space())
+                    .append(bindings_pret)
+                    .append(allocator.line())
+                    .append(body.pretty(allocator))
+                    .nest(1)
+                    .group()
+                    .parens()
+            }
         }
     }
 
-    pub fn pretty_print(&self, out: impl WriteColor) -> Result<()> {
+    pub fn pretty_print(&self, out: impl WriteColor) -> io::Result<()> {
         let allocator = BoxAllocator;
 
         self.pretty(&allocator).1.render_colored(70, out)?;
@@ -183,66 +294,143 @@ impl FExpr {
         Ok(())
     }
 
-    pub fn lift_lambdas(self) -> (LExpr, HashMap<usize, LiftedLambda>) {
+    pub fn lift_lambdas(self) -> Result<(LExpr, HashMap<usize, LiftedLambda>), CompileError> {
         let mut ctx = LiftingCtx::new();
-        let expr = self.lift_lambdas_internal(&mut ctx);
-        (expr, ctx.lambdas)
+        let expr = self.lift_lambdas_internal(&mut ctx)?;
+        Ok((expr, ctx.lambdas))
     }
 
-    fn lift_lambdas_internal(self, ctx: &mut LiftingCtx) -> LExpr {
-        match self {
+    fn lift_lambdas_internal(self, ctx: &mut LiftingCtx) -> Result<LExpr, CompileError> {
+        let expr = match self {
             FExpr::LamOne(s) => {
                 let (param, body) = s.unbind();
                 let free_vars = body.free_vars();
-                let body = clone_rc(body).lift_lambdas_internal(ctx);
+                let body = clone_rc(body).lift_lambdas_internal(ctx)?;
                 let id = ctx.get();
                 ctx.add(LiftedLambda::new(
                     id,
+                    None,
                     vec![param.0],
+                    None,
+                    None,
                     free_vars,
                     Rc::new(body),
                 ));
                 LExpr::Lifted(Ignore(id))
             }
-            FExpr::LamTwo(s) => {
-                let (param0, body) = s.unbind();
-                let (param1, body) = body.unbind();
+            FExpr::Lam(s, Ignore(info)) => {
+                let ((params, rest, cont), body) = s.unbind();
                 let free_vars = body.free_vars();
-                let body = clone_rc(body).lift_lambdas_internal(ctx);
+                let body = clone_rc(body).lift_lambdas_internal(ctx)?;
                 let id = ctx.get();
                 ctx.add(LiftedLambda::new(
                     id,
-                    vec![param0.0, param1.0],
+                    Some(info),
+                    params.into_iter().map(|p| p.0).collect(),
+                    rest.map(|r| r.0),
+                    Some(cont.0),
                     free_vars,
                     Rc::new(body),
                 ));
                 LExpr::Lifted(Ignore(id))
             }
-            FExpr::Var(v) => LExpr::Var(v),
+            FExpr::Var(v @ Var::Free(_)) => LExpr::Var(v),
+            FExpr::Var(v @ Var::Bound(_)) => {
+                return Err(CompileError::Internal(format!(
+                    "bound variable {} escaped its binder",
+                    v
+                )))
+            }
             FExpr::Lit(l) => LExpr::Lit(l),
             FExpr::BuiltinIdent(i) => LExpr::BuiltinIdent(i),
             FExpr::SetThen(n, v, c) => {
-                let v = clone_rc(v).lift_lambdas_internal(ctx);
-                let c = clone_rc(c).lift_lambdas_internal(ctx);
+                let v = clone_rc(v).lift_lambdas_internal(ctx)?;
+                let c = clone_rc(c).lift_lambdas_internal(ctx)?;
                 LExpr::SetThen(n, Rc::new(v), Rc::new(c))
             }
             FExpr::CallOne(f, p) => {
-                let f = clone_rc(f).lift_lambdas_internal(ctx);
-                let p = clone_rc(p).lift_lambdas_internal(ctx);
+                let f = clone_rc(f).lift_lambdas_internal(ctx)?;
+                let p = clone_rc(p).lift_lambdas_internal(ctx)?;
                 LExpr::CallOne(Rc::new(f), Rc::new(p))
             }
-            FExpr::CallTwo(f, p, k) => {
-                let f = clone_rc(f).lift_lambdas_internal(ctx);
-                let p = clone_rc(p).lift_lambdas_internal(ctx);
-                let k = clone_rc(k).lift_lambdas_internal(ctx);
-                LExpr::CallTwo(Rc::new(f), Rc::new(p), Rc::new(k))
+            FExpr::Call(f, args, k, site) => {
+                let f = clone_rc(f).lift_lambdas_internal(ctx)?;
+                let args = args
+                    .into_iter()
+                    .map(|e| Ok(Rc::new(clone_rc(e).lift_lambdas_internal(ctx)?)))
+                    .collect::<Result<_, CompileError>>()?;
+                let k = clone_rc(k).lift_lambdas_internal(ctx)?;
+                LExpr::Call(Rc::new(f), args, Rc::new(k), site)
             }
             FExpr::If(c, ift, iff) => {
-                let c = clone_rc(c).lift_lambdas_internal(ctx);
-                let ift = clone_rc(ift).lift_lambdas_internal(ctx);
-                let iff = clone_rc(iff).lift_lambdas_internal(ctx);
+                let c = clone_rc(c).lift_lambdas_internal(ctx)?;
+                let ift = clone_rc(ift).lift_lambdas_internal(ctx)?;
+                let iff = clone_rc(iff).lift_lambdas_internal(ctx)?;
                 LExpr::If(Rc::new(c), Rc::new(ift), Rc::new(iff))
             }
-        }
+            FExpr::Loop(inits, cont, s) => {
+                // the body of the loop becomes a lambda that is entered again on
+                // each iteration, its parameter is unused as the loop's
+                // variables are kept in its environment instead
+                let ((name, k, vars), body) = s.unbind();
+                let free_vars = body.free_vars();
+                let body = clone_rc(body).lift_lambdas_internal(ctx)?;
+                let id = ctx.get();
+                ctx.add(
+                    LiftedLambda::new(
+                        id,
+                        None,
+                        vec![FreeVar::fresh_named("_unused")],
+                        None,
+                        None,
+                        free_vars,
+                        Rc::new(body),
+                    )
+                    .for_loop(name.0.clone()),
+                );
+
+                let bindings = std::iter::once((k, cont))
+                    .chain(vars.into_iter().zip(inits))
+                    .map(|(v, e)| {
+                        let e = clone_rc(e).lift_lambdas_internal(ctx)?;
+                        Ok((Var::Free(v.0), Rc::new(e)))
+                    })
+                    .collect::<Result<_, CompileError>>()?;
+
+                LExpr::Loop(Ignore(id), Var::Free(name.0), bindings)
+            }
+            FExpr::Recur(n, vars, args) => {
+                let args = args
+                    .into_iter()
+                    .map(|e| Ok(Rc::new(clone_rc(e).lift_lambdas_internal(ctx)?)))
+                    .collect::<Result<_, CompileError>>()?;
+                LExpr::Recur(n, vars, args)
+            }
+            FExpr::BuiltinCall(i, args) => {
+                let args = args
+                    .into_iter()
+                    .map(|e| Ok(Rc::new(clone_rc(e).lift_lambdas_internal(ctx)?)))
+                    .collect::<Result<_, CompileError>>()?;
+                LExpr::BuiltinCall(i, args)
+            }
+            FExpr::Let(values, s) => {
+                let (vars, body) = s.unbind();
+                // a value that isn't used is only evaluated, not bound
+                let used = body.free_vars();
+                let bindings = vars
+                    .into_iter()
+                    .zip(values)
+                    .map(|(v, e)| {
+                        let e = clone_rc(e).lift_lambdas_internal(ctx)?;
+                        let v = Some(v.0).filter(|v| used.contains(v)).map(Var::Free);
+                        Ok((v, Rc::new(e)))
+                    })
+                    .collect::<Result<_, CompileError>>()?;
+                let body = clone_rc(body).lift_lambdas_internal(ctx)?;
+                LExpr::Let(bindings, Rc::new(body))
+            }
+        };
+
+        Ok(expr)
     }
 }
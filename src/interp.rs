@@ -0,0 +1,835 @@
+use std::cell::RefCell;
+use std::convert::TryFrom;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::base_expr::{BExpr, BExprBody, BExprBodyExpr};
+use crate::diagnostics::Span;
+use crate::literals::Literal;
+use crate::parse;
+use crate::syntax::{strip_marks, Datum};
+
+/// How deeply evaluation can nest while running a transformer, any deeper and
+/// the compiler's own stack would overflow. Tail calls don't nest.
+const MAX_EVAL_DEPTH: usize = 10_000;
+
+/// How many expressions one use of a macro can evaluate before we assume its
+/// transformer is stuck in a loop
+const MAX_STEPS: usize = 10_000_000;
+
+/// The transformer of a `define-macro` macro, a procedure that is run at
+/// compile time on the unevaluated arguments of each use of the macro and
+/// gives the code to put in place of the use.
+///
+/// Transformers are interpreted, so they can only use their own variables
+/// and the builtins.
+#[derive(Debug)]
+pub struct Procedure {
+    pub params: Vec<String>,
+    /// The parameter that gets a list of the arguments after `params`
+    pub rest: Option<String>,
+    pub body: BExprBody,
+    pub span: Span,
+}
+
+/// A value in the compile time interpreter, these mirror the objects of the
+/// runtime
+#[derive(Clone)]
+pub enum Value {
+    /// The empty list, which is the same value as void like at runtime
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    Char(char),
+    String(Rc<str>),
+    Symbol(Rc<str>),
+    Pair(Rc<Value>, Rc<Value>),
+    Table(Rc<RefCell<Vec<(Value, Value)>>>),
+    Closure(Rc<Closure>),
+    Builtin(&'static str),
+}
+
+pub struct Closure {
+    params: Vec<String>,
+    rest: Option<String>,
+    body: BExprBody,
+    env: Env,
+}
+
+/// Variables are looked up through a chain of frames holding one variable
+/// each, so closures can share the tail of their environment
+#[derive(Clone, Default)]
+struct Env(Option<Rc<Frame>>);
+
+struct Frame {
+    name: String,
+    value: RefCell<Value>,
+    parent: Env,
+}
+
+impl Env {
+    fn bind(&self, name: &str, value: Value) -> Env {
+        Env(Some(Rc::new(Frame {
+            name: name.to_owned(),
+            value: RefCell::new(value),
+            parent: self.clone(),
+        })))
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Frame> {
+        let mut env = self;
+
+        while let Some(frame) = &env.0 {
+            if frame.name == name {
+                return Some(frame);
+            }
+
+            env = &frame.parent;
+        }
+
+        None
+    }
+}
+
+#[derive(Debug)]
+pub struct EvalError {
+    pub message: String,
+    /// Where in the transformer things went wrong
+    pub span: Span,
+}
+
+/// Why the evaluation of an expression stopped early
+enum Unwind {
+    Error(EvalError),
+    /// A loop jumping back to its start, with the next values of its
+    /// variables
+    Recur(String, Vec<Value>),
+}
+
+/// What is left of an expression after a step of evaluating it
+enum Next {
+    Value(Value),
+    /// The expression in tail position, which is evaluated in place of the
+    /// one the step was given so tail calls don't nest
+    Eval(Rc<BExpr>, Env),
+}
+
+fn error<T>(message: impl Into<String>, span: Span) -> Result<T, Unwind> {
+    Err(Unwind::Error(EvalError {
+        message: message.into(),
+        span,
+    }))
+}
+
+impl Procedure {
+    /// Run the transformer on the arguments of a use of the macro, giving the
+    /// datum the use expands into
+    pub fn call(&self, args: &[Datum], span: Span) -> Result<Datum, EvalError> {
+        if args.len() < self.params.len() || (args.len() > self.params.len() && self.rest.is_none())
+        {
+            return Err(EvalError {
+                message: format!(
+                    "this macro takes {}{} argument{}, but was given {}",
+                    if self.rest.is_some() { "at least " } else { "" },
+                    self.params.len(),
+                    if self.params.len() == 1 { "" } else { "s" },
+                    args.len()
+                ),
+                span: self.span,
+            });
+        }
+
+        let (args, rest) = args.split_at(self.params.len());
+        let mut env = Env::default();
+
+        for (param, arg) in self.params.iter().zip(args) {
+            env = env.bind(param, Value::from_datum(arg)?);
+        }
+
+        if let Some(param) = &self.rest {
+            let rest = rest
+                .iter()
+                .map(Value::from_datum)
+                .collect::<Result<Vec<_>, _>>()?;
+            env = env.bind(param, list(rest.into_iter()));
+        }
+
+        // the body is run in the same form the compiler would compile it in,
+        // so only the core forms have to be interpreted
+        let body = match BExpr::Lam(Vec::new(), None, self.body.clone(), self.span)
+            .remove_let_variants()
+            .lift_defines()
+            .remove_derived_forms()
+            .recognise_loops()
+            .remove_let()
+        {
+            BExpr::Lam(_, _, body, _) => body,
+            _ => unreachable!("lowering keeps the lambda"),
+        };
+
+        let mut interp = Interpreter { steps: 0, depth: 0 };
+
+        let value = match interp.eval_body(&body, &env) {
+            Ok(value) => value,
+            Err(Unwind::Error(e)) => return Err(e),
+            Err(Unwind::Recur(..)) => unreachable!("a loop is always around its recur"),
+        };
+
+        value.to_datum(span).map_err(|message| EvalError {
+            message,
+            span: self.span,
+        })
+    }
+}
+
+struct Interpreter {
+    steps: usize,
+    depth: usize,
+}
+
+impl Interpreter {
+    fn eval(&mut self, expr: &BExpr, env: &Env) -> Result<Value, Unwind> {
+        if self.depth >= MAX_EVAL_DEPTH {
+            return error(
+                format!(
+                    "calls nested more than {} deep while expanding this macro",
+                    MAX_EVAL_DEPTH
+                ),
+                expr.span(),
+            );
+        }
+
+        self.depth += 1;
+        let mut next = self.step(expr, env);
+
+        let result = loop {
+            match next {
+                Ok(Next::Eval(expr, env)) => next = self.step(&expr, &env),
+                Ok(Next::Value(value)) => break Ok(value),
+                Err(e) => break Err(e),
+            }
+        };
+
+        self.depth -= 1;
+
+        result
+    }
+
+    /// Evaluate `expr` up to the expression in its tail position
+    fn step(&mut self, expr: &BExpr, env: &Env) -> Result<Next, Unwind> {
+        self.steps += 1;
+
+        if self.steps > MAX_STEPS {
+            return error(
+                "this macro is taking too long to expand, it might be stuck in a loop",
+                expr.span(),
+            );
+        }
+
+        let value = match expr {
+            BExpr::Var(n, s) => match env.lookup(n) {
+                Some(frame) => frame.value.borrow().clone(),
+                None => {
+                    return error(
+                        format!(
+                            "unbound variable `{}`, a macro can only use its own variables and builtins",
+                            n
+                        ),
+                        *s,
+                    )
+                }
+            },
+            BExpr::Lit(l, _) => Value::from_literal(l),
+            BExpr::BuiltinIdent(n, s) => match BUILTINS.iter().find(|(name, _)| name == n) {
+                Some((name, _)) => Value::Builtin(name),
+                None => return error(format!("unknown builtin `{}`", n), *s),
+            },
+            BExpr::Set(n, e, s) => {
+                let value = self.eval(e, env)?;
+
+                match env.lookup(n) {
+                    Some(frame) => *frame.value.borrow_mut() = value,
+                    None => return error(format!("cannot `set!` undefined variable `{}`", n), *s),
+                }
+
+                Value::Null
+            }
+            BExpr::If(c, ift, iff, _) => {
+                let branch = if self.eval(c, env)?.is_truthy() {
+                    ift
+                } else {
+                    iff
+                };
+
+                return Ok(Next::Eval(branch.clone(), env.clone()));
+            }
+            BExpr::Lam(params, rest, body, _) => Value::Closure(Rc::new(Closure {
+                params: params.clone(),
+                rest: rest.clone(),
+                body: body.clone(),
+                env: env.clone(),
+            })),
+            BExpr::App(f, args, s) => {
+                // a `let` after lowering, its body is run without making a closure
+                if let BExpr::Lam(params, None, body, _) = &**f {
+                    if params.len() == args.len() {
+                        let mut let_env = env.clone();
+
+                        for (param, arg) in params.iter().zip(args) {
+                            let_env = let_env.bind(param, self.eval(arg, env)?);
+                        }
+
+                        return self.step_body(body, &let_env);
+                    }
+                }
+
+                let f = self.eval(f, env)?;
+                let args = args
+                    .iter()
+                    .map(|a| self.eval(a, env))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                return self.apply(f, args, *s);
+            }
+            BExpr::Loop(name, bindings, body, _) => {
+                let mut values = bindings
+                    .iter()
+                    .map(|(_, e)| self.eval(e, env))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                loop {
+                    let loop_env = bindings
+                        .iter()
+                        .zip(values)
+                        .fold(env.clone(), |env, ((n, _), v)| env.bind(n, v));
+
+                    match self.eval_body(body, &loop_env) {
+                        Err(Unwind::Recur(n, next)) if &n == name => values = next,
+                        result => break result?,
+                    }
+                }
+            }
+            BExpr::Recur(name, _, args, _) => {
+                let args = args
+                    .iter()
+                    .map(|a| self.eval(a, env))
+                    .collect::<Result<Vec<_>, _>>()?;
+
+                return Err(Unwind::Recur(name.clone(), args));
+            }
+            BExpr::MacroUse(_, _, s) | BExpr::LetSyntax(_, _, s) | BExpr::LetRecSyntax(_, _, s) => {
+                return error("macros can't be used inside the body of a `define-macro`", *s)
+            }
+            BExpr::Let(..)
+            | BExpr::LetStar(..)
+            | BExpr::LetRec(..)
+            | BExpr::NamedLet(..)
+            | BExpr::Cond(..)
+            | BExpr::Case(..)
+            | BExpr::And(..)
+            | BExpr::Or(..)
+            | BExpr::When(..)
+            | BExpr::Unless(..)
+            | BExpr::Begin(..)
+            | BExpr::Guard(..)
+            | BExpr::Do(..) => unreachable!("derived forms are removed before interpreting"),
+        };
+
+        Ok(Next::Value(value))
+    }
+
+    fn eval_body(&mut self, body: &BExprBody, env: &Env) -> Result<Value, Unwind> {
+        match self.step_body(body, env)? {
+            Next::Value(value) => Ok(value),
+            Next::Eval(expr, env) => self.eval(&expr, &env),
+        }
+    }
+
+    /// Run the body up to the expression in its tail position
+    fn step_body(&mut self, body: &BExprBody, env: &Env) -> Result<Next, Unwind> {
+        for item in &body.0 {
+            match item {
+                BExprBodyExpr::Expr(e) => {
+                    self.eval(e, env)?;
+                }
+                BExprBodyExpr::DefSyntax(_, _, s) => {
+                    return error(
+                        "macros can't be defined inside the body of a `define-macro`",
+                        *s,
+                    )
+                }
+                BExprBodyExpr::Def(..) => unreachable!("defines are lifted before interpreting"),
+            }
+        }
+
+        Ok(Next::Eval(body.1.clone(), env.clone()))
+    }
+
+    /// Call `f` with `args`, a variadic function is given a list of any after
+    /// its parameters. The body of a closure is left to be evaluated in the
+    /// caller's place.
+    fn apply(&mut self, f: Value, args: Vec<Value>, span: Span) -> Result<Next, Unwind> {
+        match f {
+            Value::Closure(c) => {
+                check_arity(c.params.len(), c.rest.is_some(), args.len(), span)?;
+
+                let mut args = args.into_iter();
+                let mut env = c.env.clone();
+
+                for (param, arg) in c.params.iter().zip(&mut args) {
+                    env = env.bind(param, arg);
+                }
+
+                if let Some(rest) = &c.rest {
+                    env = env.bind(rest, list(args));
+                }
+
+                self.step_body(&c.body, &env)
+            }
+            Value::Builtin(name) => {
+                check_arity(arity(name), false, args.len(), span)?;
+
+                call_builtin(name, &args, span).map(Next::Value)
+            }
+            f => error(format!("`{}` isn't a function", f.describe()), span),
+        }
+    }
+}
+
+fn check_arity(params: usize, variadic: bool, given: usize, span: Span) -> Result<(), Unwind> {
+    if given == params || (variadic && given > params) {
+        return Ok(());
+    }
+
+    error(
+        format!(
+            "this function takes {}{} argument{}, but was given {}",
+            if variadic { "at least " } else { "" },
+            params,
+            if params == 1 { "" } else { "s" },
+            given
+        ),
+        span,
+    )
+}
+
+/// The builtins a transformer can use, and how many arguments each takes
+const BUILTINS: &[(&str, usize)] = &[
+    ("tostring", 1),
+    ("display", 1),
+    ("+", 2),
+    ("-", 2),
+    ("*", 2),
+    ("/", 2),
+    ("%", 2),
+    ("^", 2),
+    ("<", 2),
+    ("<=", 2),
+    (">", 2),
+    (">=", 2),
+    ("cons", 2),
+    ("cons?", 1),
+    ("null?", 1),
+    ("car", 1),
+    ("cdr", 1),
+    ("string-concat", 2),
+    ("string-chars", 1),
+    ("ht-new", 0),
+    ("ht-set!", 3),
+    ("ht-get", 2),
+    ("ht-del!", 2),
+    ("ht-keys", 1),
+    ("eq?", 2),
+    ("append", 2),
+    ("char?", 1),
+    ("char->integer", 1),
+    ("integer->char", 1),
+    ("char=?", 2),
+    ("symbol?", 1),
+    ("symbol->string", 1),
+    ("string->symbol", 1),
+];
+
+fn arity(builtin: &str) -> usize {
+    BUILTINS
+        .iter()
+        .find(|(name, _)| *name == builtin)
+        .map_or(0, |(_, arity)| *arity)
+}
+
+fn call_builtin(name: &'static str, args: &[Value], span: Span) -> Result<Value, Unwind> {
+    use Value::*;
+
+    let value = match (name, args) {
+        ("tostring", [v]) => String(v.to_string().into()),
+        ("display", [v]) => {
+            // there's no program output at compile time, this is for debugging
+            // transformers
+            eprintln!("{}", v);
+            Null
+        }
+        ("+", [a, b]) => arithmetic(a, b, i64::checked_add, |a, b| a + b, name, span)?,
+        ("-", [a, b]) => arithmetic(a, b, i64::checked_sub, |a, b| a - b, name, span)?,
+        ("*", [a, b]) => arithmetic(a, b, i64::checked_mul, |a, b| a * b, name, span)?,
+        ("/", [Int(_), Int(0)]) | ("%", [Int(_), Int(0)]) => return error("divide by zero", span),
+        ("/", [a, b]) => arithmetic(a, b, i64::checked_div, |a, b| a / b, name, span)?,
+        ("%", [Int(a), Int(b)]) => Int(a.wrapping_rem(*b)),
+        ("^", [Int(a), Int(b)]) => Int(a ^ b),
+        ("<", [a, b]) => compare(a, b, |o| o.is_lt(), name, span)?,
+        ("<=", [a, b]) => compare(a, b, |o| o.is_le(), name, span)?,
+        (">", [a, b]) => compare(a, b, |o| o.is_gt(), name, span)?,
+        (">=", [a, b]) => compare(a, b, |o| o.is_ge(), name, span)?,
+        ("cons", [a, b]) => Pair(Rc::new(a.clone()), Rc::new(b.clone())),
+        ("cons?", [v]) => Bool(matches!(v, Pair(..))),
+        ("null?", [v]) => Bool(matches!(v, Null)),
+        ("car", [Pair(car, _)]) => (**car).clone(),
+        ("cdr", [Pair(_, cdr)]) => (**cdr).clone(),
+        ("string-concat", [a, b]) => match (a.as_text(), b.as_text()) {
+            (Some(a), Some(b)) => String((a + &b).into()),
+            _ => return type_error(name, args, span),
+        },
+        ("string-chars", [String(s)]) => list(s.chars().map(Char)),
+        ("ht-new", []) => Table(Rc::new(RefCell::new(Vec::new()))),
+        ("ht-set!", [Table(t), k, v]) => {
+            let mut t = t.borrow_mut();
+
+            match t.iter_mut().find(|(key, _)| key.equals(k)) {
+                Some(entry) => entry.1 = v.clone(),
+                None => t.push((k.clone(), v.clone())),
+            }
+
+            Null
+        }
+        ("ht-get", [Table(t), k]) => t
+            .borrow()
+            .iter()
+            .find(|(key, _)| key.equals(k))
+            .map_or(Null, |(_, v)| v.clone()),
+        ("ht-del!", [Table(t), k]) => {
+            let mut t = t.borrow_mut();
+            let len = t.len();
+            t.retain(|(key, _)| !key.equals(k));
+
+            Bool(t.len() != len)
+        }
+        ("ht-keys", [Table(t)]) => list(t.borrow().iter().map(|(k, _)| k.clone())),
+        ("eq?", [a, b]) => Bool(a.equals(b)),
+        ("append", [a, b]) => match a.list_items() {
+            Some(items) => items
+                .into_iter()
+                .rev()
+                .fold(b.clone(), |tail, v| Pair(Rc::new(v), Rc::new(tail))),
+            None => return type_error(name, args, span),
+        },
+        ("char?", [v]) => Bool(matches!(v, Char(_))),
+        ("char->integer", [Char(c)]) => Int(*c as i64),
+        ("integer->char", [Int(i)]) => match u32::try_from(*i).ok().and_then(char::from_u32) {
+            Some(c) => Char(c),
+            None => return error(format!("{} is not a unicode scalar value", i), span),
+        },
+        ("char=?", [Char(a), Char(b)]) => Bool(a == b),
+        ("symbol?", [v]) => Bool(matches!(v, Symbol(_))),
+        ("symbol->string", [Symbol(s)]) => String(strip_marks(s).into()),
+        ("string->symbol", [String(s)]) => Symbol(s.clone()),
+        _ => return type_error(name, args, span),
+    };
+
+    Ok(value)
+}
+
+fn type_error(name: &str, args: &[Value], span: Span) -> Result<Value, Unwind> {
+    let args = args.iter().map(Value::describe).collect::<Vec<_>>();
+
+    error(
+        format!("`{}` can't be used on {}", name, args.join(" and ")),
+        span,
+    )
+}
+
+/// Integer arithmetic if both operands are integers, and floating point
+/// otherwise
+fn arithmetic(
+    a: &Value,
+    b: &Value,
+    int_op: impl Fn(i64, i64) -> Option<i64>,
+    float_op: impl Fn(f64, f64) -> f64,
+    name: &str,
+    span: Span,
+) -> Result<Value, Unwind> {
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => match int_op(*a, *b) {
+            Some(v) => Ok(Value::Int(v)),
+            None => error(format!("`{}` of {} and {} overflowed", name, a, b), span),
+        },
+        _ => match (a.as_float(), b.as_float()) {
+            (Some(a), Some(b)) => Ok(Value::Float(float_op(a, b))),
+            _ => type_error(name, &[a.clone(), b.clone()], span),
+        },
+    }
+}
+
+fn compare(
+    a: &Value,
+    b: &Value,
+    test: impl Fn(std::cmp::Ordering) -> bool,
+    name: &str,
+    span: Span,
+) -> Result<Value, Unwind> {
+    let ordering = match (a, b) {
+        (Value::Int(a), Value::Int(b)) => Some(a.cmp(b)),
+        _ => match (a.as_float(), b.as_float()) {
+            (Some(a), Some(b)) => a.partial_cmp(&b),
+            _ => return type_error(name, &[a.clone(), b.clone()], span),
+        },
+    };
+
+    Ok(Value::Bool(ordering.is_some_and(test)))
+}
+
+fn list(items: impl DoubleEndedIterator<Item = Value>) -> Value {
+    items.rev().fold(Value::Null, |tail, v| {
+        Value::Pair(Rc::new(v), Rc::new(tail))
+    })
+}
+
+impl Value {
+    fn from_literal(l: &Literal) -> Value {
+        match l {
+            Literal::String(s) => Value::String(s.as_str().into()),
+            Literal::Int(i) => Value::Int(*i),
+            Literal::Float(f) => Value::Float(*f),
+            Literal::Bool(b) => Value::Bool(*b),
+            Literal::Symbol(s) => Value::Symbol(s.as_str().into()),
+            Literal::Char(c) => Value::Char(*c),
+            Literal::Void => Value::Null,
+        }
+    }
+
+    /// The value of `datum` as quoted data, this fails if the datum is an atom
+    /// that isn't a valid literal, like a number that is out of range
+    fn from_datum(datum: &Datum) -> Result<Value, EvalError> {
+        match datum {
+            Datum::Symbol(s, _) => Ok(Value::Symbol(s.as_str().into())),
+            Datum::Atom(a, span) => match parse::parse_expression(a) {
+                Ok(BExpr::Lit(l, _)) => Ok(Value::from_literal(&l)),
+                Ok(_) => unreachable!("every atom is a literal"),
+                Err(e) => Err(EvalError {
+                    message: e.to_string(),
+                    span: *span,
+                }),
+            },
+            Datum::List(elems, tail, _) => {
+                let tail = match tail.as_deref() {
+                    Some(t) => Value::from_datum(t)?,
+                    None => Value::Null,
+                };
+
+                elems.iter().rev().try_fold(tail, |tail, d| {
+                    Ok(Value::Pair(Rc::new(Value::from_datum(d)?), Rc::new(tail)))
+                })
+            }
+        }
+    }
+
+    /// The code a transformer's result stands for, at the use of the macro
+    fn to_datum(&self, span: Span) -> Result<Datum, String> {
+        let datum = match self {
+            Value::Null => Datum::List(Vec::new(), None, span),
+            Value::Bool(b) => Datum::Atom(if *b { "#t" } else { "#f" }.to_owned(), span),
+            Value::Int(i) => Datum::Atom(i.to_string(), span),
+            Value::Float(f) if f.is_finite() => {
+                // floats have to be written with a `.` to read back as floats
+                let text = format!("{:?}", f);
+                let text = match text.find('e') {
+                    Some(i) if !text.contains('.') => format!("{}.0{}", &text[..i], &text[i..]),
+                    _ => text,
+                };
+
+                Datum::Atom(text, span)
+            }
+            Value::Char(c) => Datum::Atom(format!("#\\x{:x}", *c as u32), span),
+            Value::String(s) => Datum::Atom(quote_string(s), span),
+            Value::Symbol(s) => Datum::Symbol(s.to_string(), span),
+            Value::Pair(..) => {
+                let mut elems = Vec::new();
+                let mut v = self;
+
+                while let Value::Pair(car, cdr) = v {
+                    elems.push(car.to_datum(span)?);
+                    v = cdr;
+                }
+
+                let tail = match v {
+                    Value::Null => None,
+                    v => Some(Rc::new(v.to_datum(span)?)),
+                };
+
+                Datum::List(elems, tail, span)
+            }
+            v => {
+                return Err(format!(
+                    "this macro expanded into {}, which can't be written in a program",
+                    v.describe()
+                ))
+            }
+        };
+
+        Ok(datum)
+    }
+
+    fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Bool(false))
+    }
+
+    fn as_float(&self) -> Option<f64> {
+        match self {
+            Value::Int(i) => Some(*i as f64),
+            Value::Float(f) => Some(*f),
+            _ => None,
+        }
+    }
+
+    /// The text `string-concat` uses for this value
+    fn as_text(&self) -> Option<String> {
+        match self {
+            Value::Int(i) => Some(char::from(*i as u8).to_string()),
+            Value::Char(c) => Some(c.to_string()),
+            Value::String(s) => Some(s.to_string()),
+            _ => None,
+        }
+    }
+
+    fn list_items(&self) -> Option<Vec<Value>> {
+        let mut items = Vec::new();
+        let mut v = self;
+
+        while let Value::Pair(car, cdr) = v {
+            items.push((**car).clone());
+            v = cdr;
+        }
+
+        matches!(v, Value::Null).then_some(items)
+    }
+
+    /// `eq?`, which compares everything but functions and tables by value
+    fn equals(&self, other: &Value) -> bool {
+        match (self, other) {
+            (Value::Null, Value::Null) => true,
+            (Value::Bool(a), Value::Bool(b)) => a == b,
+            (Value::Int(a), Value::Int(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a == b,
+            (Value::Char(a), Value::Char(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Symbol(a), Value::Symbol(b)) => strip_marks(a) == strip_marks(b),
+            (Value::Pair(a, b), Value::Pair(c, d)) => a.equals(c) && b.equals(d),
+            (Value::Table(a), Value::Table(b)) => Rc::ptr_eq(a, b),
+            (Value::Closure(a), Value::Closure(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+
+    /// This value for an error message
+    fn describe(&self) -> String {
+        match self {
+            Value::String(s) => quote_string(s),
+            Value::Symbol(s) => format!("'{}", strip_marks(s)),
+            Value::Null => "'()".to_owned(),
+            Value::Table(_) => "a hash table".to_owned(),
+            Value::Closure(_) | Value::Builtin(..) => "a function".to_owned(),
+            v => v.to_string(),
+        }
+    }
+}
+
+fn quote_string(s: &str) -> String {
+    let mut out = String::from("\"");
+
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            c => out.push(c),
+        }
+    }
+
+    out.push('"');
+    out
+}
+
+/// The shortest text that reads back as `x`, printed like `float_to_string` in
+/// the runtime does: fixed notation unless the exponent is below -5 or above
+/// 16, and always with a `.` or an `e` so it still looks like a float.
+fn float_to_string(x: f64) -> String {
+    if x.is_nan() {
+        return "+nan.0".to_owned();
+    }
+
+    if x.is_infinite() {
+        return format!("{}inf.0", if x > 0.0 { '+' } else { '-' });
+    }
+
+    // rust already gives the shortest digits that round-trip
+    let shortest = format!("{:e}", x);
+    let (mantissa, exponent) = shortest.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    let precision = mantissa.chars().filter(char::is_ascii_digit).count() as i32;
+
+    let res = if (-5..17).contains(&exponent) {
+        let decimals = (precision - 1 - exponent).max(0) as usize;
+        format!("{:.*}", decimals, x)
+    } else {
+        // the same as C's `%g`
+        format!(
+            "{}e{}{:02}",
+            mantissa,
+            if exponent < 0 { '-' } else { '+' },
+            exponent.abs()
+        )
+    };
+
+    if res.contains(&['.', 'e'][..]) {
+        res
+    } else {
+        res + ".0"
+    }
+}
+
+/// The same text `tostring` gives at runtime
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => write!(f, "()"),
+            Value::Bool(b) => write!(f, "{}", if *b { "#t" } else { "#f" }),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{}", float_to_string(*x)),
+            Value::Char(c) => write!(f, "{}", c),
+            Value::String(s) => write!(f, "{}", s),
+            Value::Symbol(s) => write!(f, "{}", strip_marks(s)),
+            Value::Pair(..) => {
+                let mut v = self;
+                let mut sep = "";
+
+                write!(f, "(")?;
+
+                while let Value::Pair(car, cdr) = v {
+                    write!(f, "{}{}", sep, car)?;
+                    sep = " ";
+                    v = cdr;
+                }
+
+                match v {
+                    Value::Null => write!(f, ")"),
+                    v => write!(f, " . {})", v),
+                }
+            }
+            Value::Table(_) => write!(f, "hash table"),
+            Value::Closure(_) | Value::Builtin(..) => write!(f, "closure"),
+        }
+    }
+}
@@ -8,6 +8,8 @@ use termcolor::{Color, ColorSpec, WriteColor};
 use std::collections::HashSet;
 use std::{io::Result, rc::Rc};
 
+use crate::diagnostics::Span;
+use crate::expr::LamInfo;
 use crate::literals::Literal;
 
 #[derive(Debug, Clone, BoundTerm)]
@@ -19,34 +21,111 @@ pub enum LExpr {
     If(Rc<LExpr>, Rc<LExpr>, Rc<LExpr>),
     Lifted(Ignore<usize>),
     CallOne(Rc<LExpr>, Rc<LExpr>),
-    CallTwo(Rc<LExpr>, Rc<LExpr>, Rc<LExpr>),
+    /// The span is the call site
+    Call(Rc<LExpr>, Vec<Rc<LExpr>>, Rc<LExpr>, Ignore<Span>),
+    /// Enter the loop lambda, binding the loop's name to it and each of the
+    /// loop's variables to its value
+    Loop(Ignore<usize>, Var<String>, Vec<(Var<String>, Rc<LExpr>)>),
+    Recur(Var<String>, Vec<Var<String>>, Vec<Rc<LExpr>>),
+    BuiltinCall(Ignore<String>, Vec<Rc<LExpr>>),
+    /// Bind each variable to its value in the current environment, values
+    /// without a variable are only evaluated
+    Let(Vec<(Option<Var<String>>, Rc<LExpr>)>, Rc<LExpr>),
 }
 
 #[derive(Debug, Clone)]
 pub struct LiftedLambda {
     pub id: usize,
+    /// Continuations and loops aren't written as lambdas in the source
+    pub info: Option<LamInfo>,
     pub params: Vec<FreeVar<String>>,
+    /// For a variadic lambda, the parameter taking a list of the arguments
+    /// after `params`
+    pub rest: Option<FreeVar<String>>,
+    /// Continuations don't have a continuation of their own
+    pub cont: Option<FreeVar<String>>,
     pub freevars: HashSet<FreeVar<String>>,
+    /// The variables bound by a `Let` in the body, they are kept in the
+    /// environment along with the free variables
+    pub locals: HashSet<FreeVar<String>>,
+    /// For the body of a loop, the loop's name
+    pub loop_name: Option<FreeVar<String>>,
     pub body: Rc<LExpr>,
 }
 
 impl LiftedLambda {
     pub fn new(
         id: usize,
+        info: Option<LamInfo>,
         params: Vec<FreeVar<String>>,
+        rest: Option<FreeVar<String>>,
+        cont: Option<FreeVar<String>>,
         freevars: HashSet<FreeVar<String>>,
         body: Rc<LExpr>,
     ) -> Self {
         Self {
             id,
+            info,
             params,
+            rest,
+            cont,
             freevars,
+            locals: body.locals(),
+            loop_name: None,
             body,
         }
     }
+
+    pub fn for_loop(self, name: FreeVar<String>) -> Self {
+        Self {
+            loop_name: Some(name),
+            ..self
+        }
+    }
 }
 
 impl LExpr {
+    /// The variables bound by each `Let` in this expression, not counting
+    /// those in lifted lambdas
+    pub fn locals(&self) -> HashSet<FreeVar<String>> {
+        fn go(e: &LExpr, locals: &mut HashSet<FreeVar<String>>) {
+            match e {
+                LExpr::Var(_) | LExpr::Lit(_) | LExpr::BuiltinIdent(_) | LExpr::Lifted(_) => {}
+                LExpr::SetThen(_, v, c) | LExpr::CallOne(v, c) => {
+                    go(v, locals);
+                    go(c, locals);
+                }
+                LExpr::If(c, ift, iff) => {
+                    go(c, locals);
+                    go(ift, locals);
+                    go(iff, locals);
+                }
+                LExpr::Call(f, args, k, _) => {
+                    go(f, locals);
+                    args.iter().for_each(|a| go(a, locals));
+                    go(k, locals);
+                }
+                LExpr::Loop(_, _, bindings) => bindings.iter().for_each(|(_, e)| go(e, locals)),
+                LExpr::Recur(_, _, args) | LExpr::BuiltinCall(_, args) => {
+                    args.iter().for_each(|a| go(a, locals))
+                }
+                LExpr::Let(bindings, body) => {
+                    for (v, e) in bindings {
+                        if let Some(Var::Free(v)) = v {
+                            locals.insert(v.clone());
+                        }
+                        go(e, locals);
+                    }
+                    go(body, locals);
+                }
+            }
+        }
+
+        let mut locals = HashSet::new();
+        go(self, &mut locals);
+        locals
+    }
+
     pub fn pretty<'a, D>(&self, allocator: &'a D) -> DocBuilder<'a, D, ColorSpec>
     where
         D: DocAllocator<'a, ColorSpec>,
@@ -106,20 +185,103 @@ impl LExpr {
                     .group()
                     .parens()
             }
-            LExpr::CallTwo(f, v, c) => {
+            LExpr::Call(f, args, c, _) => {
                 let f_pret = f.pretty(allocator);
-                let v_pret = v.pretty(allocator);
+                let args_pret = allocator.concat(
+                    args.iter()
+                        .map(|e| allocator.space().append(e.pretty(allocator))),
+                );
                 let c_pret = c.pretty(allocator);
 
                 f_pret
                     .annotate(ColorSpec::new().set_fg(Some(Color::Blue)).clone())
-                    .append(allocator.space())
-                    .append(v_pret)
+                    .append(args_pret)
                     .append(allocator.space())
                     .append(c_pret)
                     .group()
                     .parens()
             }
+            LExpr::Loop(Ignore(l), name, bindings) => {
+                let bindings_pret = allocator
+                    .intersperse(
+                        bindings.iter().map(|(v, e)| {
+                            allocator
+                                .as_string(v)
+                                .annotate(ColorSpec::new().set_fg(Some(Color::Green)).clone())
+                                .append(allocator.space())
+                                .append(e.pretty(allocator))
+                                .group()
+                                .parens()
+                        }),
+                        allocator.space(),
+                    )
+                    .parens();
+
+                allocator
+                    .text("loop")
+                    .annotate(ColorSpec::new().set_fg(Some(Color::Magenta)).clone())
+                    .append(allocator.space())
+                    .append(allocator.as_string(name))
+                    .append(allocator.space())
+                    .append(bindings_pret)
+                    .append(allocator.space())
+                    .append(allocator.text("lifted-lambda@"))
+                    .append(allocator.as_string(l))
+                    .group()
+                    .parens()
+            }
+            LExpr::Recur(name, _, args) => allocator
+                .text("recur")
+                .annotate(ColorSpec::new().set_fg(Some(Color::Magenta)).clone())
+                .append(allocator.space())
+                .append(allocator.as_string(name))
+                .append(allocator.space())
+                .append(
+                    allocator
+                        .intersperse(args.iter().map(|e| e.pretty(allocator)), allocator.space()),
+                )
+                .group()
+                .parens(),
+            LExpr::BuiltinCall(Ignore(i), args) => {
+                let args_pret = allocator.concat(
+                    args.iter()
+                        .map(|e| allocator.space().append(e.pretty(allocator))),
+                );
+
+                allocator
+                    .as_string(i)
+                    .annotate(ColorSpec::new().set_fg(Some(Color::Blue)).clone())
+                    .append(args_pret)
+                    .group()
+                    .parens()
+            }
+            LExpr::Let(bindings, body) => {
+                let bindings_pret = allocator
+                    .intersperse(
+                        bindings.iter().map(|(v, e)| {
+                            allocator
+                                .text(v.as_ref().map_or("_".to_owned(), |v| v.to_string()))
+                                .annotate(ColorSpec::new().set_fg(Some(Color::Green)).clone())
+                                .append(allocator.space())
+                                .append(e.pretty(allocator))
+                                .group()
+                                .parens()
+                        }),
+                        allocator.space(),
+                    )
+                    .parens();
+
+                allocator
+                    .text("let")
+                    .annotate(ColorSpec::new().set_fg(Some(Color::Magenta)).clone())
+                    .append(allocator.space())
+                    .append(bindings_pret)
+                    .append(allocator.line())
+                    .append(body.pretty(allocator))
+                    .nest(1)
+                    .group()
+                    .parens()
+            }
         }
     }
 
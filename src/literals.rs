@@ -6,6 +6,9 @@ pub enum Literal {
     String(String),
     Int(i64),
     Float(f64),
+    Bool(bool),
+    Symbol(String),
+    Char(char),
     Void,
 }
 
@@ -25,6 +28,15 @@ impl Literal {
             Literal::Float(v) => allocator
                 .as_string(v)
                 .annotate(ColorSpec::new().set_fg(Some(Color::Yellow)).clone()),
+            Literal::Bool(b) => allocator
+                .text(if *b { "#t" } else { "#f" })
+                .annotate(ColorSpec::new().set_fg(Some(Color::Yellow)).clone()),
+            Literal::Symbol(s) => allocator
+                .text(format!("'{}", s))
+                .annotate(ColorSpec::new().set_fg(Some(Color::Yellow)).clone()),
+            Literal::Char(c) => allocator
+                .text(format!("#\\x{:x}", *c as u32))
+                .annotate(ColorSpec::new().set_fg(Some(Color::Yellow)).clone()),
             Literal::Void => allocator
                 .text("void")
                 .annotate(ColorSpec::new().set_fg(Some(Color::Yellow)).clone()),
@@ -1,12 +1,22 @@
+// moniker's `BoundTerm` derive expands to impls inside a const block
+#![allow(non_local_definitions)]
+
 pub mod base_expr;
 pub mod cdsl;
 pub mod codegen;
 pub mod cont_expr;
+pub mod diagnostics;
+pub mod error;
+pub mod expand;
 pub mod expr;
 pub mod flat_expr;
+pub mod interp;
 pub mod lifted_expr;
 pub mod literals;
 pub mod parse;
+pub mod scope;
+pub mod syntax;
+pub mod syntax_rules;
 pub mod utils;
 
 use base_expr::BExpr;
@@ -15,6 +25,8 @@ use cdsl::CExpr;
 use cdsl::CStmt;
 use cdsl::CType;
 use cdsl::ToC;
+use diagnostics::{SourceFile, Span};
+use error::CompileError;
 use failure::{format_err, Error};
 use include_dir::{include_dir, Dir};
 use std::collections::HashMap;
@@ -33,6 +45,13 @@ use termcolor::StandardStream;
 
 const RUNTIME_DIR: Dir<'_> = include_dir!("src/core");
 
+/// Exit code used when the program being compiled is wrong
+const EXIT_PROGRAM_ERROR: i32 = 1;
+/// Exit code used when the compiler itself is broken, the same code as a panic
+const EXIT_COMPILER_BUG: i32 = 101;
+/// The compiler recurses over the program, so deeply nested programs and macro
+/// expansions need more than the main thread's stack
+const COMPILER_STACK_SIZE: usize = 1 << 30;
 
 #[derive(StructOpt, Debug)]
 enum Cmd {
@@ -47,7 +66,7 @@ enum Cmd {
             default_value = "a.out"
         )]
         output: PathBuf,
-    }
+    },
 }
 
 #[derive(StructOpt, Debug)]
@@ -67,6 +86,14 @@ struct Opt {
 }
 
 fn main() -> Result<(), Error> {
+    std::thread::Builder::new()
+        .stack_size(COMPILER_STACK_SIZE)
+        .spawn(run)?
+        .join()
+        .unwrap_or_else(|_| std::process::exit(EXIT_COMPILER_BUG))
+}
+
+fn run() -> Result<(), Error> {
     let opts = Opt::from_args();
 
     let input_exp = if let Some(input_path) = opts.input.as_ref() {
@@ -77,43 +104,32 @@ fn main() -> Result<(), Error> {
         buf
     };
 
-    let body = match parse::parse(&input_exp) {
-        Ok(body) => body,
-        Err(err) => {
-            println!("{}", err);
-            return Ok(())
+    let source_name = opts
+        .input
+        .as_ref()
+        .map_or_else(|| "<stdin>".to_owned(), |p| p.display().to_string());
+    let source = SourceFile::new(source_name, &input_exp);
+
+    let generated_source = match compile(&opts, &source) {
+        Ok(generated_source) => generated_source,
+        Err(errors) => {
+            for err in &errors {
+                err.to_diagnostic()
+                    .emit(&source, StandardStream::stderr(ColorChoice::Auto))?;
+            }
+
+            if errors.len() > 1 {
+                eprintln!("aborting due to {} errors", errors.len());
+            }
+
+            std::process::exit(if errors.iter().any(CompileError::is_internal) {
+                EXIT_COMPILER_BUG
+            } else {
+                EXIT_PROGRAM_ERROR
+            });
         }
     };
 
-    let expr = BExpr::App(Rc::new(BExpr::Lam(Vec::new(), body)), Vec::new());
-
-    if opts.debug {
-        eprintln!("\n\nexpr after parsing: ");
-        let _ = expr.pretty_print(StandardStream::stderr(ColorChoice::Auto));
-        eprintln!("");
-        eprintln!("\n\nexpr after binding: ");
-        let _ = expr.clone().into_expr().pretty_print(StandardStream::stderr(ColorChoice::Auto));
-        eprintln!("");
-    }
-
-    let k = Rc::new(cont_expr::AExp::BuiltinIdent(moniker::Ignore(
-        "exit".into(),
-    )));
-
-    if opts.debug {
-        eprintln!("\n\nexpr after converting: ");
-        let _ = expr
-            .clone()
-            .into_expr()
-            .into_fexpr(k.clone())
-            .pretty_print(StandardStream::stderr(ColorChoice::Auto));
-        eprintln!("");
-    }
-
-    let (expr, lambdas) = expr.into_expr().into_fexpr(k).lift_lambdas();
-
-    let generated_source = do_codegen(&opts, expr, lambdas)?;
-
     let full_source = generate_program_source(&generated_source);
 
     if opts.debug {
@@ -127,8 +143,9 @@ fn main() -> Result<(), Error> {
     let make_stdout = match invoke_make(&build_dir) {
         Ok(s) => s,
         Err(e) => {
+            // the C we generate should always build, so this is on us
             eprintln!("{}", e);
-            return Ok(());
+            std::process::exit(EXIT_COMPILER_BUG);
         }
     };
 
@@ -221,42 +238,97 @@ fn generate_program_source(src: &str) -> String {
         src,
         r#"
 int main() {
-  struct closure_obj initial_closure = object_closure_one_new(main_lambda, NULL);
-  struct thunk initial_thunk = {
-    .closr = &initial_closure,
-    .one = {NULL},
-  };
+  static const struct lambda_info main_info = {.name = "main"};
+  struct closure_obj initial_closure = object_closure_new(main_lambda, NULL, &main_info);
 
   struct thunk *thnk_heap = malloc(sizeof(struct thunk));
-  memcpy(thnk_heap, &initial_thunk, sizeof(struct thunk));
+  thnk_heap->closr = &initial_closure;
+  thnk_heap->cont = NULL;
+  thnk_heap->nargs = 0;
   scheme_start(thnk_heap);
 }
 "#
     )
 }
 
+fn compile(opts: &Opt, source: &SourceFile) -> Result<String, Vec<CompileError>> {
+    let input = source.text;
+    let body = parse::parse(input).map_err(|e| vec![e])?;
+
+    let program_span = Span::new(0, input.len());
+    let expr = BExpr::App(
+        Rc::new(BExpr::Lam(Vec::new(), None, body, program_span)),
+        Vec::new(),
+        program_span,
+    );
+
+    if opts.debug {
+        eprintln!("\n\nexpr after parsing: ");
+        let _ = expr.pretty_print(StandardStream::stderr(ColorChoice::Auto));
+        eprintln!();
+    }
+
+    let expr = expand::expand(expr, input).map_err(|e| vec![e])?;
+
+    if opts.debug {
+        eprintln!("\n\nexpr after expanding macros: ");
+        let _ = expr.pretty_print(StandardStream::stderr(ColorChoice::Auto));
+        eprintln!();
+    }
+
+    let scope_errors = scope::check_scopes(&expr);
+    if !scope_errors.is_empty() {
+        return Err(scope_errors);
+    }
+
+    let expr = expr.into_expr().map_err(|e| vec![e])?;
+
+    if opts.debug {
+        eprintln!("\n\nexpr after binding: ");
+        let _ = expr.pretty_print(StandardStream::stderr(ColorChoice::Auto));
+        eprintln!();
+    }
+
+    let k = Rc::new(cont_expr::AExp::BuiltinIdent(moniker::Ignore(
+        "exit".into(),
+    )));
+
+    let expr = expr.into_fexpr(k);
+
+    if opts.debug {
+        eprintln!("\n\nexpr after converting: ");
+        let _ = expr.pretty_print(StandardStream::stderr(ColorChoice::Auto));
+        eprintln!();
+    }
+
+    let (expr, lambdas) = expr.lift_lambdas().map_err(|e| vec![e])?;
+
+    do_codegen(opts, expr, lambdas, source).map_err(|e| vec![e])
+}
+
 fn do_codegen(
     opts: &Opt,
     expr: lifted_expr::LExpr,
     lambdas: HashMap<usize, lifted_expr::LiftedLambda>,
-) -> Result<String, Error> {
+    source: &SourceFile,
+) -> Result<String, CompileError> {
     let mut output_buffer = String::new();
 
     if opts.debug {
         eprintln!("\n\nfinal expr before codegen: ");
         let _ = expr.pretty_print(StandardStream::stderr(ColorChoice::Auto));
-        eprintln!("");
+        eprintln!();
 
         for l in lambdas.values() {
             eprint!("lambda {}: ", l.id);
             let _ = l
                 .body
                 .pretty_print(StandardStream::stderr(ColorChoice::Auto));
-            eprintln!("");
+            eprintln!();
         }
     }
 
-    let (mut root_stmts, protos, decls) = codegen::do_codegen(expr, &lambdas);
+    let (mut root_stmts, protos, decls) = codegen::do_codegen(expr, &lambdas, source)?;
 
     for proto in &protos {
         writeln!(&mut output_buffer, "{}", proto.export())?;
@@ -274,8 +346,20 @@ fn do_codegen(
     let main_lambda = CDecl::Fun {
         name: "main_lambda".into(),
         typ: CType::Void,
-        args: vec![("input_obj".into(), CType::Ptr(Rc::new(CType::Struct("obj".into())))),
-                   ("input_env".into(), CType::Ptr(Rc::new(CType::Struct("env_obj".into()))))
+        args: vec![
+            ("nargs".into(), CType::Other("size_t".into())),
+            (
+                "args".into(),
+                CType::Ptr(Rc::new(CType::Ptr(Rc::new(CType::Struct("obj".into()))))),
+            ),
+            (
+                "cont".into(),
+                CType::Ptr(Rc::new(CType::Struct("obj".into()))),
+            ),
+            (
+                "input_env".into(),
+                CType::Ptr(Rc::new(CType::Struct("env_obj".into()))),
+            ),
         ],
         body: root_stmts,
     };
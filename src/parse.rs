@@ -1,150 +1,903 @@
 use std::rc::Rc;
 
-use crate::base_expr::{BExpr, BExprBody, BExprBodyExpr};
+use crate::base_expr::{BExpr, BExprBody, BExprBodyExpr, CondClause, Transformer};
+use crate::diagnostics::Span;
+use crate::error::CompileError;
+use crate::interp::Procedure;
 use crate::literals::Literal;
-use pest::{error::Error, Parser};
+use crate::syntax::Datum;
+use crate::syntax_rules::SyntaxRules;
+use pest::{
+    error::{Error, ErrorVariant, InputLocation},
+    Parser,
+};
 use pest_derive::Parser;
 
 #[derive(Parser)]
 #[grammar = "grammar.pest"]
 pub struct SchemeParser;
 
-pub fn parse(s: &str) -> Result<BExprBody, Error<Rule>> {
-    let mut pairs = SchemeParser::parse(Rule::program, s)?;
+pub fn parse(s: &str) -> Result<BExprBody, CompileError> {
+    let mut pairs = SchemeParser::parse(Rule::program, s)
+        .map_err(|err| unclosed_comment(s).unwrap_or_else(|| syntax_error(err)))?;
 
     let body = pairs.next().unwrap();
-    Ok(build_body_from_expr(body))
+    build_body_from_expr(body)
 }
 
-fn build_bexpr_from_expr(pair: pest::iterators::Pair<Rule>) -> BExpr {
+/// Parse the expansion of a macro, which is a definition or an expression
+pub fn parse_expansion(s: &str) -> Result<BExprBodyExpr, CompileError> {
+    let mut pairs = SchemeParser::parse(Rule::expansion, s).map_err(syntax_error)?;
+
+    build_bodyexpr_from_expr(pairs.next().unwrap())
+}
+
+/// Parse `s` as a single expression
+pub fn parse_expression(s: &str) -> Result<BExpr, CompileError> {
+    let mut pairs = SchemeParser::parse(Rule::expression, s).map_err(syntax_error)?;
+
+    build_bexpr_from_expr(pairs.next().unwrap())
+}
+
+/// Read `s` as a single datum without interpreting it
+pub fn read_syntax(s: &str) -> Result<Datum, CompileError> {
+    let mut pairs = SchemeParser::parse(Rule::syntax_datum, s).map_err(syntax_error)?;
+
+    Ok(build_syntax(pairs.next().unwrap()))
+}
+
+/// Whether `name` is a special form name or builtin, which always mean the
+/// same thing wherever they appear
+pub fn is_reserved(name: &str) -> bool {
+    SchemeParser::parse(Rule::reserved, name).is_ok()
+}
+
+fn rule_description(rule: &Rule) -> String {
+    match rule {
+        Rule::expr
+        | Rule::expression
+        | Rule::app
+        | Rule::macro_use
+        | Rule::if_form
+        | Rule::set_form
+        | Rule::let_form
+        | Rule::named_let_form
+        | Rule::let_star_form
+        | Rule::letrec_form
+        | Rule::do_form
+        | Rule::lambda_form
+        | Rule::cond_form
+        | Rule::case_form
+        | Rule::and_form
+        | Rule::or_form
+        | Rule::when_form
+        | Rule::unless_form
+        | Rule::begin_form
+        | Rule::guard_form
+        | Rule::quote_form
+        | Rule::let_syntax_form
+        | Rule::letrec_syntax_form => "an expression",
+        Rule::body | Rule::program => "a body",
+        Rule::expansion => "a definition or an expression",
+        Rule::variable | Rule::identifier | Rule::builtin => "an identifier",
+        Rule::keyword | Rule::reserved => "a special form name",
+        Rule::number | Rule::float | Rule::invalid_number | Rule::sign | Rule::exponent => {
+            "a number"
+        }
+        Rule::boolean => "a boolean",
+        Rule::null => "`()`",
+        Rule::quoted_string | Rule::string_inner => "a string",
+        Rule::string_inner_char => "a string character",
+        Rule::character => "a character",
+        Rule::literal => "a literal",
+        Rule::dot => "`.`",
+        Rule::arrow => "`=>`",
+        Rule::atom_end | Rule::delimiter => "a delimiter",
+        Rule::WHITESPACE => "whitespace",
+        Rule::COMMENT
+        | Rule::line_comment
+        | Rule::block_comment
+        | Rule::datum_comment
+        | Rule::datum_space
+        | Rule::unclosed_comment
+        | Rule::unclosed_comment_scan => "a comment",
+        Rule::definition | Rule::define_form | Rule::body_begin => "a definition",
+        Rule::define_header => "a name and parameters",
+        Rule::define_syntax_form => "a syntax definition",
+        Rule::define_macro_form => "a macro definition",
+        Rule::lambda_bindings => "parameters",
+        Rule::let_binder => "a let binding",
+        Rule::let_bindings => "let bindings",
+        Rule::do_binder => "a do binding",
+        Rule::do_bindings => "do bindings",
+        Rule::do_result => "a do test",
+        Rule::cond_clause => "a cond clause",
+        Rule::else_clause => "an else clause",
+        Rule::case_datums => "a list of datums",
+        Rule::case_clause => "a case clause",
+        Rule::guard_clauses => "a guard variable and clauses",
+        Rule::datum
+        | Rule::sexpr
+        | Rule::sexpr_list
+        | Rule::symbol
+        | Rule::syntax
+        | Rule::syntax_datum
+        | Rule::syntax_list
+        | Rule::syntax_prefix
+        | Rule::syntax_prefixed => "a datum",
+        Rule::quoted => "a quoted datum",
+        Rule::qq_list
+        | Rule::qq_quoted
+        | Rule::qq_template
+        | Rule::quasiquoted
+        | Rule::unquote
+        | Rule::unquote_splicing => "a quasiquote template",
+        Rule::syntax_literals => "a list of literals",
+        Rule::syntax_rules => "a `syntax-rules` transformer",
+        Rule::syntax_rule => "a syntax rule",
+        Rule::syntax_binding => "a macro binding",
+        Rule::syntax_bindings => "macro bindings",
+        Rule::EOI => "end of input",
+    }
+    .to_owned()
+}
+
+/// List the descriptions of `rules` the way pest does, but only mentioning
+/// each description once
+fn enumerate_rules(rules: &[Rule]) -> String {
+    let mut descriptions = Vec::new();
+
+    for description in rules.iter().map(rule_description) {
+        if !descriptions.contains(&description) {
+            descriptions.push(description);
+        }
+    }
+
+    match descriptions.split_last() {
+        Some((last, [])) => last.clone(),
+        Some((last, [first])) => format!("{} or {}", first, last),
+        Some((last, rest)) => format!("{}, or {}", rest.join(", "), last),
+        None => String::new(),
+    }
+}
+
+/// Find a block comment in `s` that is never closed
+fn unclosed_comment(s: &str) -> Option<CompileError> {
+    let pairs = SchemeParser::parse(Rule::unclosed_comment_scan, s).ok()?;
+    let pair = pairs
+        .flatten()
+        .find(|pair| pair.as_rule() == Rule::unclosed_comment)?;
+
+    Some(CompileError::Syntax {
+        message: "unterminated block comment".to_owned(),
+        span: pair.as_span().into(),
+    })
+}
+
+fn syntax_error(err: Error<Rule>) -> CompileError {
+    let span = match err.location {
+        InputLocation::Pos(p) => Span::new(p, p + 1),
+        InputLocation::Span((s, e)) => Span::new(s, e),
+    };
+
+    let message = match err.variant {
+        ErrorVariant::ParsingError {
+            positives,
+            negatives,
+        } => match (positives.is_empty(), negatives.is_empty()) {
+            (false, false) => format!(
+                "{} can't be used here; expected {}",
+                enumerate_rules(&negatives),
+                enumerate_rules(&positives)
+            ),
+            (true, false) => format!("{} can't be used here", enumerate_rules(&negatives)),
+            (false, true) => format!("expected {}", enumerate_rules(&positives)),
+            (true, true) => "unexpected input".to_owned(),
+        },
+        ErrorVariant::CustomError { message } => message,
+    };
+
+    CompileError::Syntax { message, span }
+}
+
+fn build_bexpr_from_expr(pair: pest::iterators::Pair<Rule>) -> Result<BExpr, CompileError> {
+    let span = pair.as_span().into();
+
     match pair.as_rule() {
         Rule::expr => build_bexpr_from_expr(pair.into_inner().next().unwrap()),
         Rule::literal => build_literal_from_expr(pair.into_inner().next().unwrap()),
-        Rule::builtin => BExpr::BuiltinIdent(pair.as_str().to_owned()),
+        Rule::builtin => Ok(BExpr::BuiltinIdent(pair.as_str().to_owned(), span)),
         Rule::if_form => build_if_from_expr(pair),
         Rule::set_form => build_set_from_expr(pair),
-        Rule::let_form => build_let_from_expr(pair),
+        Rule::let_form | Rule::let_star_form | Rule::letrec_form | Rule::named_let_form => {
+            build_let_from_expr(pair)
+        }
+        Rule::do_form => build_do_from_expr(pair),
         Rule::lambda_form => build_lambda_from_expr(pair),
+        Rule::quote_form => build_datum(pair.into_inner().next().unwrap()),
+        Rule::cond_form => build_cond_from_expr(pair),
+        Rule::case_form => build_case_from_expr(pair),
+        Rule::and_form => Ok(BExpr::And(build_exprs(pair.into_inner())?, span)),
+        Rule::or_form => Ok(BExpr::Or(build_exprs(pair.into_inner())?, span)),
+        Rule::when_form => {
+            let mut pair = pair.into_inner();
+            let test = build_bexpr_from_expr(pair.next().unwrap())?;
+            Ok(BExpr::When(Rc::new(test), build_exprs(pair)?, span))
+        }
+        Rule::unless_form => {
+            let mut pair = pair.into_inner();
+            let test = build_bexpr_from_expr(pair.next().unwrap())?;
+            Ok(BExpr::Unless(Rc::new(test), build_exprs(pair)?, span))
+        }
+        Rule::begin_form => Ok(BExpr::Begin(build_exprs(pair.into_inner())?, span)),
+        Rule::guard_form => {
+            let mut pair = pair.into_inner();
+            let mut clauses = pair.next().unwrap().into_inner();
+            let var = clauses.next().unwrap().as_str().to_owned();
+            let clauses = clauses
+                .map(build_cond_clause)
+                .collect::<Result<Vec<_>, CompileError>>()?;
+            let body = build_body_from_expr(pair.next().unwrap())?;
+
+            Ok(BExpr::Guard(var, clauses, body, span))
+        }
+        Rule::let_syntax_form | Rule::letrec_syntax_form => build_let_syntax_from_expr(pair),
         Rule::app => build_app_from_expr(pair),
-        Rule::variable => BExpr::Var(pair.as_str().to_owned()),
+        Rule::macro_use => {
+            let elems = pair.into_inner().map(build_syntax).collect();
+            let datum = Datum::List(elems, None, span);
+
+            Ok(BExpr::MacroUse(Rc::new(datum), None, span))
+        }
+        Rule::variable => Ok(BExpr::Var(pair.as_str().to_owned(), span)),
         e => unreachable!("{:?}", e),
     }
 }
 
-fn build_if_from_expr(pair: pest::iterators::Pair<Rule>) -> BExpr {
+fn build_if_from_expr(pair: pest::iterators::Pair<Rule>) -> Result<BExpr, CompileError> {
+    let span = pair.as_span().into();
     let mut pair = pair.into_inner();
     let cond = pair.next().unwrap();
-    let cond = build_bexpr_from_expr(cond);
+    let cond = build_bexpr_from_expr(cond)?;
     let ift = pair.next().unwrap();
-    let ift = build_bexpr_from_expr(ift);
-    let iff = pair
-        .next()
-        .map(build_bexpr_from_expr)
-        .unwrap_or(BExpr::Lit(Literal::Void));
-    BExpr::If(Rc::new(cond), Rc::new(ift), Rc::new(iff))
+    let ift = build_bexpr_from_expr(ift)?;
+    let iff = match pair.next() {
+        Some(iff) => build_bexpr_from_expr(iff)?,
+        None => BExpr::Lit(Literal::Void, span),
+    };
+    Ok(BExpr::If(Rc::new(cond), Rc::new(ift), Rc::new(iff), span))
+}
+
+fn build_exprs(pairs: pest::iterators::Pairs<Rule>) -> Result<Vec<BExpr>, CompileError> {
+    pairs.map(build_bexpr_from_expr).collect()
+}
+
+fn build_cond_from_expr(pair: pest::iterators::Pair<Rule>) -> Result<BExpr, CompileError> {
+    let span = pair.as_span().into();
+
+    let clauses = pair
+        .into_inner()
+        .map(build_cond_clause)
+        .collect::<Result<Vec<_>, CompileError>>()?;
+
+    Ok(BExpr::Cond(clauses, span))
 }
 
-fn build_set_from_expr(pair: pest::iterators::Pair<Rule>) -> BExpr {
+fn build_cond_clause(clause: pest::iterators::Pair<Rule>) -> Result<CondClause, CompileError> {
+    let rule = clause.as_rule();
+    let mut parts = clause.into_inner();
+
+    if rule == Rule::else_clause {
+        return Ok(CondClause::Else(build_exprs(parts)?));
+    }
+
+    let test = build_bexpr_from_expr(parts.next().unwrap())?;
+
+    match parts.peek().map(|p| p.as_rule()) {
+        Some(Rule::arrow) => {
+            let receiver = build_bexpr_from_expr(parts.nth(1).unwrap())?;
+            Ok(CondClause::Arrow(test, receiver))
+        }
+        _ => Ok(CondClause::Test(test, build_exprs(parts)?)),
+    }
+}
+
+fn build_case_from_expr(pair: pest::iterators::Pair<Rule>) -> Result<BExpr, CompileError> {
+    let span = pair.as_span().into();
+    let mut pair = pair.into_inner();
+    let key = build_bexpr_from_expr(pair.next().unwrap())?;
+
+    let mut clauses = Vec::new();
+    let mut else_body = None;
+
+    for clause in pair {
+        let rule = clause.as_rule();
+        let mut parts = clause.into_inner();
+
+        if rule == Rule::else_clause {
+            else_body = Some(build_exprs(parts)?);
+            continue;
+        }
+
+        let datums = parts
+            .next()
+            .unwrap()
+            .into_inner()
+            .map(build_datum)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        clauses.push((datums, build_exprs(parts)?));
+    }
+
+    Ok(BExpr::Case(Rc::new(key), clauses, else_body, span))
+}
+
+fn build_set_from_expr(pair: pest::iterators::Pair<Rule>) -> Result<BExpr, CompileError> {
+    let span = pair.as_span().into();
     let mut pair = pair.into_inner();
     let name = pair.next().unwrap().as_str().to_owned();
     let expr = pair.next().unwrap();
-    let expr = build_bexpr_from_expr(expr);
+    let expr = build_bexpr_from_expr(expr)?;
 
-    BExpr::Set(name, Rc::new(expr))
+    Ok(BExpr::Set(name, Rc::new(expr), span))
 }
 
-fn build_bodyexpr_from_expr(pair: pest::iterators::Pair<Rule>) -> BExprBodyExpr {
+fn build_bodyexpr_from_expr(
+    pair: pest::iterators::Pair<Rule>,
+) -> Result<BExprBodyExpr, CompileError> {
     match pair.as_rule() {
         Rule::define_form => build_bexprbodyexpr_from_define(pair),
-        Rule::expr => BExprBodyExpr::Expr(build_bexpr_from_expr(pair)),
+        Rule::define_syntax_form => {
+            let span = pair.as_span().into();
+            let mut pair = pair.into_inner();
+            let name = pair.next().unwrap().as_str().to_owned();
+            let rules = build_syntax_rules(pair.next().unwrap())?;
+
+            Ok(BExprBodyExpr::DefSyntax(
+                name,
+                Transformer::Rules(rules),
+                span,
+            ))
+        }
+        Rule::define_macro_form => {
+            let span = pair.as_span().into();
+            let mut pair = pair.into_inner();
+            let name = pair.next().unwrap().as_str().to_owned();
+            let (params, rest) = build_params(pair.next().unwrap());
+            let procedure = Procedure {
+                params,
+                rest,
+                body: build_body_from_expr(pair.next().unwrap())?,
+                span,
+            };
+
+            Ok(BExprBodyExpr::DefSyntax(
+                name,
+                Transformer::Procedure(Rc::new(procedure)),
+                span,
+            ))
+        }
+        Rule::expr => Ok(BExprBodyExpr::Expr(build_bexpr_from_expr(pair)?)),
         r => unreachable!("{:?}", r),
     }
 }
 
-fn build_body_from_expr(pair: pest::iterators::Pair<Rule>) -> BExprBody {
+/// Build the items of a body from one of its parts, a `begin` gives all of the
+/// items in it
+fn build_bodyexprs_from_expr(
+    pair: pest::iterators::Pair<Rule>,
+) -> Result<Vec<BExprBodyExpr>, CompileError> {
+    match pair.as_rule() {
+        Rule::body_begin => pair
+            .into_inner()
+            .map(build_bodyexprs_from_expr)
+            .collect::<Result<Vec<_>, _>>()
+            .map(|items| items.into_iter().flatten().collect()),
+        _ => Ok(vec![build_bodyexpr_from_expr(pair)?]),
+    }
+}
+
+fn build_body_from_expr(pair: pest::iterators::Pair<Rule>) -> Result<BExprBody, CompileError> {
+    let span = pair.as_span().into();
     let pair = pair.into_inner();
-    let mut things = pair.map(build_bodyexpr_from_expr).collect::<Vec<_>>();
-    let last = things.pop().unwrap();
-    if let BExprBodyExpr::Expr(e) = last {
-        BExprBody(things, Rc::new(e))
-    } else {
-        unreachable!() // grammar should prevent this
+    let mut things = pair
+        .map(build_bodyexprs_from_expr)
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+    match things.pop() {
+        Some(BExprBodyExpr::Expr(e)) => Ok(BExprBody(things, Rc::new(e))),
+        // the body can be empty if it was only `(begin)`s
+        item => Err(CompileError::Syntax {
+            message: "a body has to end with an expression".to_owned(),
+            span: item.as_ref().map_or(span, BExprBodyExpr::span),
+        }),
     }
 }
 
-fn build_let_from_expr(pair: pest::iterators::Pair<Rule>) -> BExpr {
+fn build_let_from_expr(pair: pest::iterators::Pair<Rule>) -> Result<BExpr, CompileError> {
+    let span = pair.as_span().into();
+    let rule = pair.as_rule();
     let mut pair = pair.into_inner();
+
+    let name = if rule == Rule::named_let_form {
+        Some(pair.next().unwrap().as_str().to_owned())
+    } else {
+        None
+    };
+
     let bindings = pair.next().unwrap().into_inner();
     let bindings = bindings
         .map(|pair| {
             let mut pair = pair.into_inner();
             let name = pair.next().unwrap().as_str().to_owned();
             let expr = pair.next().unwrap();
-            let expr = build_bexpr_from_expr(expr);
+            let expr = build_bexpr_from_expr(expr)?;
 
-            (name, expr)
+            Ok::<_, CompileError>((name, expr))
         })
-        .collect::<Vec<_>>();
+        .collect::<Result<Vec<_>, _>>()?;
 
     let body = pair.next().unwrap();
-    let body = build_body_from_expr(body);
+    let body = build_body_from_expr(body)?;
 
-    BExpr::Let(bindings, body)
+    let expr = match (rule, name) {
+        (Rule::let_star_form, _) => BExpr::LetStar(bindings, body, span),
+        (Rule::letrec_form, _) => BExpr::LetRec(bindings, body, span),
+        (_, Some(name)) => BExpr::NamedLet(name, bindings, body, span),
+        _ => BExpr::Let(bindings, body, span),
+    };
+
+    Ok(expr)
 }
 
-fn build_lambda_from_expr(pair: pest::iterators::Pair<Rule>) -> BExpr {
+fn build_do_from_expr(pair: pest::iterators::Pair<Rule>) -> Result<BExpr, CompileError> {
+    let span = pair.as_span().into();
     let mut pair = pair.into_inner();
+
     let bindings = pair
         .next()
         .unwrap()
         .into_inner()
-        .map(|pair| pair.as_str().to_owned())
-        .collect::<Vec<_>>();
+        .map(|pair| {
+            let mut pair = pair.into_inner();
+            let name = pair.next().unwrap().as_str().to_owned();
+            let init = build_bexpr_from_expr(pair.next().unwrap())?;
+            let step = pair.next().map(build_bexpr_from_expr).transpose()?;
+
+            Ok::<_, CompileError>((name, init, step))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut result = pair.next().unwrap().into_inner();
+    let test = build_bexpr_from_expr(result.next().unwrap())?;
+    let result = build_exprs(result)?;
+
+    let commands = build_exprs(pair)?;
+
+    Ok(BExpr::Do(bindings, Rc::new(test), result, commands, span))
+}
+
+fn build_let_syntax_from_expr(pair: pest::iterators::Pair<Rule>) -> Result<BExpr, CompileError> {
+    let span = pair.as_span().into();
+    let rule = pair.as_rule();
+    let mut pair = pair.into_inner();
+
+    let bindings = pair
+        .next()
+        .unwrap()
+        .into_inner()
+        .map(|pair| {
+            let mut pair = pair.into_inner();
+            let name = pair.next().unwrap().as_str().to_owned();
+            let rules = build_syntax_rules(pair.next().unwrap())?;
+
+            Ok::<_, CompileError>((name, rules))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let body = build_body_from_expr(pair.next().unwrap())?;
+
+    if rule == Rule::letrec_syntax_form {
+        Ok(BExpr::LetRecSyntax(bindings, body, span))
+    } else {
+        Ok(BExpr::LetSyntax(bindings, body, span))
+    }
+}
+
+fn build_syntax_rules(pair: pest::iterators::Pair<Rule>) -> Result<Rc<SyntaxRules>, CompileError> {
+    let mut pair = pair.into_inner().peekable();
+
+    let ellipsis = match pair.peek().map(|p| p.as_rule()) {
+        Some(Rule::symbol) => pair.next().unwrap().as_str().to_owned(),
+        _ => "...".to_owned(),
+    };
+
+    let literals = pair
+        .next()
+        .unwrap()
+        .into_inner()
+        .map(|p| p.as_str().to_owned())
+        .collect();
+
+    let rules = pair
+        .map(|rule| {
+            let mut rule = rule.into_inner();
+            let pattern = build_syntax(rule.next().unwrap());
+            let template = build_syntax(rule.next().unwrap());
+
+            match &pattern {
+                Datum::List(elems, _, _) if !elems.is_empty() => Ok((pattern, template)),
+                _ => Err(CompileError::Syntax {
+                    message: "a `syntax-rules` pattern has to be a list like `(_ args...)`"
+                        .to_owned(),
+                    span: pattern.span(),
+                }),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Rc::new(SyntaxRules {
+        ellipsis,
+        literals,
+        rules,
+    }))
+}
+
+/// Build a datum from a `syntax` rule, `'x` and the like become lists with
+/// the full name of the prefix at their head.
+fn build_syntax(pair: pest::iterators::Pair<Rule>) -> Datum {
+    let span = pair.as_span().into();
+
+    match pair.as_rule() {
+        Rule::syntax_list => {
+            let (elems, tail) = split_dotted_tail(pair);
+
+            Datum::List(
+                elems.into_iter().map(build_syntax).collect(),
+                tail.map(|t| Rc::new(build_syntax(t))),
+                span,
+            )
+        }
+        Rule::syntax_prefixed => {
+            let mut pair = pair.into_inner();
+            let prefix = pair.next().unwrap();
+            let name = match prefix.as_str() {
+                "'" => "quote",
+                "`" => "quasiquote",
+                ",@" => "unquote-splicing",
+                _ => "unquote",
+            };
+            let prefix = Datum::Symbol(name.to_owned(), prefix.as_span().into());
+
+            Datum::List(vec![prefix, build_syntax(pair.next().unwrap())], None, span)
+        }
+        Rule::symbol | Rule::variable => Datum::Symbol(pair.as_str().to_owned(), span),
+        _ => Datum::Atom(pair.as_str().to_owned(), span),
+    }
+}
+
+fn build_lambda_from_expr(pair: pest::iterators::Pair<Rule>) -> Result<BExpr, CompileError> {
+    let span = pair.as_span().into();
+    let mut pair = pair.into_inner();
+    let bindings = pair.next().unwrap();
+    let (params, rest) = match bindings.as_rule() {
+        Rule::variable => (Vec::new(), Some(bindings.as_str().to_owned())),
+        _ => build_params(bindings),
+    };
     let body = pair.next().unwrap();
-    let body = build_body_from_expr(body);
+    let body = build_body_from_expr(body)?;
 
-    BExpr::Lam(bindings, body)
+    Ok(BExpr::Lam(params, rest, body, span))
 }
 
-fn build_app_from_expr(pair: pest::iterators::Pair<Rule>) -> BExpr {
+/// The parameters of `lambda_bindings`, and the parameter after the `.` if
+/// there is one
+fn build_params(pair: pest::iterators::Pair<Rule>) -> (Vec<String>, Option<String>) {
+    let (params, rest) = split_dotted_tail(pair);
+
+    (
+        params.iter().map(|p| p.as_str().to_owned()).collect(),
+        rest.map(|r| r.as_str().to_owned()),
+    )
+}
+
+fn build_app_from_expr(pair: pest::iterators::Pair<Rule>) -> Result<BExpr, CompileError> {
+    let span = pair.as_span().into();
     let mut pair = pair.into_inner();
     let function = pair.next().unwrap();
-    let function = build_bexpr_from_expr(function);
+    let function = build_bexpr_from_expr(function)?;
 
-    let params = pair.map(build_bexpr_from_expr).collect::<Vec<_>>();
+    let params = pair
+        .map(build_bexpr_from_expr)
+        .collect::<Result<Vec<_>, _>>()?;
 
-    BExpr::App(Rc::new(function), params)
+    Ok(BExpr::App(Rc::new(function), params, span))
 }
 
-fn build_literal_from_expr(pair: pest::iterators::Pair<Rule>) -> BExpr {
-    match pair.as_rule() {
-        Rule::list_literal => {
-            let pair = pair.into_inner();
-            let cons = Rc::new(BExpr::BuiltinIdent("cons".to_owned()));
-            let expr = pair
-                .map(build_bexpr_from_expr)
-                .collect::<Vec<_>>()
+fn build_literal_from_expr(pair: pest::iterators::Pair<Rule>) -> Result<BExpr, CompileError> {
+    let span = pair.as_span().into();
+
+    let expr = match pair.as_rule() {
+        Rule::quoted => build_datum(pair.into_inner().next().unwrap())?,
+        Rule::quasiquoted => build_quasiquote(pair.into_inner().next().unwrap())?,
+        Rule::number => BExpr::Lit(Literal::Int(parse_integer(pair.as_str(), span)?), span),
+        // the grammar only accepts valid float syntax, out of range values become infinities
+        Rule::float => BExpr::Lit(Literal::Float(pair.as_str().parse().unwrap()), span),
+        Rule::invalid_number => {
+            return Err(CompileError::InvalidNumber {
+                literal: pair.as_str().to_owned(),
+                span,
+            })
+        }
+        Rule::quoted_string => {
+            let inner = pair.into_inner().next().unwrap();
+            BExpr::Lit(Literal::String(unescape_string(inner)?), span)
+        }
+        Rule::character => BExpr::Lit(Literal::Char(parse_character(pair.as_str(), span)?), span),
+        Rule::boolean => BExpr::Lit(Literal::Bool(matches!(pair.as_str(), "#t" | "#true")), span),
+        Rule::null => BExpr::Lit(Literal::Void, span),
+        _ => unreachable!(),
+    };
+
+    Ok(expr)
+}
+
+/// Build the value of a quoted datum, lists become calls to `cons` and
+/// identifiers become symbols.
+fn build_datum(pair: pest::iterators::Pair<Rule>) -> Result<BExpr, CompileError> {
+    let span = pair.as_span().into();
+
+    let expr = match pair.as_rule() {
+        Rule::sexpr_list => {
+            let cons = Rc::new(BExpr::BuiltinIdent("cons".to_owned(), span));
+            let (elems, tail) = split_dotted_tail(pair);
+            let tail = match tail {
+                Some(tail) => build_datum(tail)?,
+                None => BExpr::Lit(Literal::Void, span),
+            };
+
+            elems
+                .into_iter()
+                .map(build_datum)
+                .collect::<Result<Vec<_>, _>>()?
                 .into_iter()
                 .rev()
-                .fold(BExpr::Lit(Literal::Void), |a, e| {
-                    BExpr::App(cons.clone(), vec![e, a])
-                });
-            expr
-        }
-        Rule::number => BExpr::Lit(Literal::Int(pair.as_str().parse().unwrap())),
-        Rule::quoted_string => BExpr::Lit(Literal::String(
-            pair.into_inner().next().unwrap().as_str().to_owned(),
-        )),
-        Rule::null => BExpr::Lit(Literal::Void),
-        _ => unreachable!(),
+                .fold(tail, |a, e| BExpr::App(cons.clone(), vec![e, a], span))
+        }
+        // 'x inside a datum is the list (quote x)
+        Rule::quoted => {
+            let cons = Rc::new(BExpr::BuiltinIdent("cons".to_owned(), span));
+            let quoted = build_datum(pair.into_inner().next().unwrap())?;
+            let tail = BExpr::App(
+                cons.clone(),
+                vec![quoted, BExpr::Lit(Literal::Void, span)],
+                span,
+            );
+            BExpr::App(
+                cons,
+                vec![BExpr::Lit(Literal::Symbol("quote".to_owned()), span), tail],
+                span,
+            )
+        }
+        Rule::symbol => BExpr::Lit(Literal::Symbol(pair.as_str().to_owned()), span),
+        _ => return build_literal_from_expr(pair),
+    };
+
+    Ok(expr)
+}
+
+/// Split the elements of a list datum from the datum after the `.`, if it
+/// is a dotted list.
+fn split_dotted_tail(
+    pair: pest::iterators::Pair<Rule>,
+) -> (
+    Vec<pest::iterators::Pair<Rule>>,
+    Option<pest::iterators::Pair<Rule>>,
+) {
+    let mut elems = pair.into_inner().collect::<Vec<_>>();
+
+    match elems.len() {
+        n if n >= 2 && elems[n - 2].as_rule() == Rule::dot => {
+            let tail = elems.pop();
+            elems.pop();
+            (elems, tail)
+        }
+        _ => (elems, None),
+    }
+}
+
+/// Build a quasiquote template, this is the same as `build_datum` except that
+/// `,x` evaluates `x` and `,@x` splices the list `x` into the enclosing list
+/// with `append`.
+fn build_quasiquote(pair: pest::iterators::Pair<Rule>) -> Result<BExpr, CompileError> {
+    let span = pair.as_span().into();
+
+    let expr = match pair.as_rule() {
+        Rule::qq_list => {
+            let cons = Rc::new(BExpr::BuiltinIdent("cons".to_owned(), span));
+            let append = Rc::new(BExpr::BuiltinIdent("append".to_owned(), span));
+            let (elems, tail) = split_dotted_tail(pair);
+            let tail = match tail {
+                Some(tail) => build_quasiquote(tail)?,
+                None => BExpr::Lit(Literal::Void, span),
+            };
+
+            elems
+                .into_iter()
+                .map(|elem| match elem.as_rule() {
+                    Rule::unquote_splicing => Ok((
+                        append.clone(),
+                        build_bexpr_from_expr(elem.into_inner().next().unwrap())?,
+                    )),
+                    _ => Ok((cons.clone(), build_quasiquote(elem)?)),
+                })
+                .collect::<Result<Vec<_>, CompileError>>()?
+                .into_iter()
+                .rev()
+                .fold(tail, |a, (f, e)| BExpr::App(f, vec![e, a], span))
+        }
+        Rule::qq_quoted => {
+            let cons = Rc::new(BExpr::BuiltinIdent("cons".to_owned(), span));
+            let quoted = build_quasiquote(pair.into_inner().next().unwrap())?;
+            let tail = BExpr::App(
+                cons.clone(),
+                vec![quoted, BExpr::Lit(Literal::Void, span)],
+                span,
+            );
+            BExpr::App(
+                cons,
+                vec![BExpr::Lit(Literal::Symbol("quote".to_owned()), span), tail],
+                span,
+            )
+        }
+        Rule::unquote => build_bexpr_from_expr(pair.into_inner().next().unwrap())?,
+        _ => return build_datum(pair),
+    };
+
+    Ok(expr)
+}
+
+/// Parse an integer literal with an optional `#x`, `#b` or `#o` radix prefix
+/// and sign, the grammar has already checked the digits are valid.
+fn parse_integer(literal: &str, span: Span) -> Result<i64, CompileError> {
+    let (radix, digits) = match literal.get(..2).map(str::to_ascii_lowercase).as_deref() {
+        Some("#x") => (16, &literal[2..]),
+        Some("#b") => (2, &literal[2..]),
+        Some("#o") => (8, &literal[2..]),
+        _ => (10, literal),
+    };
+
+    i64::from_str_radix(digits, radix).map_err(|_| CompileError::IntegerOutOfRange {
+        literal: literal.to_owned(),
+        span,
+    })
+}
+
+/// Decode the escapes in the body of a string literal, the grammar has
+/// already checked that each escape is one we know.
+fn unescape_string(pair: pest::iterators::Pair<Rule>) -> Result<String, CompileError> {
+    let start = pair.as_span().start();
+    let raw = pair.as_str();
+    let mut s = String::with_capacity(raw.len());
+    let mut chars = raw.char_indices();
+
+    while let Some((_, c)) = chars.next() {
+        if c != '\\' {
+            s.push(c);
+            continue;
+        }
+
+        let (i, escape) = chars.next().unwrap();
+
+        let decoded = match escape {
+            'b' => '\u{8}',
+            'f' => '\u{c}',
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            'u' => {
+                let hex = &raw[i + 1..i + 5];
+                chars.nth(3);
+
+                u32::from_str_radix(hex, 16)
+                    .ok()
+                    .and_then(std::char::from_u32)
+                    .ok_or_else(|| CompileError::InvalidStringEscape {
+                        escape: format!("\\u{}", hex),
+                        span: Span::new(start + i - 1, start + i + 5),
+                    })?
+            }
+            // '"', '\\' and '/' stand for themselves
+            c => c,
+        };
+
+        s.push(decoded);
     }
+
+    Ok(s)
+}
+
+/// Whether `name`, the part of a character literal after `#\`, is a hex
+/// code like `x41`
+pub fn is_hex_character(name: &str) -> bool {
+    name.len() > 1
+        && name.starts_with(&['x', 'X'][..])
+        && name[1..].chars().all(|c| c.is_ascii_hexdigit())
 }
 
-fn build_bexprbodyexpr_from_define(pair: pest::iterators::Pair<Rule>) -> BExprBodyExpr {
+/// Parse a `#\` character literal, which is a single character, a character
+/// name or a hex escape. The grammar accepts any `#\` token, so unknown names
+/// and hex codes that aren't unicode scalar values are rejected here.
+fn parse_character(literal: &str, span: Span) -> Result<char, CompileError> {
+    let name = &literal[2..];
+    let invalid = || CompileError::InvalidCharacter {
+        literal: literal.to_owned(),
+        span,
+    };
+
+    let c = match name {
+        "alarm" => '\u{7}',
+        "backspace" => '\u{8}',
+        "delete" => '\u{7f}',
+        "escape" => '\u{1b}',
+        "newline" => '\n',
+        "null" => '\0',
+        "return" => '\r',
+        "space" => ' ',
+        "tab" => '\t',
+        _ if is_hex_character(name) => u32::from_str_radix(&name[1..], 16)
+            .ok()
+            .and_then(std::char::from_u32)
+            .ok_or_else(invalid)?,
+        _ if name.chars().count() == 1 => name.chars().next().unwrap(),
+        _ => return Err(invalid()),
+    };
+
+    Ok(c)
+}
+
+fn build_bexprbodyexpr_from_define(
+    pair: pest::iterators::Pair<Rule>,
+) -> Result<BExprBodyExpr, CompileError> {
+    let span = pair.as_span().into();
     let mut pair = pair.into_inner();
-    let name = pair.next().unwrap().as_str().to_owned();
+    let target = pair.next().unwrap();
+
+    if target.as_rule() == Rule::define_header {
+        let body = build_body_from_expr(pair.next().unwrap())?;
+        let (name, expr) = build_function_define(target, body, span);
+
+        return Ok(BExprBodyExpr::Def(name, expr, span));
+    }
+
+    let name = target.as_str().to_owned();
     let expr = pair.next().unwrap();
-    let expr = build_bexpr_from_expr(expr);
+    let expr = build_bexpr_from_expr(expr)?;
 
-    BExprBodyExpr::Def(name, expr)
+    Ok(BExprBodyExpr::Def(name, expr, span))
+}
+
+/// Turn `(define (f a b) body...)` into the name `f` and `(lambda (a b) body...)`.
+///
+/// Curried headers like `((f a) b)` wrap the body in a lambda for each level,
+/// from the innermost parameter list outwards.
+fn build_function_define(
+    header: pest::iterators::Pair<Rule>,
+    body: BExprBody,
+    span: Span,
+) -> (String, BExpr) {
+    let mut header = header.into_inner();
+    let target = header.next().unwrap();
+    let (params, rest) = build_params(header.next().unwrap());
+
+    let lambda = BExpr::Lam(params, rest, body, span);
+
+    match target.as_rule() {
+        Rule::define_header => {
+            build_function_define(target, BExprBody(Vec::new(), Rc::new(lambda)), span)
+        }
+        _ => (target.as_str().to_owned(), lambda),
+    }
 }
@@ -0,0 +1,238 @@
+use std::collections::HashSet;
+
+use crate::base_expr::{BExpr, BExprBody, BExprBodyExpr, CondClause};
+use crate::error::CompileError;
+
+/// Walk the whole program and report every reference to, or `set!` of, a name
+/// that isn't bound where it is used.
+///
+/// `BExpr::into_expr` stops at the first unbound name, running this first lets
+/// us report all of them at once.
+pub fn check_scopes(expr: &BExpr) -> Vec<CompileError> {
+    let mut checker = ScopeChecker {
+        scopes: Vec::new(),
+        errors: Vec::new(),
+    };
+
+    checker.check_expr(expr);
+
+    // report them in the order they appear in the source, whatever order the
+    // forms containing them are walked in
+    checker
+        .errors
+        .sort_by_key(|e| e.span().map(|s| (s.start, s.end)));
+
+    checker.errors
+}
+
+struct ScopeChecker {
+    scopes: Vec<HashSet<String>>,
+    errors: Vec<CompileError>,
+}
+
+impl ScopeChecker {
+    fn is_bound(&self, name: &str) -> bool {
+        self.scopes.iter().any(|s| s.contains(name))
+    }
+
+    fn suggest(&self, name: &str) -> Option<String> {
+        let len = name.chars().count();
+        // the distance has to be less than the length of the name, otherwise
+        // any one character name would suggest any other
+        let max_distance = std::cmp::min(std::cmp::max(1, len / 3), len.saturating_sub(1));
+
+        // names with a space in them were made up by the compiler
+        self.scopes
+            .iter()
+            .flatten()
+            .filter(|candidate| !candidate.contains(' '))
+            .map(|candidate| (edit_distance(name, candidate), candidate))
+            .filter(|(d, _)| *d <= max_distance)
+            .min()
+            .map(|(_, candidate)| candidate.clone())
+    }
+
+    fn with_scope(&mut self, names: impl IntoIterator<Item = String>, f: impl FnOnce(&mut Self)) {
+        self.scopes.push(names.into_iter().collect());
+        f(self);
+        self.scopes.pop();
+    }
+
+    fn check_expr(&mut self, expr: &BExpr) {
+        match expr {
+            BExpr::Var(n, s) => {
+                if !self.is_bound(n) {
+                    self.errors.push(CompileError::UnboundVariable {
+                        name: n.clone(),
+                        span: *s,
+                        suggestion: self.suggest(n),
+                    });
+                }
+            }
+            BExpr::Set(n, e, s) => {
+                if !self.is_bound(n) {
+                    self.errors.push(CompileError::UndefinedSet {
+                        name: n.clone(),
+                        span: *s,
+                        suggestion: self.suggest(n),
+                    });
+                }
+                self.check_expr(e);
+            }
+            BExpr::Lit(..) | BExpr::BuiltinIdent(..) => {}
+            BExpr::If(c, ift, iff, _) => {
+                self.check_expr(c);
+                self.check_expr(ift);
+                self.check_expr(iff);
+            }
+            BExpr::Let(bindings, body, _) => {
+                for (_, e) in bindings {
+                    self.check_expr(e);
+                }
+
+                self.with_scope(bindings.iter().map(|(n, _)| n.clone()), |c| {
+                    c.check_body(body)
+                });
+            }
+            BExpr::LetStar(bindings, body, _) => {
+                // each binding can see the ones before it
+                let depth = self.scopes.len();
+
+                for (n, e) in bindings {
+                    self.check_expr(e);
+                    self.scopes.push(std::iter::once(n.clone()).collect());
+                }
+
+                self.check_body(body);
+                self.scopes.truncate(depth);
+            }
+            BExpr::LetRec(bindings, body, _) => {
+                self.with_scope(bindings.iter().map(|(n, _)| n.clone()), |c| {
+                    for (_, e) in bindings {
+                        c.check_expr(e);
+                    }
+
+                    c.check_body(body)
+                });
+            }
+            BExpr::NamedLet(name, bindings, body, _) | BExpr::Loop(name, bindings, body, _) => {
+                for (_, e) in bindings {
+                    self.check_expr(e);
+                }
+
+                let names =
+                    std::iter::once(name.clone()).chain(bindings.iter().map(|(n, _)| n.clone()));
+                self.with_scope(names, |c| c.check_body(body));
+            }
+            BExpr::Lam(params, rest, body, _) => {
+                self.with_scope(params.iter().chain(rest).cloned(), |c| c.check_body(body));
+            }
+            BExpr::App(f, params, _) => {
+                self.check_expr(f);
+                self.check_exprs(params);
+            }
+            BExpr::Cond(clauses, _) => self.check_clauses(clauses),
+            BExpr::Case(key, clauses, else_body, _) => {
+                self.check_expr(key);
+                for (_, body) in clauses {
+                    self.check_exprs(body);
+                }
+                if let Some(body) = else_body {
+                    self.check_exprs(body);
+                }
+            }
+            BExpr::When(c, es, _) | BExpr::Unless(c, es, _) => {
+                self.check_expr(c);
+                self.check_exprs(es);
+            }
+            BExpr::And(es, _) | BExpr::Or(es, _) | BExpr::Begin(es, _) => self.check_exprs(es),
+            BExpr::Guard(var, clauses, body, _) => {
+                self.with_scope(std::iter::once(var.clone()), |c| c.check_clauses(clauses));
+                self.check_body(body);
+            }
+            BExpr::Do(bindings, test, result, commands, _) => {
+                for (_, init, _) in bindings {
+                    self.check_expr(init);
+                }
+
+                self.with_scope(bindings.iter().map(|(n, _, _)| n.clone()), |c| {
+                    for (_, _, step) in bindings {
+                        if let Some(step) = step {
+                            c.check_expr(step);
+                        }
+                    }
+
+                    c.check_expr(test);
+                    c.check_exprs(result);
+                    c.check_exprs(commands);
+                });
+            }
+            BExpr::Recur(_, _, args, _) => self.check_exprs(args),
+            BExpr::MacroUse(..) | BExpr::LetSyntax(..) | BExpr::LetRecSyntax(..) => {
+                self.errors.push(CompileError::Internal(
+                    "macros should have been expanded before checking scopes".to_owned(),
+                ))
+            }
+        }
+    }
+
+    fn check_clauses(&mut self, clauses: &[CondClause]) {
+        for clause in clauses {
+            match clause {
+                CondClause::Test(test, body) => {
+                    self.check_expr(test);
+                    self.check_exprs(body);
+                }
+                CondClause::Arrow(test, receiver) => {
+                    self.check_expr(test);
+                    self.check_expr(receiver);
+                }
+                CondClause::Else(body) => self.check_exprs(body),
+            }
+        }
+    }
+
+    fn check_exprs(&mut self, exprs: &[BExpr]) {
+        for e in exprs {
+            self.check_expr(e);
+        }
+    }
+
+    fn check_body(&mut self, body: &BExprBody) {
+        // defines are visible across the whole body, not just after them
+        let defined = body.0.iter().filter_map(|e| match e {
+            BExprBodyExpr::Def(n, _, _) => Some(n.clone()),
+            BExprBodyExpr::DefSyntax(..) | BExprBodyExpr::Expr(_) => None,
+        });
+
+        self.with_scope(defined, |c| {
+            for e in &body.0 {
+                match e {
+                    BExprBodyExpr::Def(_, e, _) | BExprBodyExpr::Expr(e) => c.check_expr(e),
+                    BExprBodyExpr::DefSyntax(..) => {}
+                }
+            }
+
+            c.check_expr(&body.1);
+        });
+    }
+}
+
+/// Levenshtein distance between two names
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut prev = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1; b.len() + 1];
+
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = prev[j] + if ca == *cb { 0 } else { 1 };
+            cur[j + 1] = substitution.min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+
+        prev = cur;
+    }
+
+    prev[b.len()]
+}
@@ -0,0 +1,169 @@
+use std::fmt;
+use std::rc::Rc;
+
+use crate::diagnostics::Span;
+
+/// Program text read as plain data, without deciding what any of it means.
+///
+/// Macros are matched against, and expand into, datums.
+#[derive(Debug, Clone)]
+pub enum Datum {
+    Symbol(String, Span),
+    /// Any other atom, kept as it was written
+    Atom(String, Span),
+    /// A list, with the datum after the `.` if it is a dotted list
+    List(Vec<Datum>, Option<Rc<Datum>>, Span),
+}
+
+// An identifier introduced by a macro expansion is renamed by appending a
+// mark to it, a control character no program would contain followed by the
+// number of the expansion. Marks stack up if an expansion is itself expanded.
+const MARK: char = '\u{1}';
+
+/// `name` as introduced by the expansion `mark`
+pub fn add_mark(name: &str, mark: usize) -> String {
+    format!("{}{}{}", name, MARK, mark)
+}
+
+/// Split the most recent mark from `name`, if it has one
+pub fn split_mark(name: &str) -> Option<(&str, usize)> {
+    let i = name.rfind(MARK)?;
+    let mark = name[i + MARK.len_utf8()..].parse().ok()?;
+
+    Some((&name[..i], mark))
+}
+
+/// `name` as it was written, without any marks
+pub fn strip_marks(name: &str) -> &str {
+    name.split(MARK).next().unwrap()
+}
+
+/// The name a variable had in the source, before the expander gave it a fresh
+/// one to keep it from shadowing another.
+pub fn source_name(name: &str) -> &str {
+    match name.rsplit_once(' ') {
+        Some((source, n)) if !source.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => source,
+        _ => name,
+    }
+}
+
+impl Datum {
+    pub fn span(&self) -> Span {
+        match self {
+            Datum::Symbol(_, s) | Datum::Atom(_, s) | Datum::List(_, _, s) => *s,
+        }
+    }
+
+    pub fn as_symbol(&self) -> Option<&str> {
+        match self {
+            Datum::Symbol(s, _) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn map_spans(self, f: &impl Fn(Span) -> Span) -> Datum {
+        match self {
+            Datum::Symbol(n, s) => Datum::Symbol(n, f(s)),
+            Datum::Atom(a, s) => Datum::Atom(a, f(s)),
+            Datum::List(elems, tail, s) => Datum::List(
+                elems.into_iter().map(|e| e.map_spans(f)).collect(),
+                tail.map(|t| Rc::new((*t).clone().map_spans(f))),
+                f(s),
+            ),
+        }
+    }
+
+    /// Write this datum back out as program text, along with the span each
+    /// part of the text came from.
+    pub fn render(&self) -> (String, SpanMap) {
+        let mut text = String::new();
+        let mut spans = Vec::new();
+
+        self.write(&mut text, &mut spans);
+
+        (text, SpanMap(spans))
+    }
+
+    fn write(&self, out: &mut String, spans: &mut Vec<(usize, usize, Span)>) {
+        let start = out.len();
+
+        match self {
+            Datum::Symbol(s, _) | Datum::Atom(s, _) => out.push_str(s),
+            Datum::List(elems, None, _) if elems.len() == 2 && prefix(&elems[0]).is_some() => {
+                out.push_str(prefix(&elems[0]).unwrap());
+                elems[1].write(out, spans);
+            }
+            Datum::List(elems, tail, _) => {
+                out.push('(');
+
+                for (i, e) in elems.iter().enumerate() {
+                    if i > 0 {
+                        out.push(' ');
+                    }
+                    e.write(out, spans);
+                }
+
+                if let Some(tail) = tail {
+                    out.push_str(" . ");
+                    tail.write(out, spans);
+                }
+
+                out.push(')');
+            }
+        }
+
+        spans.push((start, out.len(), self.span()));
+    }
+}
+
+/// The shorthand for `(quote x)` and friends
+fn prefix(head: &Datum) -> Option<&'static str> {
+    match head.as_symbol()? {
+        "quote" => Some("'"),
+        "quasiquote" => Some("`"),
+        "unquote" => Some(","),
+        "unquote-splicing" => Some(",@"),
+        _ => None,
+    }
+}
+
+impl fmt::Display for Datum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (text, _) = self.render();
+
+        f.write_str(strip_all_marks(&text).as_str())
+    }
+}
+
+fn strip_all_marks(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c == MARK {
+            while chars.peek().is_some_and(char::is_ascii_digit) {
+                chars.next();
+            }
+        } else {
+            out.push(c);
+        }
+    }
+
+    out
+}
+
+/// Where each part of a rendered datum came from in the program
+pub struct SpanMap(Vec<(usize, usize, Span)>);
+
+impl SpanMap {
+    /// The span of the innermost rendered datum that covers `span`, or of the
+    /// whole datum if none do
+    pub fn map(&self, span: Span) -> Span {
+        self.0
+            .iter()
+            .filter(|(start, end, _)| *start <= span.start && span.end <= *end)
+            .min_by_key(|(start, end, _)| end - start)
+            .or_else(|| self.0.last())
+            .map_or(span, |(_, _, original)| *original)
+    }
+}
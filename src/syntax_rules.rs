@@ -0,0 +1,371 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::diagnostics::Span;
+use crate::error::CompileError;
+use crate::parse::is_reserved;
+use crate::syntax::{add_mark, strip_marks, Datum};
+
+/// A `syntax-rules` transformer
+#[derive(Debug)]
+pub struct SyntaxRules {
+    pub ellipsis: String,
+    pub literals: Vec<String>,
+    /// Each pattern, including the position of the macro's name, and its
+    /// template. The parser has checked that every pattern is a list.
+    pub rules: Vec<(Datum, Datum)>,
+}
+
+/// What a pattern variable matched, a variable followed by an ellipsis
+/// matches any number of items
+#[derive(Debug, Clone)]
+enum Match {
+    One(Datum),
+    Many(Vec<Match>),
+}
+
+type Bindings = HashMap<String, Match>;
+
+impl SyntaxRules {
+    /// Expand `usage`, a use of the macro `name`, with the first rule whose
+    /// pattern matches it.
+    ///
+    /// Identifiers the template introduces are given the mark `mark`, so that
+    /// they can be told apart from identifiers that came from the use.
+    pub fn expand(&self, name: &str, usage: &Datum, mark: usize) -> Result<Datum, CompileError> {
+        let span = usage.span();
+        let (args, tail) = match usage {
+            Datum::List(elems, tail, _) => (&elems[1..], tail.as_deref()),
+            _ => unreachable!("a macro use is always a list"),
+        };
+
+        for (pattern, template) in &self.rules {
+            let (pats, pat_tail) = match pattern {
+                Datum::List(elems, tail, _) => (&elems[1..], tail.as_deref()),
+                _ => unreachable!("the parser checks patterns are lists"),
+            };
+
+            let mut bindings = HashMap::new();
+
+            if self.match_list(pats, pat_tail, args, tail, span, &mut bindings) {
+                let transcriber = Transcriber {
+                    rules: self,
+                    mark,
+                    span,
+                    escaped: false,
+                };
+
+                return transcriber.transcribe(template, &bindings);
+            }
+        }
+
+        Err(CompileError::NoMatchingRule {
+            name: strip_marks(name).to_owned(),
+            span,
+        })
+    }
+
+    pub fn map_spans(&self, f: &impl Fn(Span) -> Span) -> SyntaxRules {
+        SyntaxRules {
+            ellipsis: self.ellipsis.clone(),
+            literals: self.literals.clone(),
+            rules: self
+                .rules
+                .iter()
+                .map(|(p, t)| (p.clone().map_spans(f), t.clone().map_spans(f)))
+                .collect(),
+        }
+    }
+
+    fn is_ellipsis(&self, d: &Datum) -> bool {
+        d.as_symbol()
+            .is_some_and(|s| strip_marks(s) == strip_marks(&self.ellipsis))
+    }
+
+    fn is_literal(&self, s: &str) -> bool {
+        self.literals
+            .iter()
+            .any(|l| strip_marks(l) == strip_marks(s))
+    }
+
+    fn match_pattern(&self, pattern: &Datum, d: &Datum, bindings: &mut Bindings) -> bool {
+        match pattern {
+            Datum::Symbol(s, _) if self.is_literal(s) => d
+                .as_symbol()
+                .is_some_and(|d| strip_marks(d) == strip_marks(s)),
+            Datum::Symbol(s, _) if strip_marks(s) == "_" => true,
+            Datum::Symbol(s, _) => {
+                bindings.insert(s.clone(), Match::One(d.clone()));
+                true
+            }
+            Datum::Atom(a, _) => matches!(d, Datum::Atom(b, _) if a == b),
+            Datum::List(pats, pat_tail, _) => match d {
+                Datum::List(elems, tail, s) => self.match_list(
+                    pats,
+                    pat_tail.as_deref(),
+                    elems,
+                    tail.as_deref(),
+                    *s,
+                    bindings,
+                ),
+                _ => false,
+            },
+        }
+    }
+
+    /// Match the elements of a list pattern, which can contain one pattern
+    /// followed by an ellipsis, against the elements of a list.
+    fn match_list(
+        &self,
+        pats: &[Datum],
+        pat_tail: Option<&Datum>,
+        elems: &[Datum],
+        tail: Option<&Datum>,
+        span: Span,
+        bindings: &mut Bindings,
+    ) -> bool {
+        let ellipsis = pats.iter().skip(1).position(|p| self.is_ellipsis(p));
+
+        let (before, repeated, after) = match ellipsis {
+            Some(i) => (&pats[..i], Some(&pats[i]), &pats[i + 2..]),
+            None => (pats, None, &[][..]),
+        };
+
+        if elems.len() < before.len() + after.len() {
+            return false;
+        }
+
+        let (elems_before, rest) = elems.split_at(before.len());
+
+        if !self.match_each(before, elems_before, bindings) {
+            return false;
+        }
+
+        let rest = match repeated {
+            Some(repeated) => {
+                let (repeats, elems_after) = rest.split_at(rest.len() - after.len());
+                let mut matches = Vec::new();
+
+                for e in repeats {
+                    let mut m = HashMap::new();
+                    if !self.match_pattern(repeated, e, &mut m) {
+                        return false;
+                    }
+                    matches.push(m);
+                }
+
+                for var in self.pattern_vars(repeated) {
+                    let each = matches.iter_mut().map(|m| m.remove(&var).unwrap());
+                    let each = Match::Many(each.collect());
+                    bindings.insert(var, each);
+                }
+
+                if !self.match_each(after, elems_after, bindings) {
+                    return false;
+                }
+
+                &[][..]
+            }
+            None => rest,
+        };
+
+        match pat_tail {
+            // `(p ... . tail)` matches whatever is left over against `tail`
+            Some(pat_tail) => {
+                let rest = match (rest, tail) {
+                    ([], Some(tail)) => tail.clone(),
+                    _ => Datum::List(rest.to_vec(), tail.cloned().map(Rc::new), span),
+                };
+
+                self.match_pattern(pat_tail, &rest, bindings)
+            }
+            None => rest.is_empty() && tail.is_none(),
+        }
+    }
+
+    fn match_each(&self, pats: &[Datum], elems: &[Datum], bindings: &mut Bindings) -> bool {
+        pats.len() == elems.len()
+            && pats
+                .iter()
+                .zip(elems)
+                .all(|(p, e)| self.match_pattern(p, e, bindings))
+    }
+
+    fn pattern_vars(&self, pattern: &Datum) -> Vec<String> {
+        match pattern {
+            Datum::Symbol(s, _)
+                if self.is_literal(s) || strip_marks(s) == "_" || self.is_ellipsis(pattern) =>
+            {
+                Vec::new()
+            }
+            Datum::Symbol(s, _) => vec![s.clone()],
+            Datum::Atom(..) => Vec::new(),
+            Datum::List(elems, tail, _) => elems
+                .iter()
+                .chain(tail.as_deref())
+                .flat_map(|e| self.pattern_vars(e))
+                .collect(),
+        }
+    }
+}
+
+impl fmt::Display for SyntaxRules {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(syntax-rules ")?;
+
+        if self.ellipsis != "..." {
+            write!(f, "{} ", self.ellipsis)?;
+        }
+
+        write!(f, "({})", self.literals.join(" "))?;
+
+        for (pattern, template) in &self.rules {
+            write!(f, " ({} {})", pattern, template)?;
+        }
+
+        write!(f, ")")
+    }
+}
+
+struct Transcriber<'a> {
+    rules: &'a SyntaxRules,
+    mark: usize,
+    /// The span of the use being expanded
+    span: Span,
+    /// Inside `(... template)` the ellipsis is just an identifier
+    escaped: bool,
+}
+
+impl<'a> Transcriber<'a> {
+    fn is_ellipsis(&self, d: &Datum) -> bool {
+        !self.escaped && self.rules.is_ellipsis(d)
+    }
+
+    fn transcribe(&self, template: &Datum, bindings: &Bindings) -> Result<Datum, CompileError> {
+        let datum = match template {
+            Datum::Symbol(s, span) => match bindings.get(s) {
+                Some(Match::One(d)) => d.clone(),
+                Some(Match::Many(_)) => {
+                    return Err(CompileError::Syntax {
+                        message: format!(
+                            "`{}` matched a sequence, it has to be followed by `{}` here",
+                            strip_marks(s),
+                            strip_marks(&self.rules.ellipsis)
+                        ),
+                        span: *span,
+                    })
+                }
+                None if is_reserved(s) => Datum::Symbol(s.clone(), self.span),
+                None => Datum::Symbol(add_mark(s, self.mark), self.span),
+            },
+            Datum::Atom(a, _) => Datum::Atom(a.clone(), self.span),
+            Datum::List(elems, None, _) if elems.len() == 2 && self.is_ellipsis(&elems[0]) => {
+                let escaped = Transcriber {
+                    escaped: true,
+                    ..*self
+                };
+
+                return escaped.transcribe(&elems[1], bindings);
+            }
+            Datum::List(elems, tail, _) => {
+                let mut out = Vec::new();
+                let mut i = 0;
+
+                while i < elems.len() {
+                    let depth = elems[i + 1..]
+                        .iter()
+                        .take_while(|e| self.is_ellipsis(e))
+                        .count();
+
+                    out.extend(self.repeat(&elems[i], depth, bindings)?);
+                    i += 1 + depth;
+                }
+
+                let tail = tail
+                    .as_deref()
+                    .map(|t| self.transcribe(t, bindings))
+                    .transpose()?;
+
+                list(out, tail, self.span)
+            }
+        };
+
+        Ok(datum)
+    }
+
+    /// Transcribe `template` once for each item its pattern variables
+    /// matched, for each of the `depth` ellipses following it.
+    fn repeat(
+        &self,
+        template: &Datum,
+        depth: usize,
+        bindings: &Bindings,
+    ) -> Result<Vec<Datum>, CompileError> {
+        if depth == 0 {
+            return Ok(vec![self.transcribe(template, bindings)?]);
+        }
+
+        let error = |message: &str| CompileError::Syntax {
+            message: message.to_owned(),
+            span: template.span(),
+        };
+
+        let mut vars = self.template_vars(template);
+        vars.retain(|v| matches!(bindings.get(v), Some(Match::Many(_))));
+        vars.sort();
+        vars.dedup();
+
+        let lengths = vars.iter().map(|v| match &bindings[v] {
+            Match::Many(ms) => ms.len(),
+            Match::One(_) => unreachable!(),
+        });
+        let len = lengths.clone().next().ok_or_else(|| {
+            error("nothing in front of this ellipsis is a pattern variable that matched a sequence")
+        })?;
+
+        if lengths.clone().any(|l| l != len) {
+            return Err(error(
+                "the pattern variables repeated here matched sequences of different lengths",
+            ));
+        }
+
+        let mut out = Vec::new();
+
+        for i in 0..len {
+            let mut each = bindings.clone();
+            for v in &vars {
+                if let Match::Many(ms) = &bindings[v] {
+                    each.insert(v.clone(), ms[i].clone());
+                }
+            }
+
+            out.extend(self.repeat(template, depth - 1, &each)?);
+        }
+
+        Ok(out)
+    }
+
+    fn template_vars(&self, template: &Datum) -> Vec<String> {
+        match template {
+            Datum::Symbol(s, _) => vec![s.clone()],
+            Datum::Atom(..) => Vec::new(),
+            Datum::List(elems, tail, _) => elems
+                .iter()
+                .chain(tail.as_deref())
+                .flat_map(|e| self.template_vars(e))
+                .collect(),
+        }
+    }
+}
+
+/// Build a list, flattening a tail that is itself a list so that
+/// `(f . (a b))` comes out as `(f a b)`
+fn list(mut elems: Vec<Datum>, mut tail: Option<Datum>, span: Span) -> Datum {
+    while let Some(Datum::List(more, rest, _)) = tail {
+        elems.extend(more);
+        tail = rest.map(|r| (*r).clone());
+    }
+
+    Datum::List(elems, tail.map(Rc::new), span)
+}
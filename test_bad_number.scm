@@ -0,0 +1,3 @@
+;; a token that starts like a number but isn't one is an invalid number,
+;; not a variable called `#b102`
+(display (+ #b102 1))
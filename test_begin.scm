@@ -0,0 +1,33 @@
+;; a begin of definitions in a body is spliced into the body
+(define (sum)
+  (begin
+    (define a 1)
+    (define b 2))
+  (+ a b))
+
+(display (sum))
+
+(begin
+  (define c 3)
+  (begin (define d 4)))
+
+(display (+ c d))
+
+;; any other begin is an expression
+(begin
+  (display "one")
+  (display "two"))
+
+;; a begin in a body can mix definitions and expressions
+(begin
+  (define e 5)
+  (display e))
+
+(define (product)
+  (begin
+    (define f 6)
+    (display f)
+    (define g 7))
+  (* f g))
+
+(display (product))
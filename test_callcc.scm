@@ -0,0 +1,52 @@
+;; escaping out of a loop
+(define (find-first pred lst)
+  (call/cc
+   (lambda (return)
+     (let loop ((l lst))
+       (when (cons? l)
+         (when (pred (car l))
+           (return (car l)))
+         (loop (cdr l))))
+     #f)))
+
+(display (find-first (lambda (x) (> x 3)) '(1 2 5 7)))
+(display (find-first (lambda (x) (> x 30)) '(1 2 5 7)))
+
+;; re-entering a continuation runs the rest of the program again
+(define saved #f)
+(define count 0)
+
+(display (+ 100 (call-with-current-continuation
+                 (lambda (k)
+                   (set! saved k)
+                   0))))
+(set! count (+ count 1))
+(when (< count 3)
+  (saved count))
+
+;; a generator that resumes the walk over its list each time it's called
+(define (make-gen lst)
+  (define return #f)
+  (define resume #f)
+  (define (start)
+    (let loop ((l lst))
+      (when (cons? l)
+        (call/cc
+         (lambda (r)
+           (set! resume r)
+           (return (car l))))
+        (loop (cdr l))))
+    (return 'eof))
+  (lambda ()
+    (call/cc
+     (lambda (r)
+       (set! return r)
+       (if resume
+           (resume #f)
+           (start))))))
+
+(define g (make-gen '(a b c)))
+(display (g))
+(display (g))
+(display (g))
+(display (g))
@@ -0,0 +1,85 @@
+;; the body of a define-macro runs at compile time on the unevaluated arguments
+(define-macro (my-unless c . body)
+  `(if ,c #f (begin ,@body)))
+
+(my-unless #f (display "ran") (display "both"))
+(my-unless #t (display "not run"))
+
+(define-macro (count-args . args)
+  (let loop ((a args) (n 0))
+    (if (null? a)
+        n
+        (loop (cdr a) (+ n 1)))))
+
+(display (count-args a b c d))
+
+;; the expansion can define things, with names built at compile time
+(define (nth l i)
+  (if (eq? i 0)
+      (car l)
+      (nth (cdr l) (- i 1))))
+
+(define-macro (define-record name . fields)
+  (define (accessor field)
+    (string->symbol
+     (string-concat (symbol->string name)
+                    (string-concat "-" (symbol->string field)))))
+  (let loop ((fs fields) (i 0) (out '()))
+    (if (null? fs)
+        (cons 'begin out)
+        (loop (cdr fs) (+ i 1)
+              (cons `(define (,(accessor (car fs)) r) (nth r ,i)) out)))))
+
+(define-record point px py)
+(display (point-px '(3 4)))
+(display (point-py '(3 4)))
+
+;; unlike syntax-rules, names in the expansion aren't renamed
+(define-macro (swap! a b)
+  `(let ((tmp ,a)) (set! ,a ,b) (set! ,b tmp)))
+
+(define x 1)
+(define y 2)
+(swap! x y)
+(display (cons x y))
+
+(define-macro (seconds) (* 60 60))
+(display (seconds))
+
+;; transformers can recurse as deeply as they need to, and tail calls don't
+;; nest at all
+(define-macro (sum-to n)
+  (define (go i acc)
+    (if (eq? i 0)
+        acc
+        (go (- i 1) (+ acc i))))
+  (go n 0))
+
+(display (sum-to 100000))
+
+(define-macro (length-of-range n)
+  (define (range n)
+    (if (eq? n 0)
+        '()
+        (cons n (range (- n 1)))))
+  (define (len l)
+    (if (null? l)
+        0
+        (+ 1 (len (cdr l)))))
+  (len (range n)))
+
+(display (length-of-range 500))
+
+;; numbers turned into text at compile time read the same as at runtime
+(define-macro (float-text x) (tostring x))
+
+(display (float-text 0.00001))
+(display (tostring 0.00001))
+(display (float-text 1e16))
+(display (tostring 1e16))
+(display (float-text 1e17))
+(display (tostring 1e17))
+(display (float-text 0.000001))
+(display (tostring 0.000001))
+(display (float-text -2.5))
+(display (tostring -2.5))
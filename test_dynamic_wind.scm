@@ -0,0 +1,61 @@
+(define (say x) (display x))
+
+(say (dynamic-wind
+      (lambda () (say "before"))
+      (lambda () 'body)
+      (lambda () (say "after"))))
+
+;; escaping out of the thunk runs the after thunk
+(say (call/cc
+      (lambda (k)
+        (dynamic-wind
+         (lambda () (say "in"))
+         (lambda ()
+           (k 'escaped)
+           (say "not reached"))
+         (lambda () (say "out"))))))
+
+;; escaping across two extents unwinds the inner one first
+(call/cc
+ (lambda (k)
+   (dynamic-wind
+    (lambda () (say "a-in"))
+    (lambda ()
+      (dynamic-wind
+       (lambda () (say "b-in"))
+       (lambda () (k 1))
+       (lambda () (say "b-out"))))
+    (lambda () (say "a-out")))))
+
+;; re-entering the thunk runs the before thunk again
+(define reenter #f)
+(define n 0)
+
+(dynamic-wind
+ (lambda () (say "enter"))
+ (lambda ()
+   (call/cc (lambda (k) (set! reenter k)))
+   (set! n (+ n 1))
+   (say n))
+ (lambda () (say "leave")))
+
+(when (< n 3)
+  (reenter #f))
+
+;; jumping from one extent into a sibling leaves the first and enters the other
+(define kx #f)
+(define jumped #f)
+
+(dynamic-wind
+ (lambda () (say "x-in"))
+ (lambda () (call/cc (lambda (k) (set! kx k))))
+ (lambda () (say "x-out")))
+
+(if jumped
+    (say "end")
+    (begin
+      (set! jumped #t)
+      (dynamic-wind
+       (lambda () (say "y-in"))
+       (lambda () (kx #f))
+       (lambda () (say "y-out")))))
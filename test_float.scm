@@ -0,0 +1,22 @@
+;; floats print with the fewest digits that read back as the same value
+(display 1.5)
+(display 0.1)
+(display (+ 0.1 0.2))
+(display (/ 1.0 3.0))
+
+;; in fixed notation unless they are very large or very small
+(display 10.0)
+(display 100.0)
+(display 500000.0)
+(display -2.5e3)
+(display 123.456)
+(display 0.00001)
+(display 1e16)
+(display 1e17)
+(display 1.5e-7)
+(display 0.0)
+(display -0.0)
+
+;; arithmetic mixing ints and floats gives a float
+(display (+ 1 2.0))
+(display (* 4 2.5))
@@ -0,0 +1,44 @@
+(define (list . xs) xs)
+
+(display (guard (e (#t e))
+  (raise 'boom)))
+
+(display (guard (e ((symbol? e) 'symbol)
+                   ((error-object? e) (error-object-message e)))
+  (error "bad thing:" 1 2)))
+
+(display (guard (e ((error-object? e) (error-object-irritants e)))
+  (error "bad thing:" 1 2)))
+
+;; type errors in builtins are raised as conditions too
+(display (guard (e ((error-object? e) (error-object-message e)))
+  (+ 1 #t)))
+
+(display (guard (e ((eq? e 2) => (lambda (x) (list 'arrow x))))
+  (raise 2)))
+
+;; with no matching clause the condition is raised again to the outer guard
+(display (guard (e (#t (list 'outer e)))
+  (guard (e ((cons? e) 'inner))
+    (raise 'x))))
+
+;; the re-raise happens where the condition was raised, so it goes back into
+;; the dynamic-wind and out of it again
+(define r '())
+(display (guard (e (#t (list 'outer e r)))
+  (guard (e ((eq? e 'nope) 'inner))
+    (dynamic-wind
+     (lambda () (set! r (cons 'in r)))
+     (lambda () (raise 'x))
+     (lambda () (set! r (cons 'out r)))))))
+
+;; a handler for raise-continuable returns to the raise
+(display (with-exception-handler
+          (lambda (c) 42)
+          (lambda () (+ 1 (raise-continuable 'c)))))
+
+;; but returning from a handler for raise is an error
+(display (guard (e (#t (list 'reraised e)))
+  (with-exception-handler
+   (lambda (c) 42)
+   (lambda () (+ 1 (raise 'c))))))
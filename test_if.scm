@@ -1,17 +1,29 @@
-(if 1
+(if #t
   (display "good")
   (display "bad"))
 
-(if (- 1 1)
+(if #f
  (display "bad")
  (display "good"))
 
-(if ((lambda () 1))
+(if ((lambda () #t))
  (display "good")
  (display "bad"))
 
-(if 0
+(if #f
  (display "bad"))
 
-(if 1
+(if #t
  (display "good"))
+
+(if 0
+ (display "good")
+ (display "bad"))
+
+(if (< 1 2)
+ (display "good")
+ (display "bad"))
+
+(if (null? '())
+ (display "good")
+ (display "bad"))
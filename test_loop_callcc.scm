@@ -0,0 +1,20 @@
+(define k #f)
+(define reentered #f)
+
+(define (save c)
+  (if (eq? k #f)
+      (set! k c)))
+
+;; each iteration has its own i, going back into the first one with k has to
+;; count up from 0 again
+(let loop ((i 0))
+  (if (< i 3)
+      (begin
+        (call/cc save)
+        (display i)
+        (loop (+ i 1)))))
+
+(if (eq? reentered #f)
+    (begin
+      (set! reentered #t)
+      (k #f)))
@@ -0,0 +1,85 @@
+;; nested ellipses: each binding group keeps its own sequence of expressions
+(define-syntax my-let*
+  (syntax-rules ()
+    ((_ () body ...) (let () body ...))
+    ((_ ((n v) rest ...) body ...) (let ((n v)) (my-let* (rest ...) body ...)))))
+
+(define-syntax for-each-group
+  (syntax-rules ()
+    ((_ (x ...) ...) (begin (begin (display x) ...) ...))))
+
+(display (my-let* ((a 1) (b (+ a 1))) (* a b)))
+(for-each-group (1 2) (3) (4 5 6))
+
+;; literals only match themselves
+(define-syntax arrow
+  (syntax-rules (=>)
+    ((_ a => b) (cons a b))
+    ((_ a b c) 'no-arrow)))
+
+(display (arrow 1 => 2))
+(display (arrow 1 2 3))
+
+;; let-syntax is only visible in its body, letrec-syntax also in its macros
+(define (twice x) (* x 2))
+(let-syntax ((twice (syntax-rules () ((_ x) (+ x (+ x x))))))
+  (display (twice 1)))
+(display (twice 1))
+
+(letrec-syntax ((count-args
+                 (syntax-rules ()
+                   ((_) 0)
+                   ((_ x rest ...) (+ 1 (count-args rest ...))))))
+  (display (count-args a b c)))
+
+;; recursive macros can expand many times over
+(define-syntax sum
+  (syntax-rules ()
+    ((_) 0)
+    ((_ x rest ...) (+ x (sum rest ...)))))
+
+(display (sum
+    1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25
+    26 27 28 29 30 31 32 33 34 35 36 37 38 39 40 41 42 43 44 45 46 47 48 49 50
+    51 52 53 54 55 56 57 58 59 60 61 62 63 64 65 66 67 68 69 70 71 72 73 74 75
+    76 77 78 79 80 81 82 83 84 85 86 87 88 89 90 91 92 93 94 95 96 97 98 99 100
+    101 102 103 104 105 106 107 108 109 110 111 112 113 114 115 116 117 118 119 120 121 122 123 124 125
+    126 127 128 129 130 131 132 133 134 135 136 137 138 139 140 141 142 143 144 145 146 147 148 149 150))
+
+;; a macro can define another macro, which can be used straight away
+(define-syntax make-inc
+  (syntax-rules ()
+    ((_ n) (define-syntax n
+             (syntax-rules ()
+               ((_ x) (+ x 1)))))))
+
+(make-inc inc)
+(display (inc 7))
+
+;; hygiene: a macro's temporaries can't capture the user's variables...
+(define-syntax my-or
+  (syntax-rules ()
+    ((_) #f)
+    ((_ e) e)
+    ((_ e r ...) (let ((t e)) (if t t (my-or r ...))))))
+
+(let ((t 5))
+  (display (my-or #f t)))
+
+(define-syntax swap!
+  (syntax-rules ()
+    ((_ a b) (let ((tmp a)) (set! a b) (set! b tmp)))))
+
+(let ((tmp 1) (other 2))
+  (swap! tmp other)
+  (display (cons tmp other)))
+
+;; ...and the user's bindings can't capture the macro's free identifiers
+(define (helper x) (* x 10))
+(define-syntax use-helper
+  (syntax-rules ()
+    ((_ v) (helper v))))
+
+(let ((helper (lambda (x) 'shadowed)))
+  (display (use-helper 2))
+  (display (helper 2)))
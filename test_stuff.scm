@@ -1,6 +1,6 @@
 (define go (lambda (i)
             (let ((ii (- i 1)))
-             (if ii
+             (if (> ii 0)
                 (let ()
                   (display ii)
                   (go ii))))))